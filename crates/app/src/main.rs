@@ -4,13 +4,36 @@
 //!   # With local model (no server needed):
 //!   MODEL_PATH=/path/to/model.gguf cargo run -p app
 //!
+//!   # Local model on a CPU-only server with a larger context and a fixed chat template:
+//!   MODEL_PATH=... LLM_CPU_ONLY=true LLM_THREADS=16 LLM_N_CTX=32768 \
+//!     LLM_CHAT_TEMPLATE=/path/to/template.jinja cargo run -p app
+//!
 //!   # With OpenAI:
 //!   OPENAI_API_KEY=sk-... cargo run -p app
 //!
+//!   # With Anthropic:
+//!   LLM_PROVIDER=anthropic ANTHROPIC_API_KEY=sk-ant-... LLM_MODEL=claude-sonnet-4-5 cargo run -p app
+//!
+//!   # With an OpenAI-compatible server (llama-server, vLLM, LM Studio, ...):
+//!   LLM_BASE_URL=http://127.0.0.1:8080/v1 LLM_MODEL=qwen3 cargo run -p app
+//!
+//!   # With a sub-agent (`delegate` tool) for long research, optionally on a cheaper model:
+//!   SUBAGENT=true SUBAGENT_MODEL=gpt-5-nano OPENAI_API_KEY=sk-... cargo run -p app
+//!
 //!   # One-shot mode (for integration tests):
 //!   echo "Read the file configs/default.yaml" | MODEL_PATH=... cargo run -p app
+//!
+//! Interactive conversations are saved to `SESSIONS_DIR` (default `~/.voice-agent/sessions`;
+//! one-shot mode saves only when it is set). `/sessions` lists them, `/resume [id]`
+//! continues one (the latest without an id), `/rename <title>` and `/delete <id>` manage them.
+//! `/export <markdown|json|jsonl> [path]` writes the conversation to `path` (or stdout).
 
-use agent_core::{ChatMessage, create_provider};
+use agent_core::{
+    CancelToken, ChatMessage, LlmProvider, LocalRuntimeConfig, MessageMetadata, SamplingConfig,
+    SessionStore, create_provider,
+};
+use agent_core::export::{ExportEntry, ExportFormat, Transcript};
+use agent_core::react::{ReactEvent, ReactObserver};
 use agent_core::tool::ToolAccess;
 
 use std::io::{self, BufRead};
@@ -25,12 +48,16 @@ fn main() {
         .init();
 
     // Configuration from environment
+    let provider = std::env::var("LLM_PROVIDER").ok();
     let model_path = std::env::var("MODEL_PATH").ok();
     let base_url = std::env::var("LLM_BASE_URL")
         .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
     let model = std::env::var("LLM_MODEL")
         .unwrap_or_else(|_| "gpt-5-mini".to_string());
-    let api_key = std::env::var("OPENAI_API_KEY").ok();
+    let api_key = match provider.as_deref() {
+        Some("anthropic") => std::env::var("ANTHROPIC_API_KEY").ok(),
+        _ => std::env::var("OPENAI_API_KEY").ok(),
+    };
     let working_dir = std::env::var("WORKING_DIR")
         .unwrap_or_else(|_| std::env::current_dir().unwrap().to_string_lossy().to_string());
     let max_tokens: u32 = std::env::var("MAX_TOKENS")
@@ -47,25 +74,114 @@ fn main() {
         .ok()
         .and_then(|s| s.parse().ok());
     let reasoning_effort = std::env::var("REASONING_EFFORT").ok();
-    let client = create_provider(
+
+    // Local sampling chain (unset values use llama.cpp defaults)
+    fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+        std::env::var(name).ok().and_then(|s| s.parse().ok())
+    }
+    let sampling = SamplingConfig {
+        top_k: env_parse("LLM_TOP_K"),
+        top_p: env_parse("LLM_TOP_P"),
+        min_p: env_parse("LLM_MIN_P"),
+        repeat_penalty: env_parse("LLM_REPEAT_PENALTY"),
+        presence_penalty: env_parse("LLM_PRESENCE_PENALTY"),
+        dry_multiplier: env_parse("LLM_DRY_MULTIPLIER"),
+        seed: env_parse("LLM_SEED"),
+        ..Default::default()
+    };
+
+    // Local runtime (context size, offload, threads, KV cache type, template override)
+    let runtime = LocalRuntimeConfig {
+        n_ctx: env_parse("LLM_N_CTX"),
+        gpu_layers: env_parse("LLM_GPU_LAYERS"),
+        cpu_only: env_parse("LLM_CPU_ONLY").unwrap_or(false),
+        n_threads: env_parse("LLM_THREADS"),
+        n_batch: env_parse("LLM_BATCH"),
+        flash_attention: env_parse("LLM_FLASH_ATTN"),
+        kv_cache_type: std::env::var("LLM_KV_CACHE_TYPE").ok(),
+        chat_template_path: std::env::var("LLM_CHAT_TEMPLATE").ok(),
+        mmproj_path: std::env::var("LLM_MMPROJ").ok(),
+        enable_thinking: env_parse("LLM_THINKING").unwrap_or(false),
+        thinking_budget: env_parse("LLM_THINKING_BUDGET"),
+        ..Default::default()
+    };
+
+    let client: std::sync::Arc<dyn LlmProvider> = create_provider(
+        provider.clone(),
         model_path.clone(),
         base_url.clone(),
         model.clone(),
         api_key.clone(),
         temperature,
         max_tokens,
-        reasoning_effort,
+        reasoning_effort.clone(),
+        sampling.clone(),
+        runtime,
     )
-    .expect("Failed to create LLM provider");
+    .expect("Failed to create LLM provider")
+    .into();
 
     // Create tool registry
     let skill_registry = std::sync::Arc::new(agent_core::skill::SkillRegistry::new());
     let situation = std::sync::Arc::new(agent_core::situation::SituationMessages::default());
     let mut tool_registry = agent_core::tool::create_default_registry(
         std::path::PathBuf::from(&working_dir),
-        skill_registry,
-        situation,
+        skill_registry.clone(),
+        situation.clone(),
     );
+    if let Some(limit) = env_parse::<usize>("MAX_PARALLEL_TOOLS") {
+        tool_registry.set_max_parallel(limit);
+    }
+
+    // Sub-agent for long research tasks (SUBAGENT=true; SUBAGENT_MODEL picks another API model)
+    if env_parse("SUBAGENT").unwrap_or(false) {
+        let sub_client = match std::env::var("SUBAGENT_MODEL") {
+            Ok(sub_model) if model_path.is_none() => create_provider(
+                provider.clone(),
+                None,
+                base_url.clone(),
+                sub_model,
+                api_key.clone(),
+                temperature,
+                max_tokens,
+                reasoning_effort,
+                sampling,
+                LocalRuntimeConfig::default(),
+            )
+            .expect("Failed to create sub-agent LLM provider")
+            .into(),
+            Ok(sub_model) => {
+                tracing::warn!(
+                    "SUBAGENT_MODEL '{}' ignored: the local provider shares the parent's model",
+                    sub_model
+                );
+                client.clone()
+            }
+            Err(_) => client.clone(),
+        };
+        let sub_registry = agent_core::tool::create_default_registry(
+            std::path::PathBuf::from(&working_dir),
+            skill_registry,
+            situation,
+        );
+        let allowed = agent_core::delegate::DEFAULT_SUBAGENT_TOOLS
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        tool_registry.register(Box::new(agent_core::delegate::DelegateTool::new(
+            sub_client,
+            sub_registry,
+            allowed,
+            env_parse("SUBAGENT_MAX_ITERATIONS").unwrap_or(agent_core::delegate::DEFAULT_SUBAGENT_ITERATIONS),
+        )));
+    }
+
+    // Long-term memory tool (remember/recall/forget), saved to FACTS_PATH when set
+    let facts = match std::env::var("FACTS_PATH") {
+        Ok(path) => agent_core::facts::FactStore::open(path).expect("Failed to open fact store"),
+        Err(_) => agent_core::facts::FactStore::in_memory(),
+    };
+    tool_registry.register(Box::new(agent_core::facts::MemoryTool::new(std::sync::Arc::new(facts))));
 
     // Connect MCP servers from MCP_SERVERS env (comma-separated "command arg1 arg2,...")
     if let Ok(mcp_spec) = std::env::var("MCP_SERVERS") {
@@ -86,22 +202,35 @@ fn main() {
         }
     }
 
-    let provider_name = if model_path.is_some() {
-        "Local (FFI)"
-    } else if api_key.is_some() {
-        "OpenAI"
-    } else {
-        "Unknown"
+    let provider_name = match provider.as_deref() {
+        Some(name) => name.to_string(),
+        None if model_path.is_some() => "Local (FFI)".to_string(),
+        None if base_url.trim_end_matches('/') == "https://api.openai.com/v1" => "OpenAI".to_string(),
+        None if base_url.trim_end_matches('/') == "https://api.anthropic.com/v1" => "Anthropic".to_string(),
+        None => format!("OpenAI-compatible at {}", base_url),
     };
 
     // Check if stdin is a pipe (one-shot mode) or terminal (interactive)
     let is_interactive = atty::is(atty::Stream::Stdin);
 
+    // Session persistence: always in interactive mode, in one-shot mode only when asked for
+    let sessions = match std::env::var("SESSIONS_DIR") {
+        Ok(dir) if !dir.is_empty() => Some(SessionStore::new(dir)),
+        _ if is_interactive => std::env::var("HOME")
+            .ok()
+            .map(|home| SessionStore::new(std::path::Path::new(&home).join(".voice-agent/sessions"))),
+        _ => None,
+    };
+    let mut session_id: Option<String> = None;
+
     if is_interactive {
         eprintln!("=== Text Agent (ReAct Tool Calling) ===");
         eprintln!("Provider: {} ({})", provider_name, model);
         eprintln!("Working dir: {}", working_dir);
         eprintln!("Tools: {:?}", tool_registry.get_definitions().iter().map(|t| &t.name).collect::<Vec<_>>());
+        if let Some(ref store) = sessions {
+            eprintln!("Sessions: {} (/sessions, /resume [id])", store.dir().display());
+        }
         eprintln!("Type /quit to exit\n");
     }
 
@@ -134,12 +263,50 @@ fn main() {
 
         if input == "/reset" {
             messages.truncate(1); // Keep system prompt
+            session_id = None; // Next turn starts a new session
             eprintln!("Conversation reset.");
             continue;
         }
 
+        if let Some(command) = input.strip_prefix('/') {
+            let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+            let arg = arg.trim();
+            if matches!(name, "sessions" | "resume" | "rename" | "delete") {
+                match sessions {
+                    Some(ref store) => {
+                        if let Err(e) =
+                            session_command(store, name, arg, &mut session_id, &mut messages)
+                        {
+                            eprintln!("Error: {}", e);
+                        }
+                    }
+                    None => eprintln!("Sessions are disabled (set SESSIONS_DIR)."),
+                }
+                continue;
+            }
+            if name == "export" {
+                let transcript = match (&sessions, &session_id) {
+                    (Some(store), Some(id)) => store.transcript(id),
+                    _ => Ok(Transcript {
+                        entries: messages[1..].iter().cloned().map(ExportEntry::new).collect(),
+                        ..Default::default()
+                    }),
+                };
+                let result = transcript.and_then(|mut transcript| {
+                    transcript.system_prompt = Some(messages[0].content.clone());
+                    transcript.tools = tool_registry.get_definitions();
+                    export_command(&transcript, arg)
+                });
+                if let Err(e) = result {
+                    eprintln!("Error: {}", e);
+                }
+                continue;
+            }
+        }
+
         // Add user message
         messages.push(ChatMessage::user(input.clone()));
+        let user_metadata = MessageMetadata::now();
 
         if is_interactive {
             eprint!("Thinking...");
@@ -147,12 +314,15 @@ fn main() {
 
         // Run ReAct loop
         let mut react_messages = messages.clone();
+        let progress = ReplProgress;
 
         let result = agent_core::react::run(
             client.as_ref(),
             &mut react_messages,
             &tool_registry,
             Some(max_react_iterations),
+            &CancelToken::new(),
+            if is_interactive { Some(&progress) } else { None },
         );
 
         if is_interactive {
@@ -173,7 +343,29 @@ fn main() {
                 }
 
                 // Add assistant response to conversation history
-                messages.push(ChatMessage::assistant(response));
+                let reply = ChatMessage::assistant(response);
+                let reply_metadata = MessageMetadata {
+                    provider: Some(provider_name.clone()),
+                    model: Some(model.clone()),
+                    usage: Some(usage.clone()),
+                    ..MessageMetadata::now()
+                };
+                if let Some(ref store) = sessions {
+                    let saved = match session_id {
+                        Some(ref id) => Ok(id.clone()),
+                        None => store.create(),
+                    }
+                    .and_then(|id| {
+                        store.append_message(&id, &ChatMessage::user(input.clone()), &user_metadata)?;
+                        store.append_reply(&id, &reply, &reply_metadata, reasoning.as_deref())?;
+                        Ok(id)
+                    });
+                    match saved {
+                        Ok(id) => session_id = Some(id),
+                        Err(e) => eprintln!("Failed to save session: {}", e),
+                    }
+                }
+                messages.push(reply);
             }
             Err(e) => {
                 eprintln!("Error: {}", e);
@@ -186,6 +378,127 @@ fn main() {
     }
 
     if is_interactive {
+        if let Some(ref id) = session_id {
+            eprintln!("Session saved as {} (/resume {} to continue)", id, id);
+        }
         eprintln!("Goodbye!");
     }
 }
+
+/// Shows ReAct progress on stderr, one gray line per tool call
+struct ReplProgress;
+
+impl ReactObserver for ReplProgress {
+    fn on_event(&self, event: ReactEvent) {
+        match event {
+            ReactEvent::LlmCallStarted { iteration, max_iterations } if iteration > max_iterations => {
+                eprint!("Wrapping up...");
+            }
+            ReactEvent::LlmCallStarted { iteration, max_iterations } if iteration > 1 => {
+                eprint!("Thinking... ({}/{})", iteration, max_iterations);
+            }
+            ReactEvent::LlmCallFinished { .. } => {
+                eprint!("\r{:24}\r", "");
+            }
+            ReactEvent::ToolCallStarted { name, arguments, .. } => {
+                let arguments: String = arguments.chars().take(120).collect();
+                eprintln!("\x1b[90m🔧 {} {}\x1b[0m", name, arguments);
+            }
+            ReactEvent::ToolCallFinished { name, duration_ms, result_chars, images, failed, .. } => {
+                let status = if failed { "failed" } else { "done" };
+                let images = if images > 0 { format!(", {} image(s)", images) } else { String::new() };
+                eprintln!(
+                    "\x1b[90m   {} {} in {} ms ({} chars{})\x1b[0m",
+                    name, status, duration_ms, result_chars, images
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Handle `/export <format> [path]`: write the rendered transcript to `path`, or stdout
+fn export_command(transcript: &Transcript, arg: &str) -> Result<(), agent_core::AgentError> {
+    let (format, path) = arg.split_once(' ').unwrap_or((arg, ""));
+    if format.is_empty() {
+        eprintln!("Usage: /export <markdown|json|jsonl> [path]");
+        return Ok(());
+    }
+    let output = transcript.render(ExportFormat::parse(format)?)?;
+    let path = path.trim();
+    if path.is_empty() {
+        print!("{}", output);
+    } else {
+        std::fs::write(path, output)
+            .map_err(|e| agent_core::AgentError::InternalError(format!("{}: {}", path, e)))?;
+        eprintln!("Exported to {}.", path);
+    }
+    Ok(())
+}
+
+/// Handle `/sessions`, `/resume [id]`, `/rename <title>` and `/delete <id>`.
+/// `messages` keeps the system prompt at index 0.
+fn session_command(
+    store: &SessionStore,
+    name: &str,
+    arg: &str,
+    session_id: &mut Option<String>,
+    messages: &mut Vec<ChatMessage>,
+) -> Result<(), agent_core::AgentError> {
+    match name {
+        "sessions" => {
+            let list = store.list()?;
+            if list.is_empty() {
+                eprintln!("No saved sessions.");
+            }
+            for info in list {
+                let marker = if session_id.as_deref() == Some(info.id.as_str()) { "*" } else { " " };
+                eprintln!(
+                    "{} {}  {:>3} msgs  {:>7} tokens  {}",
+                    marker, info.id, info.message_count, info.total_tokens, info.title
+                );
+            }
+        }
+        "resume" => {
+            let id = if arg.is_empty() {
+                match store.list()?.into_iter().next() {
+                    Some(info) => info.id,
+                    None => {
+                        eprintln!("No saved sessions.");
+                        return Ok(());
+                    }
+                }
+            } else {
+                arg.to_string()
+            };
+            let session = store.load(&id)?;
+            messages.truncate(1);
+            messages.extend(session.memory.get_messages());
+            eprintln!("Resumed \"{}\" ({} messages).", session.info.title, session.entries.len());
+            *session_id = Some(id);
+        }
+        "rename" => match session_id {
+            Some(id) if !arg.is_empty() => {
+                store.rename(id, arg)?;
+                eprintln!("Renamed session {}.", id);
+            }
+            Some(_) => eprintln!("Usage: /rename <title>"),
+            None => eprintln!("No session yet — say something first."),
+        },
+        "delete" => {
+            if arg.is_empty() {
+                eprintln!("Usage: /delete <id>");
+                return Ok(());
+            }
+            store.delete(arg)?;
+            if session_id.as_deref() == Some(arg) {
+                // Deleting the current session also resets the conversation
+                messages.truncate(1);
+                *session_id = None;
+            }
+            eprintln!("Deleted session {}.", arg);
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,330 @@
+//! Human-in-the-loop approval of tool calls.
+//!
+//! A policy maps each tool call to allow, deny or ask. Rules match the tool name and,
+//! optionally, one argument (both as glob patterns); the first matching rule wins and
+//! calls no rule matches get the default policy. "ask" calls wait for the approver (the
+//! user, through the app) before they run. Denied calls are not errors: the model gets a
+//! tool result saying so and can carry on without them.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use glob::Pattern;
+
+use crate::llm::ToolDefinition;
+use crate::tool::{ToolAccess, ToolResult};
+use crate::AgentError;
+
+/// One approval rule, as configured
+#[derive(Debug, Clone)]
+pub struct ToolApprovalRule {
+    /// Tool name, or a glob such as "*" or "mcp_*"
+    pub tool: String,
+    /// Argument the pattern applies to; without it the pattern matches the arguments JSON
+    pub argument: Option<String>,
+    /// Glob the argument value must match (strings as-is, other values as JSON)
+    pub pattern: Option<String>,
+    /// "allow", "deny" or "ask"
+    pub policy: String,
+}
+
+/// Approval policy for tool calls
+#[derive(Debug, Clone, Default)]
+pub struct ToolApprovalConfig {
+    /// Policy of calls no rule matches: "allow" (default), "deny" or "ask"
+    pub default_policy: Option<String>,
+    /// Rules in order of precedence
+    pub rules: Vec<ToolApprovalRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl ApprovalPolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            "ask" => Ok(Self::Ask),
+            other => Err(format!(
+                "Unknown tool approval policy '{}' (expected allow, deny or ask)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    tool: Pattern,
+    argument: Option<String>,
+    pattern: Option<Pattern>,
+    policy: ApprovalPolicy,
+}
+
+impl Rule {
+    fn matches(&self, name: &str, args: &serde_json::Value) -> bool {
+        if !self.tool.matches(name) {
+            return false;
+        }
+        let value = match self.argument {
+            Some(ref argument) => match args.get(argument) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => return false,
+            },
+            None => args.to_string(),
+        };
+        self.pattern.as_ref().is_none_or(|p| p.matches(&value))
+    }
+}
+
+/// Parsed `ToolApprovalConfig`
+#[derive(Debug, Clone)]
+pub struct ApprovalRules {
+    rules: Vec<Rule>,
+    default: ApprovalPolicy,
+}
+
+impl Default for ApprovalRules {
+    /// Allow every call
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: ApprovalPolicy::Allow,
+        }
+    }
+}
+
+impl ApprovalRules {
+    pub fn new(config: &ToolApprovalConfig) -> Result<Self, String> {
+        let glob = |s: &str| {
+            Pattern::new(s).map_err(|e| format!("Invalid tool approval pattern '{}': {}", s, e))
+        };
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    tool: glob(&rule.tool)?,
+                    argument: rule.argument.clone(),
+                    pattern: rule.pattern.as_deref().map(glob).transpose()?,
+                    policy: ApprovalPolicy::parse(&rule.policy)?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let default = match config.default_policy {
+            Some(ref policy) => ApprovalPolicy::parse(policy)?,
+            None => ApprovalPolicy::Allow,
+        };
+        Ok(Self { rules, default })
+    }
+
+    /// Policy of a call to `name` with `args`
+    pub fn policy(&self, name: &str, args: &serde_json::Value) -> ApprovalPolicy {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(name, args))
+            .map_or(self.default, |rule| rule.policy)
+    }
+}
+
+/// Decides "ask" calls, typically by asking the user. Blocks until there is an answer.
+pub trait ToolApprover: Sync {
+    fn approve(&self, name: &str, args: &serde_json::Value) -> bool;
+}
+
+/// Tool access that applies `ApprovalRules` before each call
+pub struct ApprovedTools<'a> {
+    inner: &'a dyn ToolAccess,
+    rules: &'a ApprovalRules,
+    approver: Option<&'a dyn ToolApprover>,
+    /// Held while asking, so concurrent calls ask one at a time
+    asking: Mutex<Duration>,
+}
+
+impl<'a> ApprovedTools<'a> {
+    pub fn new(
+        inner: &'a dyn ToolAccess,
+        rules: &'a ApprovalRules,
+        approver: Option<&'a dyn ToolApprover>,
+    ) -> Self {
+        Self {
+            inner,
+            rules,
+            approver,
+            asking: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Time spent waiting for the approver
+    pub fn waited(&self) -> Duration {
+        *self.asking.lock().unwrap()
+    }
+
+    fn ask(&self, name: &str, args: &serde_json::Value) -> bool {
+        let Some(approver) = self.approver else {
+            tracing::warn!("Tool '{}' needs approval but no approver is set", name);
+            return false;
+        };
+        let mut waited = self.asking.lock().unwrap();
+        let started = Instant::now();
+        let approved = approver.approve(name, args);
+        *waited += started.elapsed();
+        approved
+    }
+}
+
+impl ToolAccess for ApprovedTools<'_> {
+    fn get_definitions(&self) -> Vec<ToolDefinition> {
+        self.inner.get_definitions()
+    }
+
+    fn call(&self, name: &str, args: serde_json::Value) -> Result<ToolResult, AgentError> {
+        let denial = match self.rules.policy(name, &args) {
+            ApprovalPolicy::Allow => None,
+            ApprovalPolicy::Deny => Some("is not allowed"),
+            ApprovalPolicy::Ask if self.ask(name, &args) => None,
+            ApprovalPolicy::Ask => Some("was declined by the user"),
+        };
+        match denial {
+            Some(reason) => {
+                tracing::info!("Tool call '{}' {}", name, reason);
+                Ok(ToolResult::text(format!(
+                    "The call to '{}' {} and did not run. Do not retry it; continue without it or ask the user.",
+                    name, reason
+                )))
+            }
+            None => self.inner.call(name, args),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn is_parallel_safe(&self, name: &str) -> bool {
+        self.inner.is_parallel_safe(name)
+    }
+
+    fn max_parallel_calls(&self) -> usize {
+        self.inner.max_parallel_calls()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool::{TaskTool, ToolRegistry};
+
+    fn rule(
+        tool: &str,
+        argument: Option<&str>,
+        pattern: Option<&str>,
+        policy: &str,
+    ) -> ToolApprovalRule {
+        ToolApprovalRule {
+            tool: tool.to_string(),
+            argument: argument.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+            policy: policy.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = ApprovalRules::new(&ToolApprovalConfig {
+            default_policy: Some("ask".to_string()),
+            rules: vec![
+                rule("read_file", Some("path"), Some("/etc/*"), "deny"),
+                rule("read_file", None, None, "allow"),
+                rule("mcp_*", None, Some("*\"force\":true*"), "deny"),
+            ],
+        })
+        .unwrap();
+        let args = |v| serde_json::json!({ "path": v });
+
+        assert_eq!(
+            rules.policy("read_file", &args("/etc/passwd")),
+            ApprovalPolicy::Deny
+        );
+        assert_eq!(
+            rules.policy("read_file", &args("src/lib.rs")),
+            ApprovalPolicy::Allow
+        );
+        assert_eq!(
+            rules.policy("mcp_push", &serde_json::json!({ "force": true })),
+            ApprovalPolicy::Deny
+        );
+        assert_eq!(
+            rules.policy("mcp_push", &serde_json::json!({})),
+            ApprovalPolicy::Ask
+        );
+        assert_eq!(
+            ApprovalRules::default().policy("bash", &args("x")),
+            ApprovalPolicy::Allow
+        );
+
+        let bad = ToolApprovalConfig {
+            default_policy: Some("maybe".to_string()),
+            rules: Vec::new(),
+        };
+        assert!(ApprovalRules::new(&bad).unwrap_err().contains("maybe"));
+    }
+
+    struct Answer(bool, Mutex<Vec<String>>);
+
+    impl ToolApprover for Answer {
+        fn approve(&self, name: &str, _args: &serde_json::Value) -> bool {
+            self.1.lock().unwrap().push(name.to_string());
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_denied_calls_become_results() {
+        let mut registry = ToolRegistry::new();
+        registry.register(Box::new(TaskTool::new()));
+        let rules = ApprovalRules::new(&ToolApprovalConfig {
+            default_policy: Some("ask".to_string()),
+            rules: vec![rule("tasks", Some("action"), Some("list"), "allow")],
+        })
+        .unwrap();
+        let list = serde_json::json!({ "action": "list" });
+        let create = serde_json::json!({ "action": "create", "subject": "x" });
+
+        // Allowed calls run without asking
+        let yes = Answer(true, Mutex::new(Vec::new()));
+        let tools = ApprovedTools::new(&registry, &rules, Some(&yes));
+        assert!(!tools
+            .call("tasks", list)
+            .unwrap()
+            .text
+            .contains("did not run"));
+        assert!(yes.1.lock().unwrap().is_empty());
+
+        // Approved calls run
+        assert!(!tools
+            .call("tasks", create.clone())
+            .unwrap()
+            .text
+            .contains("did not run"));
+        assert_eq!(*yes.1.lock().unwrap(), ["tasks"]);
+
+        // Declined calls, and calls with no one to ask, are reported to the model
+        let no = Answer(false, Mutex::new(Vec::new()));
+        let tools = ApprovedTools::new(&registry, &rules, Some(&no));
+        let result = tools.call("tasks", create.clone()).unwrap();
+        assert!(result.text.contains("declined by the user"));
+        let tools = ApprovedTools::new(&registry, &rules, None);
+        assert!(tools
+            .call("tasks", create)
+            .unwrap()
+            .text
+            .contains("did not run"));
+    }
+}
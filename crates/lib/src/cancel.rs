@@ -0,0 +1,117 @@
+//! Cancellation token for aborting in-flight steps (e.g. when the user barges in).
+//!
+//! The token is checked between ReAct iterations, per generated token in the local
+//! provider, while waiting on HTTP responses and while tools block on Swift.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+
+use crate::AgentError;
+
+/// How often blocking waits re-check the token.
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shared cancellation flag. Clones observe the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of all work observing this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Clear a previous cancellation so the token can be reused for the next step
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Return `AgentError::Cancelled` if cancellation has been requested
+    pub fn check(&self) -> Result<(), AgentError> {
+        if self.is_cancelled() {
+            Err(AgentError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Like `Receiver::recv_timeout`, but returns early with `AgentError::Cancelled`
+    /// once the token is cancelled.
+    pub fn recv_timeout<T>(
+        &self,
+        rx: &Receiver<T>,
+        timeout: Duration,
+    ) -> Result<Result<T, RecvTimeoutError>, AgentError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.check()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining.min(CANCEL_POLL_INTERVAL)) {
+                Err(RecvTimeoutError::Timeout) if !remaining.is_zero() => continue,
+                result => return Ok(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_and_reset() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert!(matches!(clone.check(), Err(AgentError::Cancelled)));
+
+        clone.reset();
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_recv_timeout_returns_value_or_timeout() {
+        let token = CancelToken::new();
+        let (tx, rx) = crossbeam::channel::unbounded();
+        tx.send(7).unwrap();
+        assert_eq!(token.recv_timeout(&rx, Duration::from_secs(1)).unwrap().unwrap(), 7);
+
+        let result = token.recv_timeout(&rx, Duration::from_millis(120)).unwrap();
+        assert!(matches!(result, Err(RecvTimeoutError::Timeout)));
+    }
+
+    #[test]
+    fn test_recv_timeout_aborts_on_cancel() {
+        let token = CancelToken::new();
+        let (_tx, rx) = crossbeam::channel::unbounded::<()>();
+
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        let result = token.recv_timeout(&rx, Duration::from_secs(10));
+        assert!(matches!(result, Err(AgentError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
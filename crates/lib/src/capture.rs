@@ -2,6 +2,7 @@ use crossbeam::channel::{self, Receiver, Sender};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
+use crate::cancel::CancelToken;
 use crate::llm::ImageContent;
 use crate::tool::{ToolHandler, ToolResult};
 use crate::AgentError;
@@ -9,6 +10,9 @@ use crate::AgentError;
 /// Maximum tool calls before cached image is discarded.
 const CACHE_MAX_CALLS: u64 = 5;
 
+/// How long a tool waits for Swift to answer a capture request.
+const RESULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 /// Request to capture a screen window (Rust → Swift)
 pub struct CaptureRequest {
     pub id: String,
@@ -80,6 +84,7 @@ pub struct CaptureScreenTool {
     request_tx: Sender<CaptureRequest>,
     result_rx: Receiver<CaptureResult>,
     next_id: AtomicU64,
+    cancel: CancelToken,
     cache: Mutex<Option<CacheInfo>>,
     calls_since_capture: AtomicU64,
 }
@@ -90,10 +95,17 @@ impl CaptureScreenTool {
             request_tx,
             result_rx,
             next_id: AtomicU64::new(1),
+            cancel: CancelToken::new(),
             cache: Mutex::new(None),
             calls_since_capture: AtomicU64::new(0),
         }
     }
+
+    /// Abort waiting for Swift when `cancel` fires.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
 }
 
 impl ToolHandler for CaptureScreenTool {
@@ -136,6 +148,11 @@ impl ToolHandler for CaptureScreenTool {
         })
     }
 
+    fn parallel_safe(&self) -> bool {
+        // Captures share the Swift request channel and the cached image apply_ocr reads
+        false
+    }
+
     fn call(&self, args: serde_json::Value) -> Result<ToolResult, AgentError> {
         let window_id = args
             .get("window_id")
@@ -169,8 +186,8 @@ impl ToolHandler for CaptureScreenTool {
         })?;
 
         let result = self
-            .result_rx
-            .recv_timeout(std::time::Duration::from_secs(10))
+            .cancel
+            .recv_timeout(&self.result_rx, RESULT_TIMEOUT)?
             .map_err(|e| {
                 AgentError::InternalError(format!("Capture timeout or error: {}", e))
             })?;
@@ -202,6 +219,7 @@ pub struct FindWindowTool {
     request_tx: Sender<CaptureRequest>,
     result_rx: Receiver<CaptureResult>,
     next_id: AtomicU64,
+    cancel: CancelToken,
 }
 
 impl FindWindowTool {
@@ -210,8 +228,15 @@ impl FindWindowTool {
             request_tx,
             result_rx,
             next_id: AtomicU64::new(1),
+            cancel: CancelToken::new(),
         }
     }
+
+    /// Abort waiting for Swift when `cancel` fires.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
 }
 
 impl ToolHandler for FindWindowTool {
@@ -237,6 +262,11 @@ impl ToolHandler for FindWindowTool {
         })
     }
 
+    fn parallel_safe(&self) -> bool {
+        // Shares the Swift request channel with the capture tools
+        false
+    }
+
     fn call(&self, args: serde_json::Value) -> Result<ToolResult, AgentError> {
         let keywords = args
             .get("keywords")
@@ -266,8 +296,8 @@ impl ToolHandler for FindWindowTool {
         })?;
 
         let result = self
-            .result_rx
-            .recv_timeout(std::time::Duration::from_secs(10))
+            .cancel
+            .recv_timeout(&self.result_rx, RESULT_TIMEOUT)?
             .map_err(|e| {
                 AgentError::InternalError(format!("find_window timeout or error: {}", e))
             })?;
@@ -281,6 +311,7 @@ pub struct ApplyOcrTool {
     request_tx: Sender<CaptureRequest>,
     result_rx: Receiver<CaptureResult>,
     next_id: AtomicU64,
+    cancel: CancelToken,
 }
 
 impl ApplyOcrTool {
@@ -289,8 +320,15 @@ impl ApplyOcrTool {
             request_tx,
             result_rx,
             next_id: AtomicU64::new(1),
+            cancel: CancelToken::new(),
         }
     }
+
+    /// Abort waiting for Swift when `cancel` fires.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
 }
 
 impl ToolHandler for ApplyOcrTool {
@@ -327,6 +365,11 @@ impl ToolHandler for ApplyOcrTool {
         })
     }
 
+    fn parallel_safe(&self) -> bool {
+        // Reads the cached image a capture in the same response may be replacing
+        false
+    }
+
     fn call(&self, args: serde_json::Value) -> Result<ToolResult, AgentError> {
         let crop_x = args.get("crop_x").and_then(|v| v.as_f64());
         let crop_y = args.get("crop_y").and_then(|v| v.as_f64());
@@ -352,8 +395,8 @@ impl ToolHandler for ApplyOcrTool {
         })?;
 
         let result = self
-            .result_rx
-            .recv_timeout(std::time::Duration::from_secs(10))
+            .cancel
+            .recv_timeout(&self.result_rx, RESULT_TIMEOUT)?
             .map_err(|e| {
                 AgentError::InternalError(format!("apply_ocr timeout or error: {}", e))
             })?;
@@ -510,6 +553,24 @@ mod tests {
         assert!(result.text.contains("12345"));
     }
 
+    #[test]
+    fn test_find_window_cancelled_while_waiting() {
+        let bridge = CaptureBridge::new();
+        let cancel = CancelToken::new();
+        let tool = FindWindowTool::new(bridge.request_tx.clone(), bridge.find_result_rx.clone())
+            .with_cancel(cancel.clone());
+
+        // Swift receives the request but the user barges in before it answers
+        let rx = bridge.request_rx.clone();
+        std::thread::spawn(move || {
+            let _req = rx.recv().unwrap();
+            cancel.cancel();
+        });
+
+        let err = tool.call(serde_json::json!({"keywords": "chrome"})).unwrap_err();
+        assert!(matches!(err, AgentError::Cancelled));
+    }
+
     #[test]
     fn test_find_window_missing_keywords() {
         let bridge = CaptureBridge::new();
@@ -0,0 +1,278 @@
+//! Sub-agent delegation — long exploratory work in a nested ReAct loop.
+//!
+//! The `delegate` tool hands a self-contained task ("find where sessions are saved and
+//! how") to a sub-agent with a fresh conversation, a restricted set of tools and its own
+//! iteration budget, optionally on a different model. Only the sub-agent's final summary
+//! comes back to the parent, so the dozens of files it globs and reads never enter the
+//! voice conversation's context window.
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::approval::{ApprovalRules, ApprovedTools, ToolApprover};
+use crate::cancel::CancelToken;
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::react;
+use crate::tool::{ToolHandler, ToolRegistry, ToolResult};
+use crate::{AgentError, ApprovalForwarder, ApprovalListener};
+
+/// Tools the sub-agent gets when the config names none
+pub const DEFAULT_SUBAGENT_TOOLS: &[&str] = &["read", "glob"];
+
+/// Iterations of the sub-agent's loop when the config gives no limit
+pub const DEFAULT_SUBAGENT_ITERATIONS: u32 = 15;
+
+const SUBAGENT_PROMPT: &str = "You are a research sub-agent working for another assistant. \
+Use your tools to complete the task you are given, then reply with a concise summary of \
+what you found: the answer, the file paths and facts that support it, and anything you \
+could not find. Your reply is all the other assistant will see.";
+
+/// Sub-agent settings. The `delegate` tool is registered only when this is configured.
+#[derive(Debug, Clone, Default)]
+pub struct SubAgentConfig {
+    /// Model for the sub-agent (same provider and endpoint); the parent's model when unset.
+    /// The local provider always shares the parent's model.
+    pub model: Option<String>,
+    /// Tools the sub-agent may use (default: read and glob)
+    pub tools: Option<Vec<String>>,
+    /// Iterations of the sub-agent's loop (default 15)
+    pub max_iterations: Option<u32>,
+}
+
+pub struct DelegateTool {
+    client: Arc<dyn LlmProvider>,
+    registry: ToolRegistry,
+    allowed: Vec<String>,
+    max_iterations: u32,
+    cancel: CancelToken,
+    /// The agent's approval policy and approver, read at each call
+    approval_rules: Arc<Mutex<Arc<ApprovalRules>>>,
+    approver: Arc<Mutex<Option<Arc<dyn ApprovalListener>>>>,
+}
+
+impl DelegateTool {
+    /// `registry` holds the sub-agent's tools; only those named in `allowed` are exposed.
+    pub fn new(
+        client: Arc<dyn LlmProvider>,
+        registry: ToolRegistry,
+        allowed: Vec<String>,
+        max_iterations: u32,
+    ) -> Self {
+        Self {
+            client,
+            registry,
+            allowed,
+            max_iterations,
+            cancel: CancelToken::new(),
+            approval_rules: Arc::new(Mutex::new(Arc::new(ApprovalRules::default()))),
+            approver: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Abort the sub-agent when `cancel` fires.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Apply the agent's approval policy to the sub-agent's tool calls.
+    pub fn with_approval(
+        mut self,
+        rules: Arc<Mutex<Arc<ApprovalRules>>>,
+        approver: Arc<Mutex<Option<Arc<dyn ApprovalListener>>>>,
+    ) -> Self {
+        self.approval_rules = rules;
+        self.approver = approver;
+        self
+    }
+}
+
+impl ToolHandler for DelegateTool {
+    fn name(&self) -> &str {
+        "delegate"
+    }
+
+    fn description(&self) -> &str {
+        "Hand a self-contained research task to a sub-agent that can search and read files, \
+         and get back only its summary. Use it for exploration that would take many tool \
+         calls (e.g. finding where something is implemented across a codebase). Describe \
+         the task and what the summary should contain; the sub-agent does not see this \
+         conversation."
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "task": {
+                    "type": "string",
+                    "description": "What the sub-agent should find out, with any context it needs"
+                }
+            },
+            "required": ["task"]
+        })
+    }
+
+    fn call(&self, args: serde_json::Value) -> Result<ToolResult, AgentError> {
+        let task = args["task"]
+            .as_str()
+            .ok_or_else(|| AgentError::ParseError("Missing 'task' field".to_string()))?;
+
+        tracing::info!("Delegating to sub-agent: {}", task);
+        let filtered = self.registry.filtered(&self.allowed);
+        let rules = self.approval_rules.lock().clone();
+        let approver = self.approver.lock().clone();
+        let forwarder = approver.as_deref().map(ApprovalForwarder);
+        let tools = ApprovedTools::new(
+            &filtered,
+            &rules,
+            forwarder.as_ref().map(|f| f as &dyn ToolApprover),
+        );
+        let mut messages = vec![
+            ChatMessage::system(SUBAGENT_PROMPT.to_string()),
+            ChatMessage::user(task.to_string()),
+        ];
+        let (summary, _, usage) = react::run(
+            self.client.as_ref(),
+            &mut messages,
+            &tools,
+            Some(self.max_iterations),
+            &self.cancel,
+            None,
+        )?;
+        tracing::info!(
+            "Sub-agent finished after {} messages (tokens: in={}, out={})",
+            messages.len(),
+            usage.input_tokens,
+            usage.output_tokens
+        );
+        Ok(ToolResult::text(summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approval::{ToolApprovalConfig, ToolApprovalRule};
+    use crate::llm::{ChatRole, LlmResponse, RequestOptions, ToolCallInfo, ToolDefinition};
+    use crate::tool::{GlobTool, ReadTool, TaskTool};
+
+    /// Makes one tool call, then answers; records what each request contained
+    struct ScriptedProvider {
+        call: (&'static str, serde_json::Value),
+        requests: Mutex<Vec<(Vec<ChatMessage>, Vec<String>)>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(name: &'static str, arguments: serde_json::Value) -> Arc<Self> {
+            Arc::new(Self {
+                call: (name, arguments),
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    impl LlmProvider for ScriptedProvider {
+        fn chat(
+            &self,
+            _messages: &[ChatMessage],
+            _options: &RequestOptions,
+        ) -> anyhow::Result<String> {
+            Ok(String::new())
+        }
+
+        fn supports_tools(&self) -> bool {
+            true
+        }
+
+        fn chat_with_tools(
+            &self,
+            messages: &[ChatMessage],
+            tools: &[ToolDefinition],
+            _options: &RequestOptions,
+        ) -> anyhow::Result<LlmResponse> {
+            let mut requests = self.requests.lock();
+            let names = tools.iter().map(|t| t.name.clone()).collect();
+            requests.push((messages.to_vec(), names));
+            Ok(if requests.len() == 1 {
+                LlmResponse::ToolCalls(
+                    vec![ToolCallInfo {
+                        id: "call_1".to_string(),
+                        name: self.call.0.to_string(),
+                        arguments: self.call.1.clone(),
+                    }],
+                    None,
+                )
+            } else {
+                LlmResponse::Text {
+                    content: "Found notes.md".to_string(),
+                    reasoning: None,
+                    usage: None,
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_delegate_runs_nested_loop_with_allowed_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.md"), "hello").unwrap();
+        let mut registry = ToolRegistry::new();
+        registry.register(Box::new(GlobTool::new(dir.path().to_path_buf())));
+        registry.register(Box::new(TaskTool::new()));
+
+        let provider = ScriptedProvider::new("glob", serde_json::json!({ "pattern": "*.md" }));
+        let tool = DelegateTool::new(provider.clone(), registry, vec!["glob".to_string()], 5);
+
+        let result = tool
+            .call(serde_json::json!({ "task": "Find the notes" }))
+            .unwrap();
+        assert_eq!(result.text, "Found notes.md");
+
+        let requests = provider.requests.lock();
+        assert_eq!(requests.len(), 2);
+        // A fresh conversation with only the allowed tools
+        let (messages, tools) = &requests[0];
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, ChatRole::System);
+        assert_eq!(messages[1].content, "Find the notes");
+        assert_eq!(tools, &["glob"]);
+        // The glob result went to the sub-agent, not the caller
+        assert!(requests[1].0.last().unwrap().content.contains("notes.md"));
+    }
+
+    #[test]
+    fn test_delegate_applies_approval_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "hunter2").unwrap();
+        let mut registry = ToolRegistry::new();
+        registry.register(Box::new(ReadTool::new(dir.path().to_path_buf())));
+
+        let rules = ApprovalRules::new(&ToolApprovalConfig {
+            default_policy: None,
+            rules: vec![ToolApprovalRule {
+                tool: "read".to_string(),
+                argument: None,
+                pattern: None,
+                policy: "deny".to_string(),
+            }],
+        })
+        .unwrap();
+        let provider =
+            ScriptedProvider::new("read", serde_json::json!({ "file_path": "secret.txt" }));
+        let tool = DelegateTool::new(provider.clone(), registry, vec!["read".to_string()], 5)
+            .with_approval(
+                Arc::new(Mutex::new(Arc::new(rules))),
+                Arc::new(Mutex::new(None)),
+            );
+
+        tool.call(serde_json::json!({ "task": "Read the secret" }))
+            .unwrap();
+
+        let requests = provider.requests.lock();
+        let result = &requests[1].0.last().unwrap().content;
+        assert!(result.contains("did not run"));
+        assert!(!result.contains("hunter2"));
+    }
+}
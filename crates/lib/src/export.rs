@@ -0,0 +1,394 @@
+//! Conversation exporters for review and training data.
+//!
+//! A `Transcript` is rendered as one of three formats:
+//! - `markdown`: readable transcript with tool calls, results, reasoning and timings
+//! - `json`: structured document with the system prompt, tool definitions and every
+//!   message with its metadata (timestamp, model, token usage, latencies) and reasoning;
+//!   images inline as base64
+//! - `jsonl`: one OpenAI chat fine-tuning example (`{"messages": [...], "tools": [...]}`)
+//!   per line
+
+use std::fmt::Write as _;
+
+use serde::Serialize;
+
+use crate::history::StoredMessage;
+use crate::llm::{ChatMessage, ChatRole, OpenAiCompatProvider, ToolDefinition};
+use crate::memory::MessageMetadata;
+use crate::AgentError;
+
+/// Version written by the `json` format
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Marker identifying structured exports
+const EXPORT_FORMAT: &str = "voice-agent-export";
+
+/// Output format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    FineTuneJsonl,
+}
+
+impl ExportFormat {
+    /// Parse a format name: `markdown`/`md`, `json`, or `jsonl`/`finetune`
+    pub fn parse(name: &str) -> Result<Self, AgentError> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            "jsonl" | "finetune" | "fine-tune" => Ok(Self::FineTuneJsonl),
+            other => Err(AgentError::ConfigError(format!(
+                "Unknown export format '{}' (expected markdown, json or jsonl)",
+                other
+            ))),
+        }
+    }
+
+    /// File extension for exports in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Json => "json",
+            Self::FineTuneJsonl => "jsonl",
+        }
+    }
+}
+
+/// A message with what is known about how it was produced
+#[derive(Debug, Clone)]
+pub struct ExportEntry {
+    pub message: ChatMessage,
+    pub metadata: Option<MessageMetadata>,
+    pub reasoning: Option<String>,
+}
+
+impl ExportEntry {
+    /// Entry with no metadata or reasoning
+    pub fn new(message: ChatMessage) -> Self {
+        Self {
+            message,
+            metadata: None,
+            reasoning: None,
+        }
+    }
+}
+
+/// A conversation ready for export
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    pub title: Option<String>,
+    pub system_prompt: Option<String>,
+    /// Tools the assistant could call
+    pub tools: Vec<ToolDefinition>,
+    pub entries: Vec<ExportEntry>,
+}
+
+#[derive(Serialize)]
+struct ExportDocument<'a> {
+    format: &'static str,
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: &'a [ToolDefinition],
+    messages: Vec<ExportedMessage<'a>>,
+}
+
+#[derive(Serialize)]
+struct ExportedMessage<'a> {
+    #[serde(flatten)]
+    message: StoredMessage,
+    #[serde(flatten)]
+    metadata: Option<&'a MessageMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<&'a str>,
+}
+
+impl Transcript {
+    /// Render the transcript in `format`
+    pub fn render(&self, format: ExportFormat) -> Result<String, AgentError> {
+        match format {
+            ExportFormat::Markdown => Ok(self.to_markdown()),
+            ExportFormat::Json => self.to_json(),
+            ExportFormat::FineTuneJsonl => self.to_fine_tune_jsonl(),
+        }
+    }
+
+    /// Readable transcript. Each message is headed by its role and time; replies show
+    /// their reasoning and note the model, how long after the preceding message they
+    /// came, where that time went, token usage, and whether the user interrupted them.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", self.title.as_deref().unwrap_or("Conversation"));
+        if let Some(prompt) = &self.system_prompt {
+            let _ = write!(out, "\n## System prompt\n\n{}\n", prompt);
+        }
+
+        let mut previous_ms = None;
+        for entry in &self.entries {
+            let message = &entry.message;
+            let heading = match message.role {
+                ChatRole::System => "System",
+                ChatRole::User => "User",
+                ChatRole::Assistant => "Assistant",
+                ChatRole::Tool => "Tool result",
+            };
+            let _ = write!(out, "\n## {}", heading);
+            if let Some(name) = &message.tool_name {
+                let _ = write!(out, " `{}`", name);
+            }
+            let timestamp = entry.metadata.as_ref().map(|m| m.timestamp_ms);
+            if let Some(ms) = timestamp {
+                let _ = write!(out, " · {}", format_utc(ms));
+            }
+            out.push_str("\n\n");
+
+            if let Some(reasoning) = entry.reasoning.as_deref().filter(|r| !r.is_empty()) {
+                for line in reasoning.lines() {
+                    let _ = writeln!(out, "> {}", line);
+                }
+                out.push('\n');
+            }
+            if !message.content.is_empty() {
+                if message.role == ChatRole::Tool {
+                    out.push_str(&fenced(&message.content, ""));
+                } else {
+                    let _ = writeln!(out, "{}", message.content);
+                }
+            }
+            for image in &message.images {
+                let _ = writeln!(out, "\n_[image: {}]_", image.media_type);
+            }
+            for call in message.tool_calls.iter().flatten() {
+                let arguments = serde_json::to_string_pretty(&call.arguments).unwrap_or_default();
+                let _ = write!(out, "\n**Tool call** `{}`\n\n", call.name);
+                out.push_str(&fenced(&arguments, "json"));
+            }
+
+            let mut notes = Vec::new();
+            let metadata = entry.metadata.as_ref();
+            if let Some(model) = metadata.and_then(|m| m.model.as_deref()) {
+                notes.push(model.to_string());
+            }
+            if message.role == ChatRole::Assistant {
+                if let (Some(ms), Some(prev)) = (timestamp, previous_ms) {
+                    notes.push(seconds(ms.saturating_sub(prev)));
+                }
+            }
+            if let Some(m) = metadata {
+                let latencies = [
+                    ("LLM", m.llm_latency_ms),
+                    ("first token", m.first_token_ms),
+                    ("tools", m.tool_latency_ms.filter(|&ms| ms > 0)),
+                ];
+                for (label, ms) in latencies {
+                    if let Some(ms) = ms {
+                        notes.push(format!("{} {}", label, seconds(ms)));
+                    }
+                }
+                if let Some(usage) = &m.usage {
+                    notes.push(format!(
+                        "{} in / {} out tokens",
+                        usage.input_tokens, usage.output_tokens
+                    ));
+                }
+                if m.interrupted {
+                    notes.push("interrupted".to_string());
+                }
+            }
+            if !notes.is_empty() {
+                let _ = writeln!(out, "\n_{}_", notes.join(", "));
+            }
+            previous_ms = timestamp.or(previous_ms);
+        }
+        out
+    }
+
+    /// Structured document with everything the transcript holds
+    pub fn to_json(&self) -> Result<String, AgentError> {
+        let document = ExportDocument {
+            format: EXPORT_FORMAT,
+            version: EXPORT_FORMAT_VERSION,
+            title: self.title.as_deref(),
+            system_prompt: self.system_prompt.as_deref(),
+            tools: &self.tools,
+            messages: self
+                .entries
+                .iter()
+                .map(|e| {
+                    Ok(ExportedMessage {
+                        message: StoredMessage::from_message(&e.message, None)?,
+                        metadata: e.metadata.as_ref(),
+                        reasoning: e.reasoning.as_deref(),
+                    })
+                })
+                .collect::<Result<_, AgentError>>()?,
+        };
+        serde_json::to_string_pretty(&document)
+            .map_err(|e| AgentError::InternalError(format!("Failed to encode export: {}", e)))
+    }
+
+    /// OpenAI chat fine-tuning example: the whole conversation as one line, with the
+    /// system prompt first and the tool definitions the assistant was given
+    pub fn to_fine_tune_jsonl(&self) -> Result<String, AgentError> {
+        let mut messages: Vec<ChatMessage> = Vec::new();
+        if let Some(prompt) = &self.system_prompt {
+            messages.push(ChatMessage::system(prompt.clone()));
+        }
+        messages.extend(self.entries.iter().map(|e| e.message.clone()));
+
+        let mut example = serde_json::json!({
+            "messages": OpenAiCompatProvider::convert_messages(&messages),
+        });
+        if !self.tools.is_empty() {
+            example["tools"] = OpenAiCompatProvider::convert_tools(&self.tools).into();
+        }
+        let mut line = serde_json::to_string(&example)
+            .map_err(|e| AgentError::InternalError(format!("Failed to encode export: {}", e)))?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+/// Wrap `text` in a code fence longer than any backtick run inside it
+fn fenced(text: &str, lang: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!(
+        "{}{}\n{}\n{}\n",
+        fence,
+        lang,
+        text.trim_end_matches('\n'),
+        fence
+    )
+}
+
+/// Milliseconds as seconds with one decimal, e.g. `2.5 s`
+fn seconds(ms: u64) -> String {
+    format!("{:.1} s", ms as f64 / 1000.0)
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC` for a Unix timestamp in milliseconds
+fn format_utc(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{TokenUsage, ToolCallInfo};
+
+    fn transcript() -> Transcript {
+        let call = ToolCallInfo {
+            id: "c1".to_string(),
+            name: "read".to_string(),
+            arguments: serde_json::json!({"file_path": "notes.txt"}),
+        };
+        let mut reply = ExportEntry::new(ChatMessage::assistant("It says hi.".to_string()));
+        reply.reasoning = Some("The file is short.".to_string());
+        reply.metadata = Some(MessageMetadata {
+            timestamp_ms: 1_700_000_002_500,
+            model: Some("gpt-test".to_string()),
+            usage: Some(TokenUsage {
+                input_tokens: 120,
+                output_tokens: 8,
+                total_tokens: 128,
+            }),
+            llm_latency_ms: Some(1800),
+            tool_latency_ms: Some(700),
+            interrupted: true,
+            ..Default::default()
+        });
+        let mut user = ExportEntry::new(ChatMessage::user("What's in notes?".to_string()));
+        user.metadata = Some(MessageMetadata {
+            timestamp_ms: 1_700_000_000_000,
+            ..Default::default()
+        });
+        Transcript {
+            title: Some("Notes".to_string()),
+            system_prompt: Some("You are helpful.".to_string()),
+            tools: vec![ToolDefinition {
+                name: "read".to_string(),
+                description: "Read a file".to_string(),
+                parameters: serde_json::json!({"type": "object"}),
+            }],
+            entries: vec![
+                user,
+                ExportEntry::new(ChatMessage::assistant_tool_calls(vec![call])),
+                ExportEntry::new(ChatMessage::tool_result(
+                    "c1".to_string(),
+                    "read".to_string(),
+                    "hi ```x```".to_string(),
+                )),
+                reply,
+            ],
+        }
+    }
+
+    #[test]
+    fn test_markdown_shows_tools_reasoning_and_timing() {
+        let md = transcript().to_markdown();
+        assert!(md.starts_with("# Notes\n"));
+        assert!(md.contains("## User · 2023-11-14 22:13:20 UTC"));
+        assert!(md.contains("**Tool call** `read`"));
+        assert!(md.contains("## Tool result `read`"));
+        assert!(md.contains("````\nhi ```x```\n````"));
+        assert!(md.contains("> The file is short."));
+        assert!(md.contains(
+            "_gpt-test, 2.5 s, LLM 1.8 s, tools 0.7 s, 120 in / 8 out tokens, interrupted_"
+        ));
+    }
+
+    #[test]
+    fn test_json_and_fine_tune_formats() {
+        let transcript = transcript();
+        let json: serde_json::Value =
+            serde_json::from_str(&transcript.render(ExportFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["format"], EXPORT_FORMAT);
+        assert_eq!(json["tools"][0]["name"], "read");
+        assert_eq!(json["messages"][1]["tool_calls"][0]["id"], "c1");
+        assert_eq!(json["messages"][3]["reasoning"], "The file is short.");
+        assert_eq!(json["messages"][3]["usage"]["total_tokens"], 128);
+        assert_eq!(json["messages"][3]["llm_latency_ms"], 1800);
+        assert_eq!(json["messages"][3]["interrupted"], true);
+        assert!(json["messages"][1].get("timestamp_ms").is_none());
+
+        let jsonl = transcript.render(ExportFormat::FineTuneJsonl).unwrap();
+        assert_eq!(jsonl.lines().count(), 1);
+        let example: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+        let messages = example["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(
+            messages[2]["tool_calls"][0]["function"]["arguments"],
+            "{\"file_path\":\"notes.txt\"}"
+        );
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(example["tools"][0]["function"]["name"], "read");
+
+        assert!(ExportFormat::parse("yaml").is_err());
+        assert_eq!(ExportFormat::parse("MD").unwrap(), ExportFormat::Markdown);
+    }
+}
@@ -0,0 +1,516 @@
+//! Long-term fact store — durable facts the agent remembers across sessions.
+//!
+//! Facts are short sentences ("My staging server is staging.example.com", "Call me Ken")
+//! saved by the model through the `memory` tool and kept in a JSON file. Retrieval is
+//! lexical BM25 over words, with CJK text split into character bigrams, so it runs fully
+//! offline and works the same whichever LLM provider is in use. The agent also injects
+//! the facts most relevant to each user message into the prompt.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::now_ms;
+use crate::tokens::is_cjk;
+use crate::tool::{ToolHandler, ToolResult};
+use crate::AgentError;
+
+/// Version written to the fact file
+const FACTS_FORMAT_VERSION: u32 = 1;
+
+/// BM25 term-frequency saturation
+const BM25_K1: f64 = 1.2;
+
+/// BM25 length normalisation
+const BM25_B: f64 = 0.75;
+
+/// Results returned by `recall` when the model gives no limit
+const DEFAULT_RECALL_LIMIT: usize = 5;
+
+/// Facts the agent injects into the prompt for each user message
+pub const PROMPT_FACT_LIMIT: usize = 3;
+
+/// Injected facts must score at least this fraction of the best match
+const PROMPT_RELATIVE_SCORE: f64 = 0.5;
+
+/// English function words, ignored when indexing and querying so that "what is the
+/// time?" does not match every fact containing "is" or "the"
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "am", "an", "and", "any", "are", "as", "at", "be", "been", "but", "by", "can",
+    "could", "did", "do", "does", "for", "from", "had", "has", "have", "he", "her", "here", "him",
+    "his", "how", "i", "if", "in", "is", "it", "its", "me", "my", "no", "not", "of", "on", "or",
+    "our", "please", "she", "should", "so", "that", "the", "their", "them", "there", "these",
+    "they", "this", "those", "to", "was", "we", "were", "what", "when", "where", "which", "who",
+    "why", "will", "with", "would", "you", "your",
+];
+
+/// A remembered fact
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fact {
+    pub id: u32,
+    pub text: String,
+    pub created_at_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FactFile {
+    version: u32,
+    next_id: u32,
+    facts: Vec<Fact>,
+}
+
+/// Thread-safe fact store, optionally backed by a JSON file.
+pub struct FactStore {
+    path: Option<PathBuf>,
+    state: Mutex<FactFile>,
+}
+
+impl FactStore {
+    /// Store kept in process memory only
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            state: Mutex::new(FactFile {
+                version: FACTS_FORMAT_VERSION,
+                next_id: 1,
+                facts: Vec::new(),
+            }),
+        }
+    }
+
+    /// Store backed by `path`, loading the facts saved there (if any).
+    /// Every change rewrites the file.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, AgentError> {
+        let path = path.into();
+        let state = match fs::read_to_string(&path) {
+            Ok(json) => {
+                let mut file: FactFile = serde_json::from_str(&json).map_err(|e| {
+                    AgentError::ParseError(format!("Invalid fact file {}: {}", path.display(), e))
+                })?;
+                if file.version > FACTS_FORMAT_VERSION {
+                    return Err(AgentError::ParseError(format!(
+                        "Fact file {} has version {}, newer than supported {}",
+                        path.display(),
+                        file.version,
+                        FACTS_FORMAT_VERSION
+                    )));
+                }
+                let max_id = file.facts.iter().map(|f| f.id).max().unwrap_or(0);
+                file.next_id = file.next_id.max(max_id + 1);
+                file
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => FactFile {
+                version: FACTS_FORMAT_VERSION,
+                next_id: 1,
+                facts: Vec::new(),
+            },
+            Err(e) => {
+                return Err(AgentError::InternalError(format!(
+                    "Failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        tracing::info!(
+            "Loaded {} fact(s) from {}",
+            state.facts.len(),
+            path.display()
+        );
+        Ok(Self {
+            path: Some(path),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Save a fact. Remembering the same text again returns the existing fact.
+    pub fn remember(&self, text: &str) -> Result<Fact, AgentError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(AgentError::ParseError(
+                "Fact text must not be empty".to_string(),
+            ));
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(existing) = state
+            .facts
+            .iter()
+            .find(|f| f.text.to_lowercase() == text.to_lowercase())
+        {
+            return Ok(existing.clone());
+        }
+        let fact = Fact {
+            id: state.next_id,
+            text: text.to_string(),
+            created_at_ms: now_ms(),
+        };
+        state.next_id += 1;
+        state.facts.push(fact.clone());
+        self.save(&state)?;
+        tracing::info!("Remembered fact #{}", fact.id);
+        Ok(fact)
+    }
+
+    /// Remove a fact by ID, returning it if it existed
+    pub fn forget(&self, id: u32) -> Result<Option<Fact>, AgentError> {
+        let mut state = self.state.lock().unwrap();
+        let Some(pos) = state.facts.iter().position(|f| f.id == id) else {
+            return Ok(None);
+        };
+        let fact = state.facts.remove(pos);
+        self.save(&state)?;
+        tracing::info!("Forgot fact #{}", id);
+        Ok(Some(fact))
+    }
+
+    /// Up to `limit` facts matching `query`, best first, with their BM25 scores.
+    /// Facts sharing no term with the query are not returned.
+    pub fn recall(&self, query: &str, limit: usize) -> Vec<(Fact, f64)> {
+        let state = self.state.lock().unwrap();
+        let query_terms: HashSet<String> = terms(query).into_iter().collect();
+        if query_terms.is_empty() || state.facts.is_empty() {
+            return Vec::new();
+        }
+
+        let docs: Vec<Vec<String>> = state.facts.iter().map(|f| terms(&f.text)).collect();
+        let n = docs.len() as f64;
+        let avg_len = docs.iter().map(|d| d.len()).sum::<usize>() as f64 / n;
+        let mut doc_freq: HashMap<&str, usize> = HashMap::new();
+        for doc in &docs {
+            let unique: HashSet<&str> = doc.iter().map(String::as_str).collect();
+            for term in unique {
+                *doc_freq.entry(term).or_default() += 1;
+            }
+        }
+
+        let mut scored: Vec<(Fact, f64)> = state
+            .facts
+            .iter()
+            .zip(&docs)
+            .filter_map(|(fact, doc)| {
+                let len_norm = 1.0 - BM25_B + BM25_B * doc.len() as f64 / avg_len.max(1.0);
+                let score: f64 = query_terms
+                    .iter()
+                    .map(|term| {
+                        let tf = doc.iter().filter(|t| *t == term).count() as f64;
+                        if tf == 0.0 {
+                            return 0.0;
+                        }
+                        let df = doc_freq.get(term.as_str()).copied().unwrap_or(0) as f64;
+                        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * len_norm)
+                    })
+                    .sum();
+                (score > 0.0).then(|| (fact.clone(), score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(limit);
+        scored
+    }
+
+    /// All facts, oldest first
+    pub fn all(&self) -> Vec<Fact> {
+        self.state.lock().unwrap().facts.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().facts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// System note with the facts relevant to `query`, for injection into the prompt.
+    /// Weak matches far below the best one are left out.
+    pub fn prompt_note(&self, query: &str, limit: usize) -> Option<String> {
+        let facts = self.recall(query, limit);
+        let best = facts.first()?.1;
+        let mut note = "Facts you remember from earlier conversations (memory tool):".to_string();
+        for (fact, _) in facts
+            .iter()
+            .filter(|(_, score)| *score >= best * PROMPT_RELATIVE_SCORE)
+        {
+            note.push_str(&format!("\n- [#{}] {}", fact.id, fact.text));
+        }
+        Some(note)
+    }
+
+    /// Write the facts to the backing file via a temporary file, so a crash never
+    /// leaves it half-written
+    fn save(&self, state: &FactFile) -> Result<(), AgentError> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| {
+                AgentError::InternalError(format!("Failed to create {}: {}", dir.display(), e))
+            })?;
+        }
+        let json = serde_json::to_string_pretty(state)
+            .map_err(|e| AgentError::InternalError(format!("Failed to encode facts: {}", e)))?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| {
+                AgentError::InternalError(format!("Failed to write {}: {}", path.display(), e))
+            })
+    }
+}
+
+/// Lowercased alphanumeric words other than stop words; runs of CJK characters become
+/// overlapping bigrams (or a single character), since they are not separated by spaces.
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+
+    fn flush_cjk(cjk: &mut Vec<char>, terms: &mut Vec<String>) {
+        match cjk.len() {
+            0 => {}
+            1 => terms.push(cjk[0].to_string()),
+            _ => terms.extend(cjk.windows(2).map(|w| w.iter().collect())),
+        }
+        cjk.clear();
+    }
+
+    for ch in text.chars() {
+        if is_cjk(ch) && ch.is_alphanumeric() {
+            if !word.is_empty() {
+                terms.push(std::mem::take(&mut word));
+            }
+            cjk.push(ch);
+        } else if ch.is_alphanumeric() {
+            flush_cjk(&mut cjk, &mut terms);
+            word.extend(ch.to_lowercase());
+        } else {
+            flush_cjk(&mut cjk, &mut terms);
+            if !word.is_empty() {
+                terms.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_cjk(&mut cjk, &mut terms);
+    if !word.is_empty() {
+        terms.push(word);
+    }
+    terms.retain(|t| !STOP_WORDS.contains(&t.as_str()));
+    terms
+}
+
+// ============================================================================
+// MemoryTool — remember / recall / forget
+// ============================================================================
+
+pub struct MemoryTool {
+    store: Arc<FactStore>,
+}
+
+impl MemoryTool {
+    pub fn new(store: Arc<FactStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl ToolHandler for MemoryTool {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn description(&self) -> &str {
+        "Long-term memory that persists across conversations. Actions: remember (save a \
+         durable fact the user states or asks you to keep, such as names, preferences, \
+         hosts, paths), recall (search saved facts), forget (delete a fact by id). \
+         Save one self-contained fact per call."
+    }
+
+    fn dynamic_state(&self) -> Option<String> {
+        match self.store.len() {
+            0 => None,
+            1 => Some("1 fact saved".to_string()),
+            n => Some(format!("{} facts saved", n)),
+        }
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "action": {
+                    "type": "string",
+                    "enum": ["remember", "recall", "forget"],
+                    "description": "Action to perform"
+                },
+                "fact": {
+                    "type": "string",
+                    "description": "The fact to save, as a short standalone sentence (for remember)"
+                },
+                "query": {
+                    "type": "string",
+                    "description": "Keywords to search for (for recall; omit to list every fact)"
+                },
+                "id": {
+                    "type": "integer",
+                    "description": "ID of the fact to delete (for forget)"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Maximum number of facts to return (for recall, default: 5)"
+                }
+            },
+            "required": ["action"]
+        })
+    }
+
+    fn parallel_safe(&self) -> bool {
+        // "remember" then "recall" in one response must see the new fact
+        false
+    }
+
+    fn call(&self, args: serde_json::Value) -> Result<ToolResult, AgentError> {
+        let action = args["action"]
+            .as_str()
+            .ok_or_else(|| AgentError::ParseError("Missing 'action' field".to_string()))?;
+
+        match action {
+            "remember" => {
+                let text = args["fact"].as_str().ok_or_else(|| {
+                    AgentError::ParseError("Missing 'fact' field for 'remember' action".to_string())
+                })?;
+                let fact = self.store.remember(text)?;
+                Ok(ToolResult::text(format!(
+                    "Remembered fact #{}: {}",
+                    fact.id, fact.text
+                )))
+            }
+            "recall" => {
+                let limit = args["limit"]
+                    .as_u64()
+                    .map(|n| n as usize)
+                    .unwrap_or(DEFAULT_RECALL_LIMIT);
+                let facts: Vec<Fact> = match args["query"].as_str().filter(|q| !q.trim().is_empty())
+                {
+                    Some(query) => self
+                        .store
+                        .recall(query, limit)
+                        .into_iter()
+                        .map(|(fact, _)| fact)
+                        .collect(),
+                    None => self.store.all(),
+                };
+                if facts.is_empty() {
+                    return Ok(ToolResult::text("No matching facts.".to_string()));
+                }
+                let lines: Vec<String> = facts
+                    .iter()
+                    .map(|f| format!("[#{}] {}", f.id, f.text))
+                    .collect();
+                Ok(ToolResult::text(lines.join("\n")))
+            }
+            "forget" => {
+                let id = args["id"].as_u64().ok_or_else(|| {
+                    AgentError::ParseError("Missing 'id' field for 'forget' action".to_string())
+                })?;
+                let id = u32::try_from(id)
+                    .map_err(|_| AgentError::ParseError(format!("No fact has id {}", id)))?;
+                match self.store.forget(id)? {
+                    Some(fact) => Ok(ToolResult::text(format!(
+                        "Forgot fact #{}: {}",
+                        fact.id, fact.text
+                    ))),
+                    None => Ok(ToolResult::text(format!(
+                        "Fact #{} not found. Use action 'recall' to see saved facts.",
+                        id
+                    ))),
+                }
+            }
+            _ => Err(AgentError::ParseError(format!(
+                "Unknown action: {}",
+                action
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recall_ranks_by_bm25() {
+        let store = FactStore::in_memory();
+        store
+            .remember("My staging server is staging.example.com")
+            .unwrap();
+        store.remember("Call me Ken").unwrap();
+        store.remember("The production server runs Ubuntu").unwrap();
+
+        let results = store.recall("which staging server?", 5);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].0.text.contains("staging.example.com"));
+        assert!(results[0].1 > results[1].1);
+        assert!(store.recall("weather", 5).is_empty());
+    }
+
+    #[test]
+    fn test_japanese_bigrams() {
+        let store = FactStore::in_memory();
+        store.remember("私の名前は健です").unwrap();
+        store.remember("好きな食べ物は寿司").unwrap();
+        let results = store.recall("名前を覚えてる？", 5);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].0.text.contains("健"));
+    }
+
+    #[test]
+    fn test_persistence_and_tool_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("facts.json");
+        let tool = MemoryTool::new(Arc::new(FactStore::open(&path).unwrap()));
+        tool.call(serde_json::json!({"action": "remember", "fact": "Call me Ken"}))
+            .unwrap();
+        tool.call(serde_json::json!({"action": "remember", "fact": "call me ken"}))
+            .unwrap();
+        tool.call(serde_json::json!({"action": "remember", "fact": "Editor is Helix"}))
+            .unwrap();
+        let forgot = tool
+            .call(serde_json::json!({"action": "forget", "id": 2}))
+            .unwrap();
+        assert!(forgot.text.contains("Helix"));
+        // An id past u32 must not wrap around to fact #1
+        let overflow = tool.call(serde_json::json!({"action": "forget", "id": (1u64 << 32) + 1}));
+        assert!(matches!(overflow, Err(AgentError::ParseError(_))));
+
+        let reopened = FactStore::open(&path).unwrap();
+        assert_eq!(reopened.all().len(), 1);
+        assert_eq!(reopened.remember("Uses zsh").unwrap().id, 3);
+        let note = reopened.prompt_note("what should I call you", 3).unwrap();
+        assert!(note.contains("[#1] Call me Ken"));
+    }
+
+    #[test]
+    fn test_prompt_note_ignores_stop_words_and_weak_matches() {
+        let store = FactStore::in_memory();
+        store.remember("My name is Ken").unwrap();
+        store
+            .remember("The staging server is staging.example.com")
+            .unwrap();
+        store.remember("You should answer briefly").unwrap();
+        assert!(store.prompt_note("what is the time?", 3).is_none());
+
+        store
+            .remember("The staging server restarts at midnight")
+            .unwrap();
+        store.remember("Backups run at midnight").unwrap();
+        // "Backups" shares only "midnight" with the question
+        let note = store
+            .prompt_note("does the staging server restart at midnight?", 3)
+            .unwrap();
+        assert!(note.contains("restarts at midnight"));
+        assert!(!note.contains("Backups"));
+    }
+}
@@ -22,6 +22,12 @@ impl HarmonyTemplate {
         let mut last_role: Option<ChatRole> = None;
 
         for msg in messages {
+            // Tool calls and results always pass: dropping one would orphan its counterpart
+            if msg.tool_calls.is_some() || msg.role == ChatRole::Tool {
+                formatted.push(msg.clone());
+                last_role = None;
+                continue;
+            }
             // Skip duplicate consecutive roles (except system)
             if msg.role == ChatRole::System || Some(msg.role.clone()) != last_role {
                 formatted.push(msg.clone());
@@ -69,6 +75,26 @@ mod tests {
         assert_eq!(formatted[2].role, ChatRole::Assistant);
     }
 
+    #[test]
+    fn test_format_messages_keeps_tool_trace() {
+        let call = |id: &str| crate::llm::ToolCallInfo {
+            id: id.to_string(),
+            name: "read_file".to_string(),
+            arguments: serde_json::json!({}),
+        };
+        let messages = vec![
+            ChatMessage::user("Read both".to_string()),
+            ChatMessage::assistant_tool_calls(vec![call("a"), call("b")]),
+            ChatMessage::tool_result("a".to_string(), "read_file".to_string(), "A".to_string()),
+            ChatMessage::tool_result("b".to_string(), "read_file".to_string(), "B".to_string()),
+            ChatMessage::assistant("Done".to_string()),
+        ];
+
+        let formatted = HarmonyTemplate::format_messages(&messages);
+        assert_eq!(formatted.len(), 5);
+        assert_eq!(formatted[4].content, "Done");
+    }
+
     #[test]
     fn test_create_system_message() {
         let msg = HarmonyTemplate::create_system_message("Test".to_string());
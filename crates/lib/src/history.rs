@@ -0,0 +1,409 @@
+//! Versioned, full-fidelity serialisation of conversation history.
+//!
+//! A history document keeps everything needed to replay a conversation through
+//! `react::run`: tool calls with their IDs and arguments, tool results, and attached
+//! images. Images are either inlined as base64 or written once to an image directory
+//! under a content hash, so transcripts with many screenshots stay small.
+//!
+//! ```json
+//! {"format": "voice-agent-history", "version": 1, "messages": [
+//!   {"role": "user", "content": "What's on screen?"},
+//!   {"role": "assistant", "content": "", "tool_calls": [{"id": "c1", "name": "capture_screen", "arguments": {}}]},
+//!   {"role": "tool", "content": "Captured", "tool_call_id": "c1", "tool_name": "capture_screen",
+//!    "images": [{"media_type": "image/png", "file": "3f2a….png"}]}
+//! ]}
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{ChatMessage, ChatRole, ImageContent, ToolCallInfo};
+use crate::AgentError;
+
+/// Version written by `to_json`
+pub const HISTORY_FORMAT_VERSION: u32 = 1;
+
+/// Marker identifying history documents
+const HISTORY_FORMAT: &str = "voice-agent-history";
+
+/// Text standing in for an image whose file is missing
+const IMAGE_UNAVAILABLE: &str = "[image unavailable]";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryDocument {
+    format: String,
+    version: u32,
+    messages: Vec<StoredMessage>,
+}
+
+/// A `ChatMessage` as stored, with images inline or by reference
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub role: ChatRole,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<StoredImage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+/// Image data inline, or the name of a file in the image directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StoredImage {
+    Inline { media_type: String, data: String },
+    File { media_type: String, file: String },
+}
+
+impl StoredMessage {
+    /// Convert a message for storage. With `image_dir`, images are written there
+    /// (once per distinct content) and referenced by file name; otherwise inlined.
+    pub fn from_message(
+        message: &ChatMessage,
+        image_dir: Option<&Path>,
+    ) -> Result<Self, AgentError> {
+        let images = message
+            .images
+            .iter()
+            .map(|img| match image_dir {
+                Some(dir) => Ok(StoredImage::File {
+                    media_type: img.media_type.clone(),
+                    file: save_image(dir, img)?,
+                }),
+                None => Ok(StoredImage::Inline {
+                    media_type: img.media_type.clone(),
+                    data: img.base64.clone(),
+                }),
+            })
+            .collect::<Result<Vec<_>, AgentError>>()?;
+
+        Ok(Self {
+            role: message.role.clone(),
+            content: message.content.clone(),
+            images,
+            tool_calls: message.tool_calls.clone(),
+            tool_call_id: message.tool_call_id.clone(),
+            tool_name: message.tool_name.clone(),
+        })
+    }
+
+    /// Rebuild the message, reading referenced images from `image_dir`
+    pub fn into_message(self, image_dir: Option<&Path>) -> Result<ChatMessage, AgentError> {
+        let images = self
+            .images
+            .into_iter()
+            .map(|img| match img {
+                StoredImage::Inline { media_type, data } => Ok(ImageContent {
+                    base64: data,
+                    media_type,
+                }),
+                StoredImage::File { media_type, file } => Ok(ImageContent {
+                    base64: load_image(image_dir, &file)?,
+                    media_type,
+                }),
+            })
+            .collect::<Result<Vec<_>, AgentError>>()?;
+
+        Ok(ChatMessage {
+            role: self.role,
+            content: self.content,
+            images,
+            tool_calls: self.tool_calls,
+            tool_call_id: self.tool_call_id,
+            tool_name: self.tool_name,
+        })
+    }
+
+    /// Like `into_message`, but an image that cannot be read becomes a note in the text,
+    /// so one lost file does not make the message (or its session) unreadable
+    pub fn into_message_lossy(self, image_dir: Option<&Path>) -> ChatMessage {
+        let mut content = self.content;
+        let mut images = Vec::with_capacity(self.images.len());
+        for img in self.images {
+            match img {
+                StoredImage::Inline { media_type, data } => images.push(ImageContent {
+                    base64: data,
+                    media_type,
+                }),
+                StoredImage::File { media_type, file } => match load_image(image_dir, &file) {
+                    Ok(base64) => images.push(ImageContent { base64, media_type }),
+                    Err(e) => {
+                        tracing::warn!("Image {} unavailable: {}", file, e);
+                        if !content.is_empty() {
+                            content.push('\n');
+                        }
+                        content.push_str(IMAGE_UNAVAILABLE);
+                    }
+                },
+            }
+        }
+
+        ChatMessage {
+            role: self.role,
+            content,
+            images,
+            tool_calls: self.tool_calls,
+            tool_call_id: self.tool_call_id,
+            tool_name: self.tool_name,
+        }
+    }
+}
+
+/// Serialise `messages` as a versioned history document.
+/// With `image_dir`, images are externalised to files there instead of inlined.
+pub fn to_json(messages: &[ChatMessage], image_dir: Option<&Path>) -> Result<String, AgentError> {
+    let document = HistoryDocument {
+        format: HISTORY_FORMAT.to_string(),
+        version: HISTORY_FORMAT_VERSION,
+        messages: messages
+            .iter()
+            .map(|m| StoredMessage::from_message(m, image_dir))
+            .collect::<Result<_, _>>()?,
+    };
+    serde_json::to_string_pretty(&document)
+        .map_err(|e| AgentError::InternalError(format!("Failed to encode history: {}", e)))
+}
+
+/// Parse a history document (or a bare message array, as older versions of
+/// `get_conversation_history` produced) into messages ready for `react::run`.
+/// Tool calls and results that lost their counterpart are dropped.
+pub fn from_json(json: &str, image_dir: Option<&Path>) -> Result<Vec<ChatMessage>, AgentError> {
+    let value: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| AgentError::ParseError(format!("Invalid history JSON: {}", e)))?;
+
+    let stored: Vec<StoredMessage> = if value.is_array() {
+        serde_json::from_value(value)
+    } else {
+        let document: HistoryDocument = serde_json::from_value(value)
+            .map_err(|e| AgentError::ParseError(format!("Invalid history document: {}", e)))?;
+        if document.format != HISTORY_FORMAT {
+            return Err(AgentError::ParseError(format!(
+                "Not a history document: format {:?}",
+                document.format
+            )));
+        }
+        if document.version > HISTORY_FORMAT_VERSION {
+            return Err(AgentError::ParseError(format!(
+                "History format version {} is newer than supported {}",
+                document.version, HISTORY_FORMAT_VERSION
+            )));
+        }
+        Ok(document.messages)
+    }
+    .map_err(|e| AgentError::ParseError(format!("Invalid history messages: {}", e)))?;
+
+    let messages = stored
+        .into_iter()
+        .map(|m| m.into_message(image_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(repair_tool_pairs(messages))
+}
+
+/// Drop tool results whose call is missing and tool calls that never got a result
+/// (e.g. a turn cut short), which providers reject. An assistant message left with
+/// neither calls nor text is dropped as well.
+pub fn repair_tool_pairs(mut messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let keep = repair_tool_pairs_mask(messages.iter_mut().collect());
+    messages
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(message, keep)| keep.then_some(message))
+        .collect()
+}
+
+/// `repair_tool_pairs` for messages held elsewhere (e.g. with metadata in memory):
+/// unanswered calls are removed in place, and the result says which messages to keep.
+pub(crate) fn repair_tool_pairs_mask(mut messages: Vec<&mut ChatMessage>) -> Vec<bool> {
+    let answered: HashSet<String> = messages
+        .iter()
+        .filter(|m| m.role == ChatRole::Tool)
+        .filter_map(|m| m.tool_call_id.clone())
+        .collect();
+
+    let mut called: HashSet<String> = HashSet::new();
+    messages
+        .iter_mut()
+        .map(|message| match message.role {
+            ChatRole::Tool => {
+                let known = message
+                    .tool_call_id
+                    .as_ref()
+                    .is_some_and(|id| called.contains(id));
+                if !known {
+                    tracing::warn!("Dropping tool result without a matching call");
+                }
+                known
+            }
+            ChatRole::Assistant if message.tool_calls.is_some() => {
+                let calls: Vec<ToolCallInfo> = message
+                    .tool_calls
+                    .take()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|c| answered.contains(&c.id))
+                    .collect();
+                called.extend(calls.iter().map(|c| c.id.clone()));
+                if !calls.is_empty() {
+                    message.tool_calls = Some(calls);
+                }
+                message.tool_calls.is_some() || !message.content.is_empty()
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+/// Write an image to `dir` under its content hash, returning the file name.
+/// Existing files are not rewritten, so identical screenshots are stored once.
+fn save_image(dir: &Path, image: &ImageContent) -> Result<String, AgentError> {
+    let bytes = BASE64
+        .decode(&image.base64)
+        .map_err(|e| AgentError::ParseError(format!("Invalid base64 image data: {}", e)))?;
+    let file = format!(
+        "{}.{}",
+        content_hash(&bytes),
+        extension_for(&image.media_type)
+    );
+    let path = dir.join(&file);
+    if !path.exists() {
+        fs::create_dir_all(dir).map_err(|e| {
+            AgentError::InternalError(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+        fs::write(&path, &bytes).map_err(|e| {
+            AgentError::InternalError(format!("Failed to write {}: {}", path.display(), e))
+        })?;
+    }
+    Ok(file)
+}
+
+fn load_image(dir: Option<&Path>, file: &str) -> Result<String, AgentError> {
+    let dir = dir.ok_or_else(|| {
+        AgentError::ConfigError(format!(
+            "Image {} is stored externally but no image directory was given",
+            file
+        ))
+    })?;
+    // References are bare file names; anything else could read outside the directory
+    if file.contains(['/', '\\']) || file.starts_with('.') {
+        return Err(AgentError::ParseError(format!(
+            "Invalid image reference: {}",
+            file
+        )));
+    }
+    let path = dir.join(file);
+    let bytes = fs::read(&path).map_err(|e| {
+        AgentError::InternalError(format!("Failed to read {}: {}", path.display(), e))
+    })?;
+    Ok(BASE64.encode(bytes))
+}
+
+fn extension_for(media_type: &str) -> &'static str {
+    match media_type {
+        "image/png" => "png",
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "bin",
+    }
+}
+
+/// 128-bit FNV-1a of `bytes` as hex. Not cryptographic; it only names image files.
+fn content_hash(bytes: &[u8]) -> String {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let hash = bytes
+        .iter()
+        .fold(OFFSET, |h, &b| (h ^ b as u128).wrapping_mul(PRIME));
+    format!("{:032x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_turn() -> Vec<ChatMessage> {
+        let call = ChatMessage::assistant_tool_calls(vec![ToolCallInfo {
+            id: "call_1".to_string(),
+            name: "capture_screen".to_string(),
+            arguments: serde_json::json!({"window": "Safari"}),
+        }]);
+        let result = ChatMessage::tool_result_with_images(
+            "call_1".to_string(),
+            "capture_screen".to_string(),
+            "Captured Safari".to_string(),
+            vec![ImageContent {
+                base64: BASE64.encode(b"\x89PNG fake image"),
+                media_type: "image/png".to_string(),
+            }],
+        );
+        vec![
+            ChatMessage::user("What's on screen?".to_string()),
+            call,
+            result,
+            ChatMessage::assistant("A web page.".to_string()),
+        ]
+    }
+
+    fn stored(messages: &[ChatMessage]) -> Vec<StoredMessage> {
+        messages
+            .iter()
+            .map(|m| StoredMessage::from_message(m, None).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_roundtrip_inline_keeps_tool_trace_and_images() {
+        let messages = tool_turn();
+        let json = to_json(&messages, None).unwrap();
+        let loaded = from_json(&json, None).unwrap();
+        assert_eq!(stored(&loaded), stored(&messages));
+        assert_eq!(
+            loaded[1].tool_calls.as_ref().unwrap()[0].arguments["window"],
+            "Safari"
+        );
+        assert_eq!(loaded[2].tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_images_externalised_once_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut messages = tool_turn();
+        messages.push(messages[2].clone());
+        let json = to_json(&messages, Some(dir.path())).unwrap();
+        assert!(!json.contains(&messages[2].images[0].base64));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let loaded = from_json(&json, Some(dir.path())).unwrap();
+        assert_eq!(loaded[2].images[0].base64, messages[2].images[0].base64);
+        assert!(from_json(&json, None).is_err());
+    }
+
+    #[test]
+    fn test_legacy_array_and_dangling_calls() {
+        let legacy =
+            r#"[{"role": "user", "content": "hi"}, {"role": "assistant", "content": "hello"}]"#;
+        assert_eq!(from_json(legacy, None).unwrap().len(), 2);
+
+        // A turn interrupted after the call: the call has no result yet
+        let mut messages = tool_turn();
+        messages.truncate(2);
+        messages.push(ChatMessage::tool_result(
+            "call_9".to_string(),
+            "read_file".to_string(),
+            "orphan".to_string(),
+        ));
+        let loaded = from_json(&to_json(&messages, None).unwrap(), None).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].role, ChatRole::User);
+    }
+}
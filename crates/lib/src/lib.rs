@@ -1,6 +1,12 @@
+pub mod approval;
+pub mod cancel;
 pub mod capture;
+pub mod delegate;
 pub mod event_router;
+pub mod export;
+pub mod facts;
 mod harmony;
+pub mod history;
 mod llm;
 #[cfg(feature = "local")]
 pub mod llm_local;
@@ -11,21 +17,34 @@ pub mod mcp_server;
 pub mod mcp_server_http;
 mod memory;
 pub mod react;
+pub mod session;
 pub mod situation;
 pub mod skill;
 mod state_updater;
+mod tokens;
 pub mod tool;
 
 use parking_lot::Mutex;
+use std::cell::Cell;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub use approval::{ToolApprovalConfig, ToolApprovalRule};
+pub use cancel::CancelToken;
 pub use capture::CaptureRequest;
+pub use delegate::SubAgentConfig;
 pub use harmony::HarmonyTemplate;
-pub use llm::{create_provider, ChatMessage, ChatRole, TokenUsage};
+pub use llm::{
+    create_provider, ChatMessage, ChatRole, LlmProvider, LocalRuntimeConfig, SamplingConfig,
+    TokenUsage,
+};
 use tool::ToolAccess;
-pub use memory::ConversationMemory;
+pub use react::ReactEvent;
+pub use memory::{
+    CompactionConfig, ConversationMemory, ConversationStats, MessageMetadata, ToolMemoryConfig,
+};
+pub use session::{SessionInfo, SessionStore};
 pub use state_updater::{BackchannelDetector, RuleBasedBackchannelDetector};
 
 // UniFFI generated code
@@ -84,6 +103,10 @@ pub struct McpServerConfig {
 
 /// Configuration for the agent
 pub struct AgentConfig {
+    /// Provider backend: "local", "openai" (Responses API), "openai_compat"
+    /// (Chat Completions API) or "anthropic" (Messages API).
+    /// `None` selects automatically from model_path/base_url.
+    pub provider: Option<String>,
     pub model_path: Option<String>,
     pub base_url: String,
     pub model: String,
@@ -92,16 +115,43 @@ pub struct AgentConfig {
     pub temperature: Option<f32>,
     pub max_tokens: u32,
     /// Model context window size in tokens (used for compaction triggering).
+    /// Clamped to the local provider's context size when that is smaller.
     pub context_window: u32,
     pub language: Option<String>,
     pub working_dir: Option<String>,
     pub reasoning_effort: Option<String>,
+    /// Sampling chain for the local provider (top-k/p, min-p, penalties, DRY, seed)
+    pub sampling: Option<SamplingConfig>,
+    /// Context size, GPU offload, threads, KV cache type and chat template for the local provider
+    pub local_runtime: Option<LocalRuntimeConfig>,
+    /// When and how memory is compacted (plain dropping at 90% down to 50% by default)
+    pub compaction: Option<CompactionConfig>,
+    /// Which tool calls and results are kept for later turns (all of them by default)
+    pub tool_memory: Option<ToolMemoryConfig>,
+    /// Directory for persisted sessions (one JSONL file each). `None` keeps the
+    /// conversation in memory only.
+    pub sessions_dir: Option<String>,
+    /// JSON file for long-term facts saved with the `memory` tool. `None` keeps them
+    /// for the lifetime of the agent only.
+    pub facts_path: Option<String>,
+    /// Tool calls from one model response that may run at once (default 4; 1 runs them
+    /// one after another). Tools that must be serialised opt out individually.
+    pub max_parallel_tools: Option<u32>,
+    /// Which tool calls run freely, are refused or wait for `ApprovalListener` (all run
+    /// when unset)
+    pub tool_approval: Option<ToolApprovalConfig>,
+    /// Registers the `delegate` tool, which runs research tasks in a sub-agent
+    pub subagent: Option<SubAgentConfig>,
+    /// Reply when the model keeps calling tools after the ReAct loop stopped and asked for
+    /// an answer. A short apology in the user's language (English or Japanese) when unset.
+    pub no_answer_reply: Option<String>,
     pub mcp_servers: Vec<McpServerConfig>,
 }
 
 impl Default for AgentConfig {
     fn default() -> Self {
         Self {
+            provider: None,
             model_path: None,
             base_url: "https://api.openai.com/v1".to_string(),
             model: "gpt-4o-mini".to_string(),
@@ -113,6 +163,16 @@ impl Default for AgentConfig {
             language: Some("en".to_string()),
             working_dir: None,
             reasoning_effort: None,
+            sampling: None,
+            local_runtime: None,
+            compaction: None,
+            tool_memory: None,
+            sessions_dir: None,
+            facts_path: None,
+            max_parallel_tools: None,
+            tool_approval: None,
+            subagent: None,
+            no_answer_reply: None,
             mcp_servers: Vec::new(),
         }
     }
@@ -131,6 +191,192 @@ pub struct AgentResponse {
     pub context_percent: f32,
 }
 
+/// Settings for one step (`Agent::step_with_options`). Unset fields keep the agent's
+/// configuration.
+#[derive(Debug, Clone, Default)]
+pub struct StepOptions {
+    /// Only these tools are offered (all registered tools when unset)
+    pub allowed_tools: Option<Vec<String>>,
+    /// ReAct iterations before the agent asks for a final answer (default 10)
+    pub max_iterations: Option<u32>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub reasoning_effort: Option<String>,
+    /// "auto" (default), "none", "required", or the name of a tool to call. Applies to the
+    /// first LLM call of the step; a tool the step does not offer is a `ConfigError`.
+    pub tool_choice: Option<String>,
+    /// JSON Schema (as JSON text) the reply must match. The reply content is then that
+    /// JSON, generated without tools.
+    pub json_schema: Option<String>,
+}
+
+/// `StepOptions` in the form a turn uses them
+#[derive(Debug, Clone, Default)]
+struct TurnOptions {
+    react: react::ReactOptions,
+    json_schema: Option<serde_json::Value>,
+    /// Set by `rerun_turn`: the turn replaces the one it popped, so the session records
+    /// the undo with the new turn, once that succeeds
+    replaces_last: bool,
+}
+
+impl StepOptions {
+    /// Turn settings from these options, with the agent-wide ones from `config`
+    fn turn_options(&self, config: &AgentConfig) -> Result<TurnOptions, AgentError> {
+        let json_schema = match self.json_schema {
+            Some(ref schema) => Some(serde_json::from_str(schema).map_err(|e| {
+                AgentError::ConfigError(format!("Invalid json_schema: {}", e))
+            })?),
+            None => None,
+        };
+        Ok(TurnOptions {
+            react: react::ReactOptions {
+                max_iterations: self.max_iterations,
+                request: llm::RequestOptions {
+                    model: self.model.clone(),
+                    temperature: self.temperature,
+                    reasoning_effort: self.reasoning_effort.clone(),
+                    tool_choice: self
+                        .tool_choice
+                        .as_deref()
+                        .map(llm::ToolChoice::parse)
+                        .unwrap_or_default(),
+                },
+                no_answer_reply: config.no_answer_reply.clone(),
+            },
+            json_schema,
+            replaces_last: false,
+        })
+    }
+}
+
+/// Receives incremental response text from `Agent::step_streaming`.
+/// Implemented on the Swift side (e.g. to feed TTS sentence by sentence).
+pub trait StreamListener: Send + Sync {
+    fn on_text_delta(&self, delta: String);
+}
+
+/// Receives ReAct loop progress (LLM and tool calls) while a step runs, e.g. so the
+/// Swift side can speak a short filler during a slow tool call.
+pub trait ProgressListener: Send + Sync {
+    fn on_progress(&self, event: ReactEvent);
+}
+
+/// Decides tool calls the approval policy marks "ask", e.g. by asking the user
+/// "Should I run that?". Blocks the step until it returns; `arguments` is JSON.
+pub trait ApprovalListener: Send + Sync {
+    fn approve_tool_call(&self, name: String, arguments: String) -> bool;
+}
+
+/// Forwards approval questions to the agent's `ApprovalListener`
+pub(crate) struct ApprovalForwarder<'a>(&'a dyn ApprovalListener);
+
+impl approval::ToolApprover for ApprovalForwarder<'_> {
+    fn approve(&self, name: &str, args: &serde_json::Value) -> bool {
+        self.0.approve_tool_call(name.to_string(), args.to_string())
+    }
+}
+
+/// Forwards ReAct events to the agent's `ProgressListener`
+struct ProgressForwarder<'a>(&'a dyn ProgressListener);
+
+impl react::ReactObserver for ProgressForwarder<'_> {
+    fn on_event(&self, event: ReactEvent) {
+        self.0.on_progress(event);
+    }
+}
+
+/// Reply produced by `Agent::respond`
+struct Reply {
+    text: String,
+    keywords: Vec<String>,
+    reasoning: Option<String>,
+    usage: TokenUsage,
+    /// Assistant tool calls and tool results that led to the reply, oldest first
+    trace: Vec<ChatMessage>,
+    /// Time from the request to the first streamed text
+    first_token: Option<Duration>,
+}
+
+impl Reply {
+    /// Plain reply without reasoning, usage or tool calls
+    fn text(text: String, keywords: Vec<String>) -> Self {
+        Self {
+            text,
+            keywords,
+            reasoning: None,
+            usage: TokenUsage::default(),
+            trace: Vec::new(),
+            first_token: None,
+        }
+    }
+}
+
+/// Tool access that measures the wall-clock time during which at least one tool call was
+/// running, so calls that ran concurrently are not counted twice
+struct TimedTools<'a> {
+    inner: &'a dyn ToolAccess,
+    timer: Mutex<ToolTimer>,
+}
+
+#[derive(Default)]
+struct ToolTimer {
+    in_flight: usize,
+    /// When the current stretch of running calls began
+    since: Option<Instant>,
+    elapsed: Duration,
+}
+
+impl<'a> TimedTools<'a> {
+    fn new(inner: &'a dyn ToolAccess) -> Self {
+        Self {
+            inner,
+            timer: Mutex::new(ToolTimer::default()),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.timer.lock().elapsed
+    }
+}
+
+impl ToolAccess for TimedTools<'_> {
+    fn get_definitions(&self) -> Vec<llm::ToolDefinition> {
+        self.inner.get_definitions()
+    }
+
+    fn call(&self, name: &str, args: serde_json::Value) -> Result<tool::ToolResult, AgentError> {
+        {
+            let mut timer = self.timer.lock();
+            if timer.in_flight == 0 {
+                timer.since = Some(Instant::now());
+            }
+            timer.in_flight += 1;
+        }
+        let result = self.inner.call(name, args);
+        let mut timer = self.timer.lock();
+        timer.in_flight -= 1;
+        if timer.in_flight == 0 {
+            if let Some(since) = timer.since.take() {
+                timer.elapsed += since.elapsed();
+            }
+        }
+        result
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn is_parallel_safe(&self, name: &str) -> bool {
+        self.inner.is_parallel_safe(name)
+    }
+
+    fn max_parallel_calls(&self) -> usize {
+        self.inner.max_parallel_calls()
+    }
+}
+
 /// Error types for the agent
 #[derive(Debug, thiserror::Error)]
 pub enum AgentError {
@@ -142,19 +388,41 @@ pub enum AgentError {
     ConfigError(String),
     #[error("Internal error: {0}")]
     InternalError(String),
+    #[error("Cancelled")]
+    Cancelled,
+}
+
+impl AgentError {
+    /// Map an LLM provider error, keeping cancellation distinct from network failures
+    pub fn from_provider(e: anyhow::Error) -> Self {
+        match e.downcast::<AgentError>() {
+            Ok(AgentError::Cancelled) => AgentError::Cancelled,
+            Ok(other) => AgentError::NetworkError(other.to_string()),
+            Err(e) => AgentError::NetworkError(e.to_string()),
+        }
+    }
 }
 
 /// Main agent struct
 pub struct Agent {
     config: AgentConfig,
-    client: Box<dyn llm::LlmProvider>,
+    client: Arc<dyn llm::LlmProvider>,
     memory: Arc<Mutex<ConversationMemory>>,
     backchannel_detector: Box<dyn BackchannelDetector>,
     system_prompt: Arc<Mutex<Option<String>>>,
     tool_registry: tool::ToolRegistry,
     skill_registry: Arc<skill::SkillRegistry>,
     situation: Arc<situation::SituationMessages>,
-    last_input_tokens: AtomicU64,
+    facts: Arc<facts::FactStore>,
+    cancel: CancelToken,
+    progress: Mutex<Option<Arc<dyn ProgressListener>>>,
+    /// Shared with the sub-agent, whose tool calls follow the same policy
+    approval_rules: Arc<Mutex<Arc<approval::ApprovalRules>>>,
+    approver: Arc<Mutex<Option<Arc<dyn ApprovalListener>>>>,
+    tool_retention: memory::ToolRetention,
+    sessions: Option<SessionStore>,
+    /// Session the conversation is appended to; started on the first persisted turn
+    session_id: Mutex<Option<String>>,
     capture_request_rx: crossbeam::channel::Receiver<capture::CaptureRequest>,
     capture_result_tx: crossbeam::channel::Sender<capture::CaptureResult>,
     find_result_tx: crossbeam::channel::Sender<capture::CaptureResult>,
@@ -162,7 +430,7 @@ pub struct Agent {
 }
 
 // Top-level constructor function for UniFFI
-pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
+pub fn agent_new(mut config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
     // Initialize tracing (only once)
     let _ = tracing_subscriber::fmt()
         .with_env_filter(
@@ -172,7 +440,8 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
         .try_init();
 
     // Create LLM provider
-    let client = create_provider(
+    let client: Arc<dyn llm::LlmProvider> = Arc::from(create_provider(
+        config.provider.clone(),
         config.model_path.clone(),
         config.base_url.clone(),
         config.model.clone(),
@@ -180,8 +449,43 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
         config.temperature,
         config.max_tokens,
         config.reasoning_effort.clone(),
+        config.sampling.clone().unwrap_or_default(),
+        config.local_runtime.clone().unwrap_or_default(),
     )
-    .map_err(|e| AgentError::ConfigError(e.to_string()))?;
+    .map_err(|e| AgentError::ConfigError(e.to_string()))?);
+
+    // Compaction must trigger before a local context overflows
+    if let Some(n_ctx) = client.context_size() {
+        if config.context_window > n_ctx {
+            tracing::warn!(
+                "context_window {} exceeds the local context size {}, using {} (raise local_runtime.n_ctx for more)",
+                config.context_window,
+                n_ctx,
+                n_ctx
+            );
+            config.context_window = n_ctx;
+        }
+    }
+
+    let tool_retention = config
+        .tool_memory
+        .clone()
+        .unwrap_or_default()
+        .retention()
+        .map_err(AgentError::ConfigError)?;
+
+    let approval_rules = match config.tool_approval {
+        Some(ref approval) => {
+            approval::ApprovalRules::new(approval).map_err(AgentError::ConfigError)?
+        }
+        None => approval::ApprovalRules::default(),
+    };
+    let approval_rules = Arc::new(Mutex::new(Arc::new(approval_rules)));
+    let approver: Arc<Mutex<Option<Arc<dyn ApprovalListener>>>> = Arc::new(Mutex::new(None));
+
+    // One token per agent: cancel() reaches the provider, ReAct loop and capture tools
+    let cancel = CancelToken::new();
+    client.set_cancel_token(cancel.clone());
 
     // Create tool registry with built-in tools
     let working_dir = config
@@ -199,7 +503,7 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
     let capture_bridge = capture::CaptureBridge::new();
 
     let mut tool_registry = tool::create_default_registry(
-        working_dir,
+        working_dir.clone(),
         skill_registry.clone(),
         situation.clone(),
     );
@@ -219,19 +523,98 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
         }
     }
 
+    // Long-term facts, shared by the memory tool and prompt injection
+    let facts = Arc::new(match config.facts_path {
+        Some(ref path) => facts::FactStore::open(path)?,
+        None => facts::FactStore::in_memory(),
+    });
+    tool_registry.register(Box::new(facts::MemoryTool::new(facts.clone())));
+
     // Register capture tools (shared request channel, separate result channels)
-    tool_registry.register(Box::new(capture::CaptureScreenTool::new(
-        capture_bridge.request_tx.clone(),
-        capture_bridge.capture_result_rx.clone(),
-    )));
-    tool_registry.register(Box::new(capture::FindWindowTool::new(
-        capture_bridge.request_tx.clone(),
-        capture_bridge.find_result_rx.clone(),
-    )));
-    tool_registry.register(Box::new(capture::ApplyOcrTool::new(
-        capture_bridge.request_tx.clone(),
-        capture_bridge.ocr_result_rx.clone(),
-    )));
+    tool_registry.register(Box::new(
+        capture::CaptureScreenTool::new(
+            capture_bridge.request_tx.clone(),
+            capture_bridge.capture_result_rx.clone(),
+        )
+        .with_cancel(cancel.clone()),
+    ));
+    tool_registry.register(Box::new(
+        capture::FindWindowTool::new(
+            capture_bridge.request_tx.clone(),
+            capture_bridge.find_result_rx.clone(),
+        )
+        .with_cancel(cancel.clone()),
+    ));
+    tool_registry.register(Box::new(
+        capture::ApplyOcrTool::new(
+            capture_bridge.request_tx.clone(),
+            capture_bridge.ocr_result_rx.clone(),
+        )
+        .with_cancel(cancel.clone()),
+    ));
+
+    if let Some(limit) = config.max_parallel_tools {
+        tool_registry.set_max_parallel(limit as usize);
+    }
+
+    // Sub-agent with its own copy of the built-in tools (never `delegate` itself)
+    if let Some(ref subagent) = config.subagent {
+        let kind = llm::provider_kind(
+            config.provider.as_deref(),
+            config.model_path.as_deref(),
+            &config.base_url,
+        );
+        let sub_client = match subagent.model {
+            // A second local model would be loaded into memory again
+            Some(ref model) if kind != "local" => {
+                let sub_client = create_provider(
+                    config.provider.clone(),
+                    None,
+                    config.base_url.clone(),
+                    model.clone(),
+                    config.api_key.clone(),
+                    config.temperature,
+                    config.max_tokens,
+                    config.reasoning_effort.clone(),
+                    config.sampling.clone().unwrap_or_default(),
+                    LocalRuntimeConfig::default(),
+                )
+                .map_err(|e| AgentError::ConfigError(e.to_string()))?;
+                sub_client.set_cancel_token(cancel.clone());
+                Arc::from(sub_client)
+            }
+            Some(ref model) => {
+                tracing::warn!(
+                    "subagent.model '{}' ignored: the local provider shares the parent's model",
+                    model
+                );
+                client.clone()
+            }
+            None => client.clone(),
+        };
+        let allowed = subagent.tools.clone().unwrap_or_else(|| {
+            delegate::DEFAULT_SUBAGENT_TOOLS.iter().map(|s| s.to_string()).collect()
+        });
+        let sub_registry =
+            tool::create_default_registry(working_dir, skill_registry.clone(), situation.clone());
+        tool_registry.register(Box::new(
+            delegate::DelegateTool::new(
+                sub_client,
+                sub_registry,
+                allowed,
+                subagent
+                    .max_iterations
+                    .unwrap_or(delegate::DEFAULT_SUBAGENT_ITERATIONS),
+            )
+            .with_cancel(cancel.clone())
+            .with_approval(approval_rules.clone(), approver.clone()),
+        ));
+    }
+
+    let sessions = config.sessions_dir.as_ref().map(|dir| {
+        tracing::info!("Sessions directory: {}", dir);
+        SessionStore::new(dir)
+    });
 
     Ok(Arc::new(Agent {
         config,
@@ -242,7 +625,14 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
         tool_registry,
         skill_registry,
         situation,
-        last_input_tokens: AtomicU64::new(0),
+        facts,
+        cancel,
+        progress: Mutex::new(None),
+        approval_rules,
+        approver,
+        tool_retention,
+        sessions,
+        session_id: Mutex::new(None),
         capture_request_rx: capture_bridge.request_rx,
         capture_result_tx: capture_bridge.capture_result_tx,
         find_result_tx: capture_bridge.find_result_tx,
@@ -253,17 +643,245 @@ pub fn agent_new(config: AgentConfig) -> Result<Arc<Agent>, AgentError> {
 impl Agent {
     /// Process a user input and return the agent's response
     pub fn step(&self, user_input: String) -> Result<AgentResponse, AgentError> {
-        let mut memory = self.memory.lock();
+        self.step_inner(user_input, &StepOptions::default(), None)
+    }
 
-        // Compact if last turn approached context window limit (>= 90%)
-        self.maybe_compact(&mut memory);
+    /// Process a user input, delivering response text to `listener` as it is generated.
+    /// Keyword extraction is skipped in this mode since it requires a complete JSON response.
+    pub fn step_streaming(
+        &self,
+        user_input: String,
+        listener: Box<dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.step_inner(user_input, &StepOptions::default(), Some(listener.as_ref()))
+    }
+
+    /// Process a user input with per-step tools, limits and model settings
+    pub fn step_with_options(
+        &self,
+        user_input: String,
+        options: StepOptions,
+    ) -> Result<AgentResponse, AgentError> {
+        self.step_inner(user_input, &options, None)
+    }
+
+    /// `step_with_options`, delivering response text to `listener` as it is generated
+    pub fn step_streaming_with_options(
+        &self,
+        user_input: String,
+        options: StepOptions,
+        listener: Box<dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.step_inner(user_input, &options, Some(listener.as_ref()))
+    }
+
+    /// Report the progress of subsequent steps to `listener`, replacing any previous one
+    pub fn set_progress_listener(&self, listener: Box<dyn ProgressListener>) {
+        *self.progress.lock() = Some(Arc::from(listener));
+    }
+
+    pub fn clear_progress_listener(&self) {
+        *self.progress.lock() = None;
+    }
+
+    /// Send tool calls the approval policy marks "ask" to `listener`. Without one they
+    /// are refused.
+    pub fn set_approval_listener(&self, listener: Box<dyn ApprovalListener>) {
+        *self.approver.lock() = Some(Arc::from(listener));
+    }
+
+    pub fn clear_approval_listener(&self) {
+        *self.approver.lock() = None;
+    }
+
+    /// Replace the tool approval policy (e.g. after the user says "always allow that")
+    pub fn set_tool_approval(&self, config: ToolApprovalConfig) -> Result<(), AgentError> {
+        let rules = approval::ApprovalRules::new(&config).map_err(AgentError::ConfigError)?;
+        *self.approval_rules.lock() = Arc::new(rules);
+        Ok(())
+    }
+
+    /// Abort the step in flight, if any. The aborted step returns `AgentError::Cancelled`
+    /// and its user message is removed from memory.
+    pub fn cancel(&self) {
+        tracing::info!("Cancel requested");
+        self.cancel.cancel();
+    }
+
+    /// Every step variant runs through here
+    fn step_inner(
+        &self,
+        user_input: String,
+        options: &StepOptions,
+        listener: Option<&dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.with_step_options(options, |tools, turn| {
+            let mut memory = self.memory.lock();
+            self.run_turn(&mut memory, user_input, tools, turn, listener)
+        })
+    }
+
+    /// Run `f` with the tools and turn settings `options` select
+    fn with_step_options<T>(
+        &self,
+        options: &StepOptions,
+        f: impl FnOnce(&dyn ToolAccess, &TurnOptions) -> Result<T, AgentError>,
+    ) -> Result<T, AgentError> {
+        let turn = options.turn_options(&self.config)?;
+        let filtered;
+        let tools: &dyn ToolAccess = match options.allowed_tools {
+            Some(ref allowed) => {
+                filtered = self.tool_registry.filtered(allowed);
+                &filtered
+            }
+            None => &self.tool_registry,
+        };
+        // A tool the request does not offer would only fail at the provider, mid-turn
+        if let llm::ToolChoice::Tool(ref name) = turn.react.request.tool_choice {
+            let offered = self.uses_tools(tools)
+                && tools.get_definitions().iter().any(|d| d.name == *name);
+            if !offered {
+                return Err(AgentError::ConfigError(format!(
+                    "tool_choice '{}' is not \"auto\", \"none\", \"required\" or one of this step's tools",
+                    name
+                )));
+            }
+        }
+        f(tools, &turn)
+    }
+
+    /// Answer `user_input` as the next turn of the conversation in `memory`
+    fn run_turn(
+        &self,
+        memory: &mut ConversationMemory,
+        user_input: String,
+        tools: &dyn ToolAccess,
+        turn: &TurnOptions,
+        listener: Option<&dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        // A cancel() aimed at an earlier step must not abort this one
+        self.cancel.reset();
 
         // Add user message to memory
-        memory.add_message(ChatMessage::user(user_input.clone()));
+        let user_metadata = MessageMetadata::now();
+        memory.add_message_with_metadata(
+            ChatMessage::user(user_input.clone()),
+            user_metadata.clone(),
+        );
+
+        // Compact before sending if this request would approach the context window (>= 90%)
+        self.maybe_compact(memory, tools);
 
+        let started = Instant::now();
+        let timed_tools = TimedTools::new(tools);
+        let rules = self.approval_rules.lock().clone();
+        let approver = self.approver.lock().clone();
+        let forwarder = approver.as_deref().map(ApprovalForwarder);
+        let approved_tools = approval::ApprovedTools::new(
+            &timed_tools,
+            &rules,
+            forwarder.as_ref().map(|f| f as &dyn approval::ToolApprover),
+        );
+        let result = self.respond(memory, &approved_tools, turn, listener);
+        let tool_time = timed_tools.elapsed();
+        // Waiting for the user's answer is neither LLM nor tool time
+        let llm_time = started
+            .elapsed()
+            .saturating_sub(tool_time + approved_tools.waited());
+        if matches!(result, Err(AgentError::Cancelled)) {
+            // Discard the turn so the next step starts from the previous state
+            memory.pop_message();
+            tracing::info!("Step cancelled, user message discarded");
+        }
+        let Reply {
+            text: response_text,
+            keywords,
+            reasoning,
+            usage,
+            trace,
+            first_token,
+        } = result?;
+        let reply_metadata = MessageMetadata {
+            provider: Some(llm::provider_kind(
+                self.config.provider.as_deref(),
+                self.config.model_path.as_deref(),
+                &self.config.base_url,
+            )),
+            model: Some(turn.react.request.model.clone().unwrap_or_else(|| self.model_name())),
+            usage: Some(usage.clone()),
+            llm_latency_ms: Some(llm_time.as_millis() as u64),
+            first_token_ms: first_token.map(|d| d.as_millis() as u64),
+            tool_latency_ms: Some(tool_time.as_millis() as u64),
+            ..MessageMetadata::now()
+        };
+        tracing::info!(
+            "Turn latency: LLM {} ms, tools {} ms",
+            llm_time.as_millis(),
+            tool_time.as_millis()
+        );
+
+        // Keep the tool trace as the policy allows, then the assistant response
+        let trace = memory.add_tool_trace(trace, self.tool_retention);
+        let reply = ChatMessage::assistant(response_text.clone());
+        memory.add_message_with_metadata(reply.clone(), reply_metadata.clone());
+        let pruned_to = match self.tool_retention {
+            memory::ToolRetention::LastTurns(turns) if memory.prune_tool_turns(turns) > 0 => {
+                Some(turns)
+            }
+            _ => None,
+        };
+        self.persist(|store, id| {
+            if turn.replaces_last {
+                store.append_undo(id)?;
+            }
+            store.append_message(id, &ChatMessage::user(user_input), &user_metadata)?;
+            for message in &trace {
+                store.append_message(id, message, &MessageMetadata::now())?;
+            }
+            store.append_reply(id, &reply, &reply_metadata, reasoning.as_deref())?;
+            if let Some(turns) = pruned_to {
+                store.append_tool_pruning(id, turns)?;
+            }
+            Ok(())
+        });
+
+        let context_percent = if self.config.context_window > 0 {
+            (usage.input_tokens as f64 / self.config.context_window as f64 * 100.0) as f32
+        } else {
+            0.0
+        };
+
+        Ok(AgentResponse {
+            content: response_text,
+            role: "assistant".to_string(),
+            is_final: true,
+            keywords: if keywords.is_empty() { None } else { Some(keywords) },
+            reasoning,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+            context_percent,
+        })
+    }
+
+    /// Messages sent to the provider for the conversation in `memory`: custom system prompt,
+    /// history with remembered facts relevant to the latest user message, and skill
+    /// catalog, Harmony-formatted if enabled.
+    fn request_messages(&self, memory: &ConversationMemory) -> Vec<ChatMessage> {
         // Get conversation context
         let mut messages = memory.get_messages();
 
+        // Facts matching what the user just said, placed right before that message so the
+        // earlier prompt stays a stable prefix for the local provider's KV cache
+        if let Some(pos) = messages.iter().rposition(|m| m.role == ChatRole::User) {
+            let note = self
+                .facts
+                .prompt_note(&messages[pos].content, facts::PROMPT_FACT_LIMIT);
+            if let Some(note) = note {
+                messages.insert(pos, ChatMessage::system(note));
+            }
+        }
+
         // Prepend custom system prompt if set
         let system_prompt = self.system_prompt.lock().clone();
         if let Some(prompt) = system_prompt {
@@ -276,67 +894,109 @@ impl Agent {
         }
 
         // Apply Harmony template if enabled
-        let formatted_messages = if self.config.use_harmony_template {
+        if self.config.use_harmony_template {
             HarmonyTemplate::format_messages(&messages)
         } else {
-            messages.clone()
+            messages
+        }
+    }
+
+    /// Whether the reply goes through the ReAct loop with tool definitions attached
+    fn uses_tools(&self, tools: &dyn ToolAccess) -> bool {
+        self.client.supports_tools() && !tools.is_empty()
+    }
+
+    /// Generate the assistant reply to the conversation in `memory`
+    fn respond(
+        &self,
+        memory: &ConversationMemory,
+        tools: &dyn ToolAccess,
+        turn: &TurnOptions,
+        listener: Option<&dyn StreamListener>,
+    ) -> Result<Reply, AgentError> {
+        let started = Instant::now();
+        let first_token = Cell::new(None);
+        let mut on_delta = |delta: &str| {
+            if first_token.get().is_none() {
+                first_token.set(Some(started.elapsed()));
+            }
+            if let Some(listener) = listener {
+                listener.on_text_delta(delta.to_string());
+            }
         };
 
-        // Use ReAct loop if provider supports tools and tools are registered
-        let (response_text, keywords, reasoning, usage) = if self.client.supports_tools()
-            && !self.tool_registry.is_empty()
-        {
+        let formatted_messages = self.request_messages(memory);
+        let request = &turn.react.request;
+
+        if let Some(ref schema) = turn.json_schema {
+            // Caller-defined structured reply (no tools)
+            let json = self
+                .client
+                .chat_with_schema(&formatted_messages, schema.clone(), "step_response", request)
+                .map_err(AgentError::from_provider)?;
+            if listener.is_some() {
+                on_delta(&json);
+            }
+            Ok(Reply {
+                first_token: first_token.get(),
+                ..Reply::text(json, Vec::new())
+            })
+        } else if self.uses_tools(tools) {
+            // Use ReAct loop if provider supports tools and tools are registered
             // ReAct loop with tool calling
             let mut react_messages = formatted_messages;
-            let (text, reasoning, usage) = react::run(
+            let request_len = react_messages.len();
+            let progress = self.progress.lock().clone();
+            let forwarder = progress.as_deref().map(ProgressForwarder);
+            let observer = forwarder.as_ref().map(|f| f as &dyn react::ReactObserver);
+            let (text, reasoning, usage) = react::run_with_options(
                 self.client.as_ref(),
                 &mut react_messages,
-                &self.tool_registry,
-                None,
+                tools,
+                &turn.react,
+                &self.cancel,
+                observer,
+                listener.map(|_| &mut on_delta as &mut dyn FnMut(&str)),
             )?;
 
-            (text, Vec::new(), reasoning, usage)
+            // The loop appends the calls and results it made after the request messages
+            let trace = react_messages.split_off(request_len);
+            Ok(Reply {
+                text,
+                keywords: Vec::new(),
+                reasoning,
+                usage,
+                trace,
+                first_token: first_token.get(),
+            })
+        } else if listener.is_some() {
+            // Streaming: plain chat so text can be delivered as it is generated
+            let (response, usage) = self
+                .client
+                .chat_streaming(&formatted_messages, request, &mut on_delta)
+                .map_err(AgentError::from_provider)?;
+            Ok(Reply {
+                usage: usage.unwrap_or_default(),
+                first_token: first_token.get(),
+                ..Reply::text(response, Vec::new())
+            })
         } else if self.client.supports_structured_output() {
             // Structured output for keyword extraction (no tools)
             let schema = get_keyword_schema();
             let json_response = self
                 .client
-                .chat_with_schema(&formatted_messages, schema, "conversation_response")
-                .map_err(|e| AgentError::NetworkError(e.to_string()))?;
+                .chat_with_schema(&formatted_messages, schema, "conversation_response", request)
+                .map_err(AgentError::from_provider)?;
             let (text, keywords) = parse_structured_response(&json_response)?;
-            (text, keywords, None, TokenUsage::default())
+            Ok(Reply::text(text, keywords))
         } else {
             // Fallback: regular chat (no keywords, no tools)
             let response = self
                 .client
-                .chat(&formatted_messages)
-                .map_err(|e| AgentError::NetworkError(e.to_string()))?;
-            (response, Vec::new(), None, TokenUsage::default())
-        };
-
-        // Track token usage for compaction decisions
-        self.last_input_tokens.store(usage.input_tokens, Ordering::Relaxed);
-
-        // Add assistant response to memory
-        memory.add_message(ChatMessage::assistant(response_text.clone()));
-
-        let context_percent = if self.config.context_window > 0 {
-            (usage.input_tokens as f64 / self.config.context_window as f64 * 100.0) as f32
-        } else {
-            0.0
-        };
-
-        Ok(AgentResponse {
-            content: response_text,
-            role: "assistant".to_string(),
-            is_final: true,
-            keywords: if keywords.is_empty() { None } else { Some(keywords) },
-            reasoning,
-            input_tokens: usage.input_tokens,
-            output_tokens: usage.output_tokens,
-            total_tokens: usage.total_tokens,
-            context_percent,
-        })
+                .chat(&formatted_messages, request)
+                .map_err(AgentError::from_provider)?;
+            Ok(Reply::text(response, Vec::new()))
+        }
     }
 
     /// Process a backchannel event (audio only, no history pollution)
@@ -353,16 +1013,189 @@ impl Agent {
         None
     }
 
-    /// Reset the conversation memory
+    /// Flag the last reply as interrupted, when the user spoke over it before playback
+    /// finished. Shows up in `get_stats` and exports.
+    pub fn mark_interrupted(&self) {
+        if self.memory.lock().mark_last_reply_interrupted() {
+            self.persist(|store, id| store.append_interrupted(id));
+        }
+    }
+
+    /// Token usage and where the time went (LLM, first token, tools) over the replies in
+    /// memory
+    pub fn get_stats(&self) -> ConversationStats {
+        self.memory.lock().stats()
+    }
+
+    /// Model name for message metadata: the configured model, else the local model file
+    fn model_name(&self) -> String {
+        match self.config.model_path {
+            Some(ref path) if self.config.model.is_empty() => PathBuf::from(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone()),
+            _ => self.config.model.clone(),
+        }
+    }
+
+    /// Remove the last exchange (user message, tool calls and results, reply) and return
+    /// the user's text, e.g. to correct a misheard request
+    pub fn undo_last_turn(&self) -> Result<String, AgentError> {
+        let mut memory = self.memory.lock();
+        let turn = memory
+            .pop_turn()
+            .ok_or_else(|| AgentError::InternalError("No turn to undo".to_string()))?;
+        self.persist(|store, id| store.append_undo(id));
+        tracing::info!("Undid last turn ({} messages)", turn.messages().len());
+        Ok(turn.user_message().content.clone())
+    }
+
+    /// Replace the last user message with `text` and answer it again
+    pub fn edit_last_user_message(&self, text: String) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(Some(text), &StepOptions::default(), None)
+    }
+
+    /// `edit_last_user_message` with per-step settings, e.g. those the turn first ran with
+    pub fn edit_last_user_message_with_options(
+        &self,
+        text: String,
+        options: StepOptions,
+    ) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(Some(text), &options, None)
+    }
+
+    /// `edit_last_user_message_with_options`, delivering response text to `listener`
+    pub fn edit_last_user_message_streaming(
+        &self,
+        text: String,
+        options: StepOptions,
+        listener: Box<dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(Some(text), &options, Some(listener.as_ref()))
+    }
+
+    /// Discard the last reply and answer the same user message again
+    pub fn regenerate_last_response(&self) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(None, &StepOptions::default(), None)
+    }
+
+    /// `regenerate_last_response` with per-step settings, e.g. those the turn first ran with
+    pub fn regenerate_last_response_with_options(
+        &self,
+        options: StepOptions,
+    ) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(None, &options, None)
+    }
+
+    /// `regenerate_last_response_with_options`, delivering response text to `listener`
+    pub fn regenerate_last_response_streaming(
+        &self,
+        options: StepOptions,
+        listener: Box<dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.rerun_last_turn(None, &options, Some(listener.as_ref()))
+    }
+
+    /// Roll back the last turn and run it again with `user_input` (or the original text).
+    /// If the new attempt fails or is cancelled, the rolled-back turn is put back.
+    fn rerun_last_turn(
+        &self,
+        user_input: Option<String>,
+        options: &StepOptions,
+        listener: Option<&dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.with_step_options(options, |tools, turn| {
+            self.rerun_turn(user_input, tools, turn, listener)
+        })
+    }
+
+    /// `rerun_last_turn` with the options resolved
+    fn rerun_turn(
+        &self,
+        user_input: Option<String>,
+        tools: &dyn ToolAccess,
+        turn_options: &TurnOptions,
+        listener: Option<&dyn StreamListener>,
+    ) -> Result<AgentResponse, AgentError> {
+        let mut memory = self.memory.lock();
+        let turn = memory
+            .pop_turn()
+            .ok_or_else(|| AgentError::InternalError("No turn to redo".to_string()))?;
+
+        let user_input = user_input.unwrap_or_else(|| turn.user_message().content.clone());
+        let turn_options = TurnOptions {
+            replaces_last: true,
+            ..turn_options.clone()
+        };
+        let result = self.run_turn(&mut memory, user_input, tools, &turn_options, listener);
+        if let Err(ref e) = result {
+            // A cancelled turn already removed its user message; a failed one left it
+            if !matches!(e, AgentError::Cancelled) {
+                memory.pop_message();
+            }
+            // Nothing was persisted, so the session still holds the previous turn
+            memory.restore_turn(turn);
+            tracing::info!("Redo failed, previous turn restored");
+        }
+        result
+    }
+
+    /// Reset the conversation memory. With persistence enabled the next turn starts a
+    /// new session; the previous one stays on disk.
     pub fn reset(&self) {
         let mut memory = self.memory.lock();
         memory.clear();
+        *self.session_id.lock() = None;
     }
 
-    /// Get the conversation history as JSON string
+    /// Get the conversation history as a versioned JSON document (see `history`),
+    /// including tool calls, tool results and inline images
     pub fn get_conversation_history(&self) -> String {
         let memory = self.memory.lock();
-        serde_json::to_string_pretty(&memory.get_messages()).unwrap_or_default()
+        history::to_json(&memory.get_messages(), None).unwrap_or_default()
+    }
+
+    /// Export the conversation as `markdown`, `json` or `jsonl` (OpenAI fine-tuning).
+    /// With sessions enabled the full session transcript is exported, reasoning included;
+    /// otherwise the messages currently in memory.
+    pub fn export_conversation(&self, format: String) -> Result<String, AgentError> {
+        let format = export::ExportFormat::parse(&format)?;
+        let current = self.session_id.lock().clone();
+        let transcript = match (&self.sessions, current) {
+            (Some(store), Some(id)) => store.transcript(&id)?,
+            _ => export::Transcript {
+                entries: self
+                    .memory
+                    .lock()
+                    .get_messages_with_metadata()
+                    .into_iter()
+                    .map(|(message, metadata)| export::ExportEntry {
+                        message,
+                        metadata: Some(metadata),
+                        reasoning: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        };
+        self.render_export(transcript, format)
+    }
+
+    /// Export a stored session, in the formats of `export_conversation`
+    pub fn export_session(&self, id: String, format: String) -> Result<String, AgentError> {
+        let format = export::ExportFormat::parse(&format)?;
+        let transcript = self.session_store()?.transcript(&id)?;
+        self.render_export(transcript, format)
+    }
+
+    fn render_export(
+        &self,
+        mut transcript: export::Transcript,
+        format: export::ExportFormat,
+    ) -> Result<String, AgentError> {
+        transcript.system_prompt = self.system_prompt.lock().clone();
+        transcript.tools = self.tool_registry.get_definitions();
+        transcript.render(format)
     }
 
     /// Set a custom system prompt for the conversation
@@ -383,87 +1216,11 @@ impl Agent {
         user_input: String,
         allowed_tools: Vec<String>,
     ) -> Result<AgentResponse, AgentError> {
-        let mut memory = self.memory.lock();
-
-        // Compact if last turn approached context window limit (>= 90%)
-        self.maybe_compact(&mut memory);
-
-        // Add user message to memory
-        memory.add_message(ChatMessage::user(user_input.clone()));
-
-        // Get conversation context
-        let mut messages = memory.get_messages();
-
-        // Prepend custom system prompt if set
-        let system_prompt = self.system_prompt.lock().clone();
-        if let Some(prompt) = system_prompt {
-            messages.insert(0, ChatMessage::system(prompt));
-        }
-
-        // Inject skill catalog
-        if let Some(catalog) = self.skill_registry.catalog() {
-            messages.push(ChatMessage::system(catalog));
-        }
-
-        // Apply Harmony template if enabled
-        let formatted_messages = if self.config.use_harmony_template {
-            HarmonyTemplate::format_messages(&messages)
-        } else {
-            messages.clone()
-        };
-
-        // Use ReAct loop with filtered tools
-        let filtered = self.tool_registry.filtered(&allowed_tools);
-        let (response_text, keywords, reasoning, usage) = if self.client.supports_tools()
-            && !filtered.is_empty()
-        {
-            let mut react_messages = formatted_messages;
-            let (text, reasoning, usage) = react::run(
-                self.client.as_ref(),
-                &mut react_messages,
-                &filtered,
-                None,
-            )?;
-            (text, Vec::new(), reasoning, usage)
-        } else if self.client.supports_structured_output() {
-            let schema = get_keyword_schema();
-            let json_response = self
-                .client
-                .chat_with_schema(&formatted_messages, schema, "conversation_response")
-                .map_err(|e| AgentError::NetworkError(e.to_string()))?;
-            let (text, keywords) = parse_structured_response(&json_response)?;
-            (text, keywords, None, TokenUsage::default())
-        } else {
-            let response = self
-                .client
-                .chat(&formatted_messages)
-                .map_err(|e| AgentError::NetworkError(e.to_string()))?;
-            (response, Vec::new(), None, TokenUsage::default())
-        };
-
-        // Track token usage for compaction decisions
-        self.last_input_tokens.store(usage.input_tokens, Ordering::Relaxed);
-
-        // Add assistant response to memory
-        memory.add_message(ChatMessage::assistant(response_text.clone()));
-
-        let context_percent = if self.config.context_window > 0 {
-            (usage.input_tokens as f64 / self.config.context_window as f64 * 100.0) as f32
-        } else {
-            0.0
+        let options = StepOptions {
+            allowed_tools: Some(allowed_tools),
+            ..Default::default()
         };
-
-        Ok(AgentResponse {
-            content: response_text,
-            role: "assistant".to_string(),
-            is_final: true,
-            keywords: if keywords.is_empty() { None } else { Some(keywords) },
-            reasoning,
-            input_tokens: usage.input_tokens,
-            output_tokens: usage.output_tokens,
-            total_tokens: usage.total_tokens,
-            context_percent,
-        })
+        self.step_inner(user_input, &options, None)
     }
 
     /// Feed a watcher event — parses JSON and pushes to the situation stack.
@@ -508,32 +1265,172 @@ impl Agent {
         }
     }
 
-    /// Compact memory if the last turn's input tokens reached >= 90% of context window.
-    /// Targets 50% of context window after compaction to leave room.
-    fn maybe_compact(&self, memory: &mut ConversationMemory) {
-        let last = self.last_input_tokens.load(Ordering::Relaxed);
-        if last == 0 {
+    /// Compact memory if the request about to be sent, plus the reply budget, would reach
+    /// the trigger ratio (90%) of the context window, counted with the provider's tokenizer
+    /// or estimator. Targets the target ratio (50%) of the window after compaction.
+    /// In summarising mode the dropped span is folded into the memory's rolling summary.
+    fn maybe_compact(&self, memory: &mut ConversationMemory, tools: &dyn ToolAccess) {
+        let window = self.config.context_window as usize;
+        if window == 0 {
             return;
         }
-        let threshold = (self.config.context_window as f64 * 0.9) as u64;
-        if last >= threshold {
-            let target = self.config.context_window as usize / 2;
-            let dropped = memory.compact(target);
-            if dropped > 0 {
-                tracing::info!(
-                    "Compacted memory: dropped {} messages (last input: {} tokens, window: {})",
-                    dropped,
-                    last,
-                    self.config.context_window,
-                );
+        let compaction = self.config.compaction.clone().unwrap_or_default();
+        let trigger_ratio = compaction.trigger_ratio.unwrap_or(0.9).clamp(0.05, 1.0);
+        let target_ratio = compaction.target_ratio.unwrap_or(0.5).clamp(0.05, trigger_ratio);
+
+        let tool_defs = if self.uses_tools(tools) {
+            tools.get_definitions()
+        } else {
+            Vec::new()
+        };
+
+        // The reply has to fit in the window too
+        let needed = self
+            .client
+            .count_tokens(&self.request_messages(memory), &tool_defs)
+            + self.config.max_tokens as usize;
+        let threshold = (window as f64 * trigger_ratio as f64) as usize;
+        if needed < threshold {
+            return;
+        }
+
+        // Shrink the history so the whole request lands at the target ratio
+        let count = |m: &ChatMessage| self.client.count_tokens(std::slice::from_ref(m), &[]);
+        let history: usize = memory.get_messages().iter().map(count).sum();
+        let overhead = needed.saturating_sub(history);
+        let target = ((window as f64 * target_ratio as f64) as usize).saturating_sub(overhead);
+        // Compact a copy, so a cancelled summarisation leaves the history as it was
+        let mut compacted = memory.clone();
+        let dropped = compacted.drain_compactable(target, count);
+        if dropped.is_empty() {
+            return;
+        }
+
+        if compaction.summarize {
+            let request = memory::summary_request(memory.summary(), &dropped);
+            let result = self
+                .client
+                .chat(&request, &llm::RequestOptions::default())
+                .map_err(AgentError::from_provider);
+            if matches!(result, Err(AgentError::Cancelled)) {
+                tracing::info!("Summarisation cancelled, memory left uncompacted");
+                return;
+            }
+            *memory = compacted;
+            match result {
+                Ok(summary) if !summary.trim().is_empty() => {
+                    memory.set_summary(summary.trim().to_string());
+                    self.persist(|store, id| {
+                        store.append_compaction(id, dropped.len(), memory.summary())
+                    });
+                    tracing::info!(
+                        "Compacted memory: summarised {} messages (request: {} tokens, window: {})",
+                        dropped.len(),
+                        needed,
+                        window,
+                    );
+                    return;
+                }
+                Ok(_) => tracing::warn!("Summarisation returned nothing, dropping messages"),
+                Err(e) => tracing::warn!("Summarisation failed, dropping messages: {}", e),
             }
+        } else {
+            *memory = compacted;
         }
+        self.persist(|store, id| store.append_compaction(id, dropped.len(), None));
+        tracing::info!(
+            "Compacted memory: dropped {} messages (request: {} tokens, window: {})",
+            dropped.len(),
+            needed,
+            window,
+        );
     }
 
     /// Push a situation message from Swift (e.g. periodic window list).
     pub fn push_situation_message(&self, text: String, source: String, session_id: String) {
         self.situation.push(text, source, session_id);
     }
+
+    /// Stored sessions, most recently updated first
+    pub fn list_sessions(&self) -> Result<Vec<SessionInfo>, AgentError> {
+        self.session_store()?.list()
+    }
+
+    /// Full transcript of a stored session as a JSON array of
+    /// `{timestamp_ms, role, content, usage?}`, without switching to it
+    pub fn load_session(&self, id: String) -> Result<String, AgentError> {
+        let session = self.session_store()?.load(&id)?;
+        serde_json::to_string_pretty(&session.entries)
+            .map_err(|e| AgentError::InternalError(e.to_string()))
+    }
+
+    /// Continue a stored session: its memory replaces the current conversation and
+    /// later turns are appended to it.
+    pub fn resume_session(&self, id: String) -> Result<(), AgentError> {
+        let session = self.session_store()?.load(&id)?;
+        let mut memory = self.memory.lock();
+        *memory = session.memory;
+        *self.session_id.lock() = Some(id);
+        tracing::info!(
+            "Resumed session {} ({} messages)",
+            session.info.id,
+            memory.len()
+        );
+        Ok(())
+    }
+
+    /// Set the title shown in `list_sessions`
+    pub fn rename_session(&self, id: String, title: String) -> Result<(), AgentError> {
+        self.session_store()?.rename(&id, &title)
+    }
+
+    /// Delete a stored session. Deleting the current session also resets the conversation.
+    pub fn delete_session(&self, id: String) -> Result<(), AgentError> {
+        let store = self.session_store()?;
+        let mut memory = self.memory.lock();
+        store.delete(&id)?;
+        let mut current = self.session_id.lock();
+        if current.as_deref() == Some(id.as_str()) {
+            memory.clear();
+            *current = None;
+        }
+        Ok(())
+    }
+
+    /// ID of the session the conversation is being saved to, if one has started
+    pub fn current_session_id(&self) -> Option<String> {
+        self.session_id.lock().clone()
+    }
+
+    fn session_store(&self) -> Result<&SessionStore, AgentError> {
+        self.sessions.as_ref().ok_or_else(|| {
+            AgentError::ConfigError(
+                "Session persistence is disabled (sessions_dir not set)".to_string(),
+            )
+        })
+    }
+
+    /// Append to the current session, starting one first if needed. Does nothing when
+    /// persistence is disabled; write failures are logged and never fail the turn.
+    fn persist(&self, write: impl FnOnce(&SessionStore, &str) -> Result<(), AgentError>) {
+        let Some(ref store) = self.sessions else {
+            return;
+        };
+        let mut current = self.session_id.lock();
+        let id = match current.as_ref() {
+            Some(id) => id.clone(),
+            None => match store.create() {
+                Ok(id) => current.insert(id).clone(),
+                Err(e) => {
+                    tracing::warn!("Failed to start session: {}", e);
+                    return;
+                }
+            },
+        };
+        if let Err(e) = write(store, &id) {
+            tracing::warn!("Failed to save session {}: {}", id, e);
+        }
+    }
 }
 
 /// Extract the last path component for display.
@@ -1,12 +1,16 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::cancel::{CancelToken, CANCEL_POLL_INTERVAL};
+use crate::tokens::TokenEstimator;
+use crate::AgentError;
+
 // ============================================================================
 // Core types
 // ============================================================================
 
 /// Token usage information from an LLM API call
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TokenUsage {
     pub input_tokens: u64,
     pub output_tokens: u64,
@@ -33,7 +37,7 @@ pub enum ChatRole {
 }
 
 /// Image content for multimodal messages
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageContent {
     pub base64: String,
     pub media_type: String, // "image/png", "image/jpeg"
@@ -45,16 +49,16 @@ pub struct ChatMessage {
     pub role: ChatRole,
     pub content: String,
     /// Images attached to this message (for vision models)
-    #[serde(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub images: Vec<ImageContent>,
     /// Tool calls made by assistant (set by ReAct loop)
-    #[serde(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCallInfo>>,
     /// Tool call ID this message is responding to (for role=Tool)
-    #[serde(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
     /// Tool name this message is responding to (for role=Tool)
-    #[serde(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tool_name: Option<String>,
 }
 
@@ -131,8 +135,67 @@ impl ChatMessage {
     }
 }
 
+/// Sampling parameters for the local provider. Unset fields use llama.cpp's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct SamplingConfig {
+    /// Keep only the k most likely tokens (0 disables)
+    pub top_k: Option<i32>,
+    /// Nucleus sampling: keep tokens up to this cumulative probability (1.0 disables)
+    pub top_p: Option<f32>,
+    /// Drop tokens less likely than this fraction of the top token (0.0 disables)
+    pub min_p: Option<f32>,
+    /// Multiplicative penalty for recently seen tokens (1.0 disables)
+    pub repeat_penalty: Option<f32>,
+    /// Additive penalty for tokens already present (0.0 disables)
+    pub presence_penalty: Option<f32>,
+    /// Additive penalty scaled by occurrence count (0.0 disables)
+    pub frequency_penalty: Option<f32>,
+    /// Number of recent tokens the repetition penalties look at
+    pub penalty_last_n: Option<i32>,
+    /// DRY ("don't repeat yourself") penalty strength (0.0 disables)
+    pub dry_multiplier: Option<f32>,
+    pub dry_base: Option<f32>,
+    pub dry_allowed_length: Option<i32>,
+    /// Number of recent tokens DRY scans (-1 = whole context)
+    pub dry_penalty_last_n: Option<i32>,
+    /// Fixed RNG seed for reproducible output; `None` picks a random seed
+    pub seed: Option<u32>,
+}
+
+/// Runtime options for the local provider. Unset fields use the defaults noted below.
+#[derive(Debug, Clone, Default)]
+pub struct LocalRuntimeConfig {
+    /// Context size in tokens (default 8192, capped at the model's `n_ctx_train`)
+    pub n_ctx: Option<u32>,
+    /// Layers offloaded to the GPU (default: all)
+    pub gpu_layers: Option<u32>,
+    /// Run entirely on the CPU: no layer or KV offload (e.g. Linux servers without a GPU)
+    pub cpu_only: bool,
+    /// Threads used for generation (default: llama.cpp's)
+    pub n_threads: Option<i32>,
+    /// Threads used for prompt processing (default: same as generation)
+    pub n_threads_batch: Option<i32>,
+    /// Prompt tokens decoded per batch (default 512); cancellation is checked between batches
+    pub n_batch: Option<u32>,
+    /// Force flash attention on or off (default: llama.cpp decides)
+    pub flash_attention: Option<bool>,
+    /// KV cache element type: "f32", "f16" (default), "bf16", "q8_0", "q5_1", "q5_0",
+    /// "q4_1", "q4_0" or "iq4_nl". Quantized V caches need flash attention.
+    pub kv_cache_type: Option<String>,
+    /// Jinja chat template file overriding the one embedded in the GGUF
+    pub chat_template_path: Option<String>,
+    /// Multimodal projector GGUF (mmproj) enabling image input for vision models
+    pub mmproj_path: Option<String>,
+    /// Let templates that support it (e.g. Qwen3) emit a `<think>` block before answering.
+    /// Its content is returned as reasoning and never streamed.
+    pub enable_thinking: bool,
+    /// Thinking tokens allowed before the block is force-closed; these do not count
+    /// against `max_tokens` (default: `max_tokens`)
+    pub thinking_budget: Option<u32>,
+}
+
 /// Tool definition for LLM
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ToolDefinition {
     pub name: String,
     pub description: String,
@@ -140,13 +203,50 @@ pub struct ToolDefinition {
 }
 
 /// Tool call info returned by LLM
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ToolCallInfo {
     pub id: String,
     pub name: String,
     pub arguments: serde_json::Value,
 }
 
+/// Whether the model may, must or must not call tools
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model decides
+    #[default]
+    Auto,
+    /// Answer in text, no tool calls
+    None,
+    /// Call at least one tool
+    Required,
+    /// Call the named tool
+    Tool(String),
+}
+
+impl ToolChoice {
+    /// "auto", "none", "required", or a tool name
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "auto" | "" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" | "any" => ToolChoice::Required,
+            name => ToolChoice::Tool(name.to_string()),
+        }
+    }
+}
+
+/// Overrides of the provider's configured settings for one request. Unset fields keep the
+/// provider's own values.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub reasoning_effort: Option<String>,
+    /// Only applies to requests with tools
+    pub tool_choice: ToolChoice,
+}
+
 /// LLM response — either text or tool calls
 #[derive(Debug)]
 pub enum LlmResponse {
@@ -162,10 +262,11 @@ pub enum LlmResponse {
 // LlmProvider trait
 // ============================================================================
 
-/// Trait for LLM providers
+/// Trait for LLM providers. Every request takes `RequestOptions`; providers ignore the
+/// overrides they cannot apply.
 pub trait LlmProvider: Send + Sync {
     /// Send a chat completion request
-    fn chat(&self, messages: &[ChatMessage]) -> Result<String>;
+    fn chat(&self, messages: &[ChatMessage], options: &RequestOptions) -> Result<String>;
 
     /// Send a chat completion request with JSON Schema for structured output
     fn chat_with_schema(
@@ -173,11 +274,12 @@ pub trait LlmProvider: Send + Sync {
         messages: &[ChatMessage],
         _schema: serde_json::Value,
         _schema_name: &str,
+        options: &RequestOptions,
     ) -> Result<String> {
         tracing::warn!(
             "chat_with_schema not supported by this provider, falling back to regular chat"
         );
-        self.chat(messages)
+        self.chat(messages, options)
     }
 
     /// Send a chat request with tool definitions, returning either text or tool calls
@@ -185,10 +287,58 @@ pub trait LlmProvider: Send + Sync {
         &self,
         _messages: &[ChatMessage],
         _tools: &[ToolDefinition],
+        _options: &RequestOptions,
     ) -> Result<LlmResponse> {
         Err(anyhow::anyhow!("Tool calling not supported by this provider"))
     }
 
+    /// Streaming variant of `chat`: `on_delta` receives text fragments as they are generated.
+    /// Returns the full text and, where the provider reports it, the token usage. The default
+    /// implementation delivers the whole response as a single delta, without usage.
+    fn chat_streaming(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let text = self.chat(messages, options)?;
+        on_delta(&text);
+        Ok((text, None))
+    }
+
+    /// Streaming variant of `chat_with_tools`: text deltas are passed to `on_delta` as they arrive.
+    /// Tool calls are only returned once complete. The default implementation delivers the
+    /// final text as a single delta.
+    fn chat_with_tools_streaming(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<LlmResponse> {
+        let response = self.chat_with_tools(messages, tools, options)?;
+        if let LlmResponse::Text { ref content, .. } = response {
+            on_delta(content);
+        }
+        Ok(response)
+    }
+
+    /// Install the token that aborts in-flight requests. A cancelled request fails with
+    /// `AgentError::Cancelled`. Providers that cannot be interrupted ignore it.
+    fn set_cancel_token(&self, _token: CancelToken) {}
+
+    /// Prompt tokens a request with these messages and tools would use. The default is a
+    /// generic estimate; providers refine it with their tokenizer or model family.
+    fn count_tokens(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        TokenEstimator::default().count_request(messages, tools)
+    }
+
+    /// Context size the provider actually runs with, when it is fixed on our side
+    /// (local models). `None` means the server decides.
+    fn context_size(&self) -> Option<u32> {
+        None
+    }
+
     /// Check if this provider supports structured output
     fn supports_structured_output(&self) -> bool {
         false
@@ -200,6 +350,182 @@ pub trait LlmProvider: Send + Sync {
     }
 }
 
+// ============================================================================
+// HTTP helpers
+// ============================================================================
+
+/// Build TLS connector with custom CA certificates
+fn build_tls_with_custom_ca(cert_file: &str) -> Result<native_tls::TlsConnector> {
+    use std::fs::File;
+    use std::io::Read;
+
+    // Read certificate file
+    let mut file = File::open(cert_file)
+        .map_err(|e| anyhow::anyhow!("Failed to open certificate file: {}", e))?;
+    let mut cert_data = Vec::new();
+    file.read_to_end(&mut cert_data)
+        .map_err(|e| anyhow::anyhow!("Failed to read certificate file: {}", e))?;
+
+    // Parse certificate(s) - PEM format can contain multiple certificates
+    let mut builder = native_tls::TlsConnector::builder();
+
+    // Try to parse as PEM (most common format)
+    let cert_str = String::from_utf8_lossy(&cert_data);
+    let mut found_cert = false;
+
+    // Split by PEM boundaries
+    for pem_block in cert_str.split("-----END CERTIFICATE-----") {
+        if let Some(cert_start) = pem_block.find("-----BEGIN CERTIFICATE-----") {
+            let pem_cert = format!("{}-----END CERTIFICATE-----", &pem_block[cert_start..]);
+
+            match native_tls::Certificate::from_pem(pem_cert.as_bytes()) {
+                Ok(cert) => {
+                    builder.add_root_certificate(cert);
+                    found_cert = true;
+                    tracing::debug!("Added certificate from PEM");
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse PEM certificate: {}", e);
+                }
+            }
+        }
+    }
+
+    if !found_cert {
+        // Try DER format as fallback
+        match native_tls::Certificate::from_der(&cert_data) {
+            Ok(cert) => {
+                builder.add_root_certificate(cert);
+                tracing::debug!("Added certificate from DER");
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!("No valid certificates found in file: {}", e));
+            }
+        }
+    }
+
+    builder.build()
+        .map_err(|e| anyhow::anyhow!("Failed to build TLS connector: {}", e))
+}
+
+/// Build the shared blocking HTTP agent, honouring `SSL_CERT_FILE` for custom CA certificates
+fn build_http_agent() -> ureq::Agent {
+    if let Ok(cert_file) = std::env::var("SSL_CERT_FILE") {
+        tracing::info!("Loading custom CA certificates from: {}", cert_file);
+        match build_tls_with_custom_ca(&cert_file) {
+            Ok(tls) => {
+                tracing::info!("Custom CA certificates loaded successfully");
+                ureq::AgentBuilder::new()
+                    .tls_connector(std::sync::Arc::new(tls))
+                    .build()
+            }
+            Err(e) => {
+                tracing::error!("Failed to load custom CA certificates: {}", e);
+                tracing::warn!("Falling back to default TLS configuration");
+                ureq::agent()
+            }
+        }
+    } else {
+        ureq::agent()
+    }
+}
+
+/// Join an API base URL (e.g. "https://api.openai.com/v1") with an endpoint path
+fn endpoint_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+/// Send a JSON POST on a helper thread and apply `read` to the response there, so a request
+/// blocked on the network can be abandoned when `cancel` fires. HTTP error statuses are
+/// reported as "<api> API error <code>: <body>".
+fn send_cancellable<T: Send + 'static>(
+    request: ureq::Request,
+    body: serde_json::Value,
+    api: &'static str,
+    cancel: &CancelToken,
+    read: impl FnOnce(ureq::Response) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    cancel.check()?;
+
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    std::thread::spawn(move || {
+        let result = match request.send_json(body) {
+            Ok(resp) => read(resp),
+            Err(ureq::Error::Status(code, resp)) => {
+                let error_body = resp
+                    .into_string()
+                    .unwrap_or_else(|_| "Unable to read error body".to_string());
+                tracing::error!("{} API error (status {}): {}", api, code, error_body);
+                Err(anyhow::anyhow!("{} API error {}: {}", api, code, error_body))
+            }
+            Err(e) => Err(e.into()),
+        };
+        // The receiver is gone if the request was cancelled
+        let _ = tx.send(result);
+    });
+
+    loop {
+        match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(result) => return result,
+            Err(crossbeam::channel::RecvTimeoutError::Timeout) => cancel.check()?,
+            Err(crossbeam::channel::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("{} request thread exited without a result", api)
+            }
+        }
+    }
+}
+
+/// Response body reader that fails once `cancel` fires, dropping the connection so the
+/// server stops generating.
+struct CancelReader<R> {
+    inner: R,
+    cancel: CancelToken,
+}
+
+impl<R: std::io::Read> std::io::Read for CancelReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(std::io::Error::other("request cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Wrap a streaming response body so reading stops once `cancel` fires
+fn cancellable_reader(
+    resp: ureq::Response,
+    cancel: &CancelToken,
+) -> std::io::BufReader<CancelReader<impl std::io::Read>> {
+    std::io::BufReader::new(CancelReader {
+        inner: resp.into_reader(),
+        cancel: cancel.clone(),
+    })
+}
+
+/// Report a failed stream as cancelled when that is why it failed
+fn stream_error(e: anyhow::Error, cancel: &CancelToken) -> anyhow::Error {
+    if cancel.is_cancelled() {
+        AgentError::Cancelled.into()
+    } else {
+        e
+    }
+}
+
+/// Read `data:` payloads from a server-sent event stream, stopping at `[DONE]`
+fn sse_data_lines(reader: impl std::io::BufRead) -> impl Iterator<Item = Result<String>> {
+    reader
+        .lines()
+        .filter_map(|line| match line {
+            Ok(line) => {
+                // "event:" lines and keep-alive blank lines carry no payload
+                let data = line.strip_prefix("data:")?.trim().to_string();
+                (!data.is_empty()).then_some(Ok(data))
+            }
+            Err(e) => Some(Err(e.into())),
+        })
+        .take_while(|data| !matches!(data, Ok(d) if d == "[DONE]"))
+}
+
 // ============================================================================
 // OpenAI Provider (cloud API) — Responses API
 // ============================================================================
@@ -255,9 +581,13 @@ struct ResponsesRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<ResponsesTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     text: Option<ResponseTextFormat>,
     #[serde(skip_serializing_if = "Option::is_none")]
     reasoning: Option<ReasoningParam>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
 }
 
 /// Text format specification for structured output
@@ -300,6 +630,19 @@ struct IncompleteDetails {
     reason: String,
 }
 
+/// Server-sent event from a streaming Responses API request
+#[derive(Debug, Deserialize)]
+struct ResponsesStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    delta: Option<String>,
+    #[serde(default)]
+    response: Option<serde_json::Value>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct ResponseOutput {
     #[serde(rename = "type")]
@@ -334,70 +677,19 @@ struct ResponseContent {
 }
 
 pub struct OpenAiProvider {
+    base_url: String,
     api_key: String,
     model: String,
     temperature: Option<f32>,
     max_tokens: u32,
     reasoning_effort: Option<String>,
     http_agent: ureq::Agent,
+    cancel: parking_lot::Mutex<CancelToken>,
 }
 
 impl OpenAiProvider {
-    /// Build TLS connector with custom CA certificates
-    fn build_tls_with_custom_ca(cert_file: &str) -> Result<native_tls::TlsConnector> {
-        use std::fs::File;
-        use std::io::Read;
-
-        // Read certificate file
-        let mut file = File::open(cert_file)
-            .map_err(|e| anyhow::anyhow!("Failed to open certificate file: {}", e))?;
-        let mut cert_data = Vec::new();
-        file.read_to_end(&mut cert_data)
-            .map_err(|e| anyhow::anyhow!("Failed to read certificate file: {}", e))?;
-
-        // Parse certificate(s) - PEM format can contain multiple certificates
-        let mut builder = native_tls::TlsConnector::builder();
-
-        // Try to parse as PEM (most common format)
-        let cert_str = String::from_utf8_lossy(&cert_data);
-        let mut found_cert = false;
-
-        // Split by PEM boundaries
-        for pem_block in cert_str.split("-----END CERTIFICATE-----") {
-            if let Some(cert_start) = pem_block.find("-----BEGIN CERTIFICATE-----") {
-                let pem_cert = format!("{}-----END CERTIFICATE-----", &pem_block[cert_start..]);
-
-                match native_tls::Certificate::from_pem(pem_cert.as_bytes()) {
-                    Ok(cert) => {
-                        builder.add_root_certificate(cert);
-                        found_cert = true;
-                        tracing::debug!("Added certificate from PEM");
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to parse PEM certificate: {}", e);
-                    }
-                }
-            }
-        }
-
-        if !found_cert {
-            // Try DER format as fallback
-            match native_tls::Certificate::from_der(&cert_data) {
-                Ok(cert) => {
-                    builder.add_root_certificate(cert);
-                    tracing::debug!("Added certificate from DER");
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!("No valid certificates found in file: {}", e));
-                }
-            }
-        }
-
-        builder.build()
-            .map_err(|e| anyhow::anyhow!("Failed to build TLS connector: {}", e))
-    }
-
     pub fn new(
+        base_url: String,
         api_key: String,
         model: String,
         temperature: Option<f32>,
@@ -405,46 +697,63 @@ impl OpenAiProvider {
         reasoning_effort: Option<String>,
     ) -> Self {
         tracing::info!("Initializing OpenAI provider (Responses API)");
+        tracing::info!("  Base URL: {}", base_url);
         tracing::info!("  Model: {}", model);
         tracing::info!("  Reasoning effort: {:?}", reasoning_effort);
 
-        let http_agent = if let Ok(cert_file) = std::env::var("SSL_CERT_FILE") {
-            tracing::info!("Loading custom CA certificates from: {}", cert_file);
-            match Self::build_tls_with_custom_ca(&cert_file) {
-                Ok(tls) => {
-                    tracing::info!("Custom CA certificates loaded successfully");
-                    ureq::AgentBuilder::new()
-                        .tls_connector(std::sync::Arc::new(tls))
-                        .build()
-                }
-                Err(e) => {
-                    tracing::error!("Failed to load custom CA certificates: {}", e);
-                    tracing::warn!("Falling back to default TLS configuration");
-                    ureq::agent()
-                }
-            }
-        } else {
-            ureq::agent()
-        };
+        let http_agent = build_http_agent();
 
         Self {
+            base_url,
             api_key,
             model,
             temperature,
             max_tokens,
             reasoning_effort,
             http_agent,
+            cancel: parking_lot::Mutex::new(CancelToken::new()),
         }
     }
 
     /// Build reasoning param if configured
-    fn reasoning_param(&self) -> Option<ReasoningParam> {
-        self.reasoning_effort.as_ref().map(|effort| ReasoningParam {
+    fn reasoning_param(&self, options: &RequestOptions) -> Option<ReasoningParam> {
+        let effort = options.reasoning_effort.as_ref().or(self.reasoning_effort.as_ref());
+        effort.map(|effort| ReasoningParam {
             effort: effort.clone(),
             summary: "auto".to_string(),
         })
     }
 
+    /// Build a request without tools, with the configured settings and `options` applied
+    fn request(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        stream: Option<bool>,
+    ) -> ResponsesRequest {
+        ResponsesRequest {
+            model: options.model.clone().unwrap_or_else(|| self.model.clone()),
+            input: Self::convert_to_input_items(messages),
+            temperature: options.temperature.or(self.temperature),
+            max_output_tokens: Some(self.max_tokens),
+            tools: None,
+            tool_choice: None,
+            text: None,
+            reasoning: self.reasoning_param(options),
+            stream,
+        }
+    }
+
+    /// Wire form of a non-default tool choice
+    fn tool_choice(choice: &ToolChoice) -> Option<serde_json::Value> {
+        match choice {
+            ToolChoice::Auto => None,
+            ToolChoice::None => Some(serde_json::json!("none")),
+            ToolChoice::Required => Some(serde_json::json!("required")),
+            ToolChoice::Tool(name) => Some(serde_json::json!({"type": "function", "name": name})),
+        }
+    }
+
     /// Convert ChatMessages to Responses API input items
     fn convert_to_input_items(messages: &[ChatMessage]) -> Vec<ResponsesInputItem> {
         messages
@@ -541,44 +850,106 @@ impl OpenAiProvider {
             .collect()
     }
 
-    /// Send request and parse response
-    fn send_request(&self, request: &ResponsesRequest) -> Result<ResponsesResponse> {
-        let url = "https://api.openai.com/v1/responses";
+    /// POST a request to the Responses API, applying `read` to the response
+    fn post<T: Send + 'static>(
+        &self,
+        request: &ResponsesRequest,
+        read: impl FnOnce(ureq::Response) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let url = endpoint_url(&self.base_url, "responses");
         let auth_header = format!("Bearer {}", self.api_key);
 
-        tracing::debug!("Sending request to OpenAI Responses API");
+        tracing::debug!("Sending request to OpenAI Responses API at {}", url);
         tracing::debug!("Model: {}", self.model);
 
-        let response_result = self.http_agent.post(url)
+        let req = self.http_agent.post(&url)
             .set("Content-Type", "application/json")
-            .set("Authorization", &auth_header)
-            .send_json(request);
-
-        let response: ResponsesResponse = match response_result {
-            Ok(resp) => {
-                let body = resp.into_string()?;
-                tracing::debug!("Raw OpenAI response: {}", body);
-                serde_json::from_str(&body).map_err(|e| {
-                    tracing::error!("Failed to parse OpenAI response: {}", e);
-                    tracing::error!("Response body: {}", body);
-                    anyhow::anyhow!("Failed to read JSON: {}", e)
-                })?
-            }
-            Err(ureq::Error::Status(code, resp)) => {
-                let error_body = resp
-                    .into_string()
-                    .unwrap_or_else(|_| "Unable to read error body".to_string());
-                tracing::error!("OpenAI API error (status {}): {}", code, error_body);
-                return Err(anyhow::anyhow!(
-                    "OpenAI API error {}: {}",
-                    code,
-                    error_body
-                ));
+            .set("Authorization", &auth_header);
+
+        let cancel = self.cancel.lock().clone();
+        send_cancellable(req, serde_json::to_value(request)?, "OpenAI", &cancel, read)
+    }
+
+    /// Send request and parse response
+    fn send_request(&self, request: &ResponsesRequest) -> Result<ResponsesResponse> {
+        let body = self.post(request, |resp| Ok(resp.into_string()?))?;
+        tracing::debug!("Raw OpenAI response: {}", body);
+        let response: ResponsesResponse = serde_json::from_str(&body).map_err(|e| {
+            tracing::error!("Failed to parse OpenAI response: {}", e);
+            tracing::error!("Response body: {}", body);
+            anyhow::anyhow!("Failed to read JSON: {}", e)
+        })?;
+
+        Self::check_complete(response)
+    }
+
+    /// Send a streaming request, forwarding output text deltas to `on_delta`.
+    /// Returns the final response object carried by the terminal event.
+    fn send_request_streaming(
+        &self,
+        request: &ResponsesRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<ResponsesResponse> {
+        let resp = self.post(request, Ok)?;
+        let cancel = self.cancel.lock().clone();
+        let response = Self::read_event_stream(cancellable_reader(resp, &cancel), on_delta)
+            .map_err(|e| stream_error(e, &cancel))?;
+        Self::check_complete(response)
+    }
+
+    /// Parse a Responses API server-sent event stream.
+    fn read_event_stream(
+        reader: impl std::io::BufRead,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<ResponsesResponse> {
+        for data in sse_data_lines(reader) {
+            let data = data?;
+            let event: ResponsesStreamEvent = match serde_json::from_str(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Skipping unparseable stream event: {} ({})", data, e);
+                    continue;
+                }
+            };
+
+            match event.event_type.as_str() {
+                "response.output_text.delta" => {
+                    if let Some(ref delta) = event.delta {
+                        on_delta(delta);
+                    }
+                }
+                "response.completed" | "response.incomplete" => {
+                    let body = event
+                        .response
+                        .ok_or_else(|| anyhow::anyhow!("Stream event missing response object"))?;
+                    tracing::debug!("Final streamed OpenAI response: {}", body);
+                    return serde_json::from_value(body)
+                        .map_err(|e| anyhow::anyhow!("Failed to read JSON: {}", e));
+                }
+                "response.failed" => {
+                    let message = event
+                        .response
+                        .as_ref()
+                        .and_then(|r| r["error"]["message"].as_str())
+                        .unwrap_or("unknown error")
+                        .to_string();
+                    return Err(anyhow::anyhow!("OpenAI response failed: {}", message));
+                }
+                "error" => {
+                    return Err(anyhow::anyhow!(
+                        "OpenAI stream error: {}",
+                        event.message.unwrap_or_else(|| "unknown error".to_string())
+                    ));
+                }
+                _ => {}
             }
-            Err(e) => return Err(e.into()),
-        };
+        }
+
+        Err(anyhow::anyhow!("Stream ended without a completed response"))
+    }
 
-        // Check if response is complete
+    /// Reject responses that stopped early (e.g. hit max_output_tokens)
+    fn check_complete(response: ResponsesResponse) -> Result<ResponsesResponse> {
         if response.status == "incomplete" {
             let reason = response
                 .incomplete_details
@@ -662,6 +1033,57 @@ impl OpenAiProvider {
         })
     }
 
+    /// Build a tool-calling request
+    fn tools_request(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        stream: Option<bool>,
+    ) -> ResponsesRequest {
+        let wire_tools = Self::convert_tools(tools);
+        let mut request = self.request(messages, options, stream);
+        if !wire_tools.is_empty() {
+            request.tools = Some(wire_tools);
+            request.tool_choice = Self::tool_choice(&options.tool_choice);
+        }
+        request
+    }
+
+    /// Interpret a tool-calling response as either tool calls or text
+    fn into_llm_response(response: ResponsesResponse) -> Result<LlmResponse> {
+        let usage = Self::convert_usage(&response.usage);
+
+        if let Some(ref u) = usage {
+            tracing::info!(
+                "Token usage: input={}, output={}, total={}",
+                u.input_tokens, u.output_tokens, u.total_tokens
+            );
+        }
+
+        // Check for tool calls first
+        let tool_calls = Self::extract_tool_calls(&response.output);
+        if !tool_calls.is_empty() {
+            tracing::info!("OpenAI returned {} tool calls", tool_calls.len());
+            return Ok(LlmResponse::ToolCalls(tool_calls, usage));
+        }
+
+        // Text response
+        let text = Self::extract_text(&response.output)
+            .ok_or_else(|| anyhow::anyhow!("No text content or tool calls in response"))?;
+        let reasoning = Self::extract_reasoning(&response.output);
+        tracing::debug!(
+            "Response output types: {:?}",
+            response.output.iter().map(|o| &o.output_type).collect::<Vec<_>>()
+        );
+
+        Ok(LlmResponse::Text {
+            content: text,
+            reasoning,
+            usage,
+        })
+    }
+
     /// Extract tool calls from response output
     fn extract_tool_calls(output: &[ResponseOutput]) -> Vec<ToolCallInfo> {
         output
@@ -685,6 +1107,14 @@ impl OpenAiProvider {
 }
 
 impl LlmProvider for OpenAiProvider {
+    fn set_cancel_token(&self, token: CancelToken) {
+        *self.cancel.lock() = token;
+    }
+
+    fn count_tokens(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        TokenEstimator::for_model(&self.model).count_request(messages, tools)
+    }
+
     fn supports_structured_output(&self) -> bool {
         true
     }
@@ -693,18 +1123,8 @@ impl LlmProvider for OpenAiProvider {
         true
     }
 
-    fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
-        let input = Self::convert_to_input_items(messages);
-
-        let request = ResponsesRequest {
-            model: self.model.clone(),
-            input,
-            temperature: self.temperature,
-            max_output_tokens: Some(self.max_tokens),
-            tools: None,
-            text: None,
-            reasoning: self.reasoning_param(),
-        };
+    fn chat(&self, messages: &[ChatMessage], options: &RequestOptions) -> Result<String> {
+        let request = self.request(messages, options, None);
 
         let response = self.send_request(&request)?;
 
@@ -717,25 +1137,17 @@ impl LlmProvider for OpenAiProvider {
         messages: &[ChatMessage],
         schema: serde_json::Value,
         schema_name: &str,
+        options: &RequestOptions,
     ) -> Result<String> {
-        let input = Self::convert_to_input_items(messages);
-
-        let request = ResponsesRequest {
-            model: self.model.clone(),
-            input,
-            temperature: self.temperature,
-            max_output_tokens: Some(self.max_tokens),
-            tools: None,
-            text: Some(ResponseTextFormat {
-                format: ResponseFormatSpec {
-                    format_type: "json_schema".to_string(),
-                    name: schema_name.to_string(),
-                    schema,
-                    strict: true,
-                },
-            }),
-            reasoning: self.reasoning_param(),
-        };
+        let mut request = self.request(messages, options, None);
+        request.text = Some(ResponseTextFormat {
+            format: ResponseFormatSpec {
+                format_type: "json_schema".to_string(),
+                name: schema_name.to_string(),
+                schema,
+                strict: true,
+            },
+        });
 
         tracing::debug!("Sending request to OpenAI Responses API with JSON Schema");
 
@@ -749,112 +1161,1397 @@ impl LlmProvider for OpenAiProvider {
         &self,
         messages: &[ChatMessage],
         tools: &[ToolDefinition],
+        options: &RequestOptions,
     ) -> Result<LlmResponse> {
-        let input = Self::convert_to_input_items(messages);
-        let wire_tools = Self::convert_tools(tools);
-
-        let request = ResponsesRequest {
-            model: self.model.clone(),
-            input,
-            temperature: self.temperature,
-            max_output_tokens: Some(self.max_tokens),
-            tools: if wire_tools.is_empty() {
-                None
-            } else {
-                Some(wire_tools)
-            },
-            text: None,
-            reasoning: self.reasoning_param(),
-        };
+        let request = self.tools_request(messages, tools, options, None);
 
         tracing::debug!("Sending chat_with_tools request to OpenAI Responses API");
 
         let response = self.send_request(&request)?;
-        let usage = Self::convert_usage(&response.usage);
+        Self::into_llm_response(response)
+    }
 
-        if let Some(ref u) = usage {
-            tracing::info!(
-                "Token usage: input={}, output={}, total={}",
-                u.input_tokens, u.output_tokens, u.total_tokens
-            );
-        }
+    fn chat_streaming(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let request = self.request(messages, options, Some(true));
 
-        // Check for tool calls first
-        let tool_calls = Self::extract_tool_calls(&response.output);
-        if !tool_calls.is_empty() {
-            tracing::info!("OpenAI returned {} tool calls", tool_calls.len());
-            return Ok(LlmResponse::ToolCalls(tool_calls, usage));
-        }
+        let response = self.send_request_streaming(&request, on_delta)?;
 
-        // Text response
         let text = Self::extract_text(&response.output)
-            .ok_or_else(|| anyhow::anyhow!("No text content or tool calls in response"))?;
-        let reasoning = Self::extract_reasoning(&response.output);
-        tracing::debug!(
-            "Response output types: {:?}",
-            response.output.iter().map(|o| &o.output_type).collect::<Vec<_>>()
-        );
+            .ok_or_else(|| anyhow::anyhow!("No text content in response"))?;
+        Ok((text, Self::convert_usage(&response.usage)))
+    }
 
-        Ok(LlmResponse::Text {
-            content: text,
-            reasoning,
-            usage,
+    fn chat_with_tools_streaming(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<LlmResponse> {
+        let request = self.tools_request(messages, tools, options, Some(true));
+
+        tracing::debug!("Sending streaming chat_with_tools request to OpenAI Responses API");
+
+        let response = self.send_request_streaming(&request, on_delta)?;
+        Self::into_llm_response(response)
+    }
+}
+
+// ============================================================================
+// OpenAI-compatible Provider — Chat Completions API
+// ============================================================================
+//
+// Speaks `/chat/completions` against any base URL: llama-server, vLLM, LM Studio,
+// Ollama, internal gateways, or OpenAI itself.
+
+// -- Wire format types for Chat Completions API --
+
+/// Chat Completions request
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+}
+
+/// Chat Completions response
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<ChatCompletionChoice>,
+    #[serde(default)]
+    usage: Option<ChatCompletionUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChatCompletionMessage {
+    #[serde(default)]
+    content: Option<String>,
+    /// Thinking output (llama-server, vLLM and DeepSeek-style servers)
+    #[serde(default)]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ChatCompletionToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionToolCall {
+    #[serde(default)]
+    id: String,
+    function: ChatCompletionFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionFunction {
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+/// Token usage from the Chat Completions API
+#[derive(Debug, Deserialize)]
+struct ChatCompletionUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    total_tokens: u64,
+}
+
+/// Streaming chunk from the Chat Completions API
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChatCompletionChunkChoice>,
+    #[serde(default)]
+    usage: Option<ChatCompletionUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunkChoice {
+    #[serde(default)]
+    delta: ChatCompletionDelta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChatCompletionDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ChatCompletionToolCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<ChatCompletionFunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionFunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+pub struct OpenAiCompatProvider {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: u32,
+    reasoning_effort: Option<String>,
+    http_agent: ureq::Agent,
+    cancel: parking_lot::Mutex<CancelToken>,
+}
+
+impl OpenAiCompatProvider {
+    pub fn new(
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+        temperature: Option<f32>,
+        max_tokens: u32,
+        reasoning_effort: Option<String>,
+    ) -> Self {
+        tracing::info!("Initializing OpenAI-compatible provider (Chat Completions API)");
+        tracing::info!("  Base URL: {}", base_url);
+        tracing::info!("  Model: {}", model);
+
+        Self {
+            base_url,
+            api_key,
+            model,
+            temperature,
+            max_tokens,
+            reasoning_effort,
+            http_agent: build_http_agent(),
+            cancel: parking_lot::Mutex::new(CancelToken::new()),
+        }
+    }
+
+    /// Build content for a message: plain string, or text + image parts when images are attached
+    fn message_content(text: &str, images: &[ImageContent]) -> serde_json::Value {
+        if images.is_empty() {
+            return serde_json::Value::String(text.to_string());
+        }
+        let mut parts = vec![serde_json::json!({
+            "type": "text",
+            "text": text,
+        })];
+        for img in images {
+            parts.push(serde_json::json!({
+                "type": "image_url",
+                "image_url": {
+                    "url": format!("data:{};base64,{}", img.media_type, img.base64),
+                },
+            }));
+        }
+        serde_json::Value::Array(parts)
+    }
+
+    /// Convert ChatMessages to Chat Completions messages
+    pub(crate) fn convert_messages(messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+        messages
+            .iter()
+            .flat_map(|msg| {
+                // Assistant with tool calls
+                if let Some(ref calls) = msg.tool_calls {
+                    let tool_calls: Vec<serde_json::Value> = calls
+                        .iter()
+                        .map(|c| {
+                            serde_json::json!({
+                                "id": c.id,
+                                "type": "function",
+                                "function": {
+                                    "name": c.name,
+                                    "arguments": serde_json::to_string(&c.arguments).unwrap_or_default(),
+                                }
+                            })
+                        })
+                        .collect();
+                    return vec![serde_json::json!({
+                        "role": "assistant",
+                        "content": if msg.content.is_empty() { serde_json::Value::Null } else { serde_json::Value::String(msg.content.clone()) },
+                        "tool_calls": tool_calls,
+                    })];
+                }
+
+                // Tool result — content must be a string on most servers, so images
+                // are sent as a follow-up user message.
+                if let Some(ref call_id) = msg.tool_call_id {
+                    let mut items = vec![serde_json::json!({
+                        "role": "tool",
+                        "tool_call_id": call_id,
+                        "content": msg.content,
+                    })];
+                    if !msg.images.is_empty() {
+                        let label = format!(
+                            "[Screenshot from tool '{}']",
+                            msg.tool_name.as_deref().unwrap_or("unknown")
+                        );
+                        items.push(serde_json::json!({
+                            "role": "user",
+                            "content": Self::message_content(&label, &msg.images),
+                        }));
+                    }
+                    return items;
+                }
+
+                let role = match msg.role {
+                    ChatRole::System => "system",
+                    ChatRole::User => "user",
+                    ChatRole::Assistant => "assistant",
+                    ChatRole::Tool => return vec![], // Handled above via tool_call_id
+                };
+
+                vec![serde_json::json!({
+                    "role": role,
+                    "content": Self::message_content(&msg.content, &msg.images),
+                })]
+            })
+            .collect()
+    }
+
+    /// Convert ToolDefinitions to Chat Completions tools
+    pub(crate) fn convert_tools(tools: &[ToolDefinition]) -> Vec<serde_json::Value> {
+        tools
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": t.parameters,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Build a request with the provider's sampling settings, overridden by `options`
+    fn request(
+        &self,
+        messages: &[ChatMessage],
+        tools: Option<&[ToolDefinition]>,
+        response_format: Option<serde_json::Value>,
+        options: &RequestOptions,
+        stream: bool,
+    ) -> ChatCompletionRequest {
+        let wire_tools = tools.map(Self::convert_tools).filter(|t| !t.is_empty());
+        let tool_choice = match options.tool_choice {
+            _ if wire_tools.is_none() => None,
+            ToolChoice::Auto => None,
+            ToolChoice::None => Some(serde_json::json!("none")),
+            ToolChoice::Required => Some(serde_json::json!("required")),
+            ToolChoice::Tool(ref name) => Some(serde_json::json!({
+                "type": "function",
+                "function": { "name": name },
+            })),
+        };
+        ChatCompletionRequest {
+            model: options.model.clone().unwrap_or_else(|| self.model.clone()),
+            messages: Self::convert_messages(messages),
+            temperature: options.temperature.or(self.temperature),
+            max_tokens: Some(self.max_tokens),
+            tools: wire_tools,
+            tool_choice,
+            response_format,
+            reasoning_effort: options
+                .reasoning_effort
+                .clone()
+                .or_else(|| self.reasoning_effort.clone()),
+            stream: stream.then_some(true),
+            stream_options: stream.then(|| serde_json::json!({ "include_usage": true })),
+        }
+    }
+
+    /// POST a request to `/chat/completions`, applying `read` to the response
+    fn post<T: Send + 'static>(
+        &self,
+        request: &ChatCompletionRequest,
+        read: impl FnOnce(ureq::Response) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let url = endpoint_url(&self.base_url, "chat/completions");
+
+        tracing::debug!("Sending request to Chat Completions API at {}", url);
+        tracing::debug!("Model: {}", self.model);
+
+        let mut req = self.http_agent.post(&url).set("Content-Type", "application/json");
+        if let Some(ref key) = self.api_key {
+            req = req.set("Authorization", &format!("Bearer {}", key));
+        }
+
+        let cancel = self.cancel.lock().clone();
+        send_cancellable(req, serde_json::to_value(request)?, "Chat Completions", &cancel, read)
+    }
+
+    /// Send request and return the first choice with usage
+    fn send_request(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<(ChatCompletionChoice, Option<TokenUsage>)> {
+        let body = self.post(request, |resp| Ok(resp.into_string()?))?;
+        tracing::debug!("Raw Chat Completions response: {}", body);
+        let response: ChatCompletionResponse = serde_json::from_str(&body).map_err(|e| {
+            tracing::error!("Failed to parse Chat Completions response: {}", e);
+            tracing::error!("Response body: {}", body);
+            anyhow::anyhow!("Failed to read JSON: {}", e)
+        })?;
+
+        let usage = Self::convert_usage(&response.usage);
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No choices in response"))?;
+        Self::check_complete(&choice)?;
+        Ok((choice, usage))
+    }
+
+    /// Send a streaming request, forwarding content deltas to `on_delta`.
+    /// Returns the choice reassembled from all chunks.
+    fn send_request_streaming(
+        &self,
+        request: &ChatCompletionRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(ChatCompletionChoice, Option<TokenUsage>)> {
+        let resp = self.post(request, Ok)?;
+        let cancel = self.cancel.lock().clone();
+        let (choice, usage) = Self::read_chunk_stream(cancellable_reader(resp, &cancel), on_delta)
+            .map_err(|e| stream_error(e, &cancel))?;
+        Self::check_complete(&choice)?;
+        Ok((choice, usage))
+    }
+
+    /// Reassemble a streamed completion from its chunks.
+    fn read_chunk_stream(
+        reader: impl std::io::BufRead,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(ChatCompletionChoice, Option<TokenUsage>)> {
+        let mut content = String::new();
+        let mut reasoning = String::new();
+        // (id, name, arguments) per tool call index
+        let mut calls: Vec<(String, String, String)> = Vec::new();
+        let mut finish_reason = None;
+        let mut usage = None;
+
+        for data in sse_data_lines(reader) {
+            let data = data?;
+            let chunk: ChatCompletionChunk = match serde_json::from_str(&data) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    tracing::warn!("Skipping unparseable stream chunk: {} ({})", data, e);
+                    continue;
+                }
+            };
+
+            if chunk.usage.is_some() {
+                usage = Self::convert_usage(&chunk.usage);
+            }
+
+            for choice in chunk.choices {
+                if let Some(text) = choice.delta.content {
+                    if !text.is_empty() {
+                        on_delta(&text);
+                        content.push_str(&text);
+                    }
+                }
+                if let Some(text) = choice.delta.reasoning_content {
+                    reasoning.push_str(&text);
+                }
+                for tc in choice.delta.tool_calls {
+                    if calls.len() <= tc.index {
+                        calls.resize(tc.index + 1, Default::default());
+                    }
+                    let entry = &mut calls[tc.index];
+                    if let Some(id) = tc.id {
+                        entry.0 = id;
+                    }
+                    if let Some(function) = tc.function {
+                        if let Some(name) = function.name {
+                            entry.1.push_str(&name);
+                        }
+                        if let Some(arguments) = function.arguments {
+                            entry.2.push_str(&arguments);
+                        }
+                    }
+                }
+                if choice.finish_reason.is_some() {
+                    finish_reason = choice.finish_reason;
+                }
+            }
+        }
+
+        let choice = ChatCompletionChoice {
+            message: ChatCompletionMessage {
+                content: (!content.is_empty()).then_some(content),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: calls
+                    .into_iter()
+                    .filter(|(_, name, _)| !name.is_empty())
+                    .map(|(id, name, arguments)| ChatCompletionToolCall {
+                        id,
+                        function: ChatCompletionFunction { name, arguments },
+                    })
+                    .collect(),
+            },
+            finish_reason,
+        };
+        Ok((choice, usage))
+    }
+
+    /// Reject responses cut off by the token limit
+    fn check_complete(choice: &ChatCompletionChoice) -> Result<()> {
+        if choice.finish_reason.as_deref() == Some("length") && choice.message.tool_calls.is_empty()
+        {
+            return Err(anyhow::anyhow!(
+                "Response incomplete: length. Consider increasing max_tokens."
+            ));
+        }
+        Ok(())
+    }
+
+    /// Convert API usage to TokenUsage
+    fn convert_usage(usage: &Option<ChatCompletionUsage>) -> Option<TokenUsage> {
+        usage.as_ref().map(|u| TokenUsage {
+            input_tokens: u.prompt_tokens,
+            output_tokens: u.completion_tokens,
+            total_tokens: u.total_tokens,
+        })
+    }
+
+    /// Interpret a choice as either tool calls or text
+    fn into_llm_response(
+        choice: ChatCompletionChoice,
+        usage: Option<TokenUsage>,
+    ) -> Result<LlmResponse> {
+        if let Some(ref u) = usage {
+            tracing::info!(
+                "Token usage: input={}, output={}, total={}",
+                u.input_tokens, u.output_tokens, u.total_tokens
+            );
+        }
+
+        let message = choice.message;
+        if !message.tool_calls.is_empty() {
+            let calls: Vec<ToolCallInfo> = message
+                .tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, tc)| ToolCallInfo {
+                    // Some local servers omit ids; the ReAct loop needs them to pair results
+                    id: if tc.id.is_empty() { format!("call_{}", i) } else { tc.id },
+                    name: tc.function.name,
+                    arguments: serde_json::from_str(&tc.function.arguments).unwrap_or_default(),
+                })
+                .collect();
+            tracing::info!("Chat Completions returned {} tool calls", calls.len());
+            return Ok(LlmResponse::ToolCalls(calls, usage));
+        }
+
+        Ok(LlmResponse::Text {
+            content: message.content.unwrap_or_default(),
+            reasoning: message.reasoning_content.filter(|r| !r.is_empty()),
+            usage,
+        })
+    }
+}
+
+impl LlmProvider for OpenAiCompatProvider {
+    fn set_cancel_token(&self, token: CancelToken) {
+        *self.cancel.lock() = token;
+    }
+
+    fn count_tokens(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        TokenEstimator::for_model(&self.model).count_request(messages, tools)
+    }
+
+    fn supports_structured_output(&self) -> bool {
+        true
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn chat(&self, messages: &[ChatMessage], options: &RequestOptions) -> Result<String> {
+        let request = self.request(messages, None, None, options, false);
+        let (choice, _usage) = self.send_request(&request)?;
+        choice
+            .message
+            .content
+            .ok_or_else(|| anyhow::anyhow!("No text content in response"))
+    }
+
+    fn chat_with_schema(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+        schema_name: &str,
+        options: &RequestOptions,
+    ) -> Result<String> {
+        let response_format = serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": schema_name,
+                "schema": schema,
+                "strict": true,
+            }
+        });
+        let request = self.request(messages, None, Some(response_format), options, false);
+
+        tracing::debug!("Sending request to Chat Completions API with JSON Schema");
+
+        let (choice, _usage) = self.send_request(&request)?;
+        choice
+            .message
+            .content
+            .ok_or_else(|| anyhow::anyhow!("No text content in response"))
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+    ) -> Result<LlmResponse> {
+        let request = self.request(messages, Some(tools), None, options, false);
+        let (choice, usage) = self.send_request(&request)?;
+        Self::into_llm_response(choice, usage)
+    }
+
+    fn chat_streaming(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let request = self.request(messages, None, None, options, true);
+        let (choice, usage) = self.send_request_streaming(&request, on_delta)?;
+        Ok((choice.message.content.unwrap_or_default(), usage))
+    }
+
+    fn chat_with_tools_streaming(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<LlmResponse> {
+        let request = self.request(messages, Some(tools), None, options, true);
+        let (choice, usage) = self.send_request_streaming(&request, on_delta)?;
+        Self::into_llm_response(choice, usage)
+    }
+}
+
+// ============================================================================
+// Anthropic Provider — Messages API
+// ============================================================================
+
+/// Default endpoint of the Anthropic API
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+/// API version sent in the `anthropic-version` header
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Thinking blocks kept for replay are pruned beyond this many tool-calling turns
+const MAX_CACHED_THINKING_TURNS: usize = 32;
+
+// -- Wire format types for Messages API --
+
+/// Messages API request
+#[derive(Debug, Serialize)]
+struct AnthropicRequest {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+}
+
+/// A message with content blocks
+#[derive(Debug, Serialize)]
+struct AnthropicMessage {
+    role: String,
+    content: Vec<serde_json::Value>,
+}
+
+/// Messages API response
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicResponse {
+    #[serde(default)]
+    content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+/// Response content block (text, thinking, redacted_thinking or tool_use)
+#[derive(Debug, Clone, Default, Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    thinking: Option<String>,
+    #[serde(default)]
+    signature: Option<String>,
+    #[serde(default)]
+    data: Option<String>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    input: Option<serde_json::Value>,
+}
+
+/// Token usage from the Messages API
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+/// Server-sent event from a streaming Messages API request
+#[derive(Debug, Deserialize)]
+struct AnthropicStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    message: Option<AnthropicResponse>,
+    #[serde(default)]
+    content_block: Option<AnthropicContentBlock>,
+    #[serde(default)]
+    delta: Option<serde_json::Value>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+pub struct AnthropicProvider {
+    base_url: String,
+    api_key: String,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: u32,
+    /// Extended thinking budget; `None` disables thinking
+    thinking_budget: Option<u32>,
+    /// Thinking blocks from tool-calling turns, keyed by the turn's first tool_use id.
+    /// The API requires them to be sent back alongside the tool results.
+    thinking_blocks: parking_lot::Mutex<std::collections::HashMap<String, Vec<serde_json::Value>>>,
+    http_agent: ureq::Agent,
+    cancel: parking_lot::Mutex<CancelToken>,
+}
+
+impl AnthropicProvider {
+    pub fn new(
+        base_url: String,
+        api_key: String,
+        model: String,
+        temperature: Option<f32>,
+        max_tokens: u32,
+        reasoning_effort: Option<String>,
+    ) -> Self {
+        let thinking_budget = reasoning_effort
+            .as_deref()
+            .and_then(|effort| Self::thinking_budget(effort, max_tokens));
+
+        tracing::info!("Initializing Anthropic provider (Messages API)");
+        tracing::info!("  Base URL: {}", base_url);
+        tracing::info!("  Model: {}", model);
+        tracing::info!("  Thinking budget: {:?}", thinking_budget);
+
+        Self {
+            base_url,
+            api_key,
+            model,
+            temperature,
+            max_tokens,
+            thinking_budget,
+            thinking_blocks: parking_lot::Mutex::new(std::collections::HashMap::new()),
+            http_agent: build_http_agent(),
+            cancel: parking_lot::Mutex::new(CancelToken::new()),
+        }
+    }
+
+    /// Map a reasoning effort ("low"/"medium"/"high") to a thinking token budget.
+    /// The budget must stay below max_tokens and at or above the API minimum of 1024.
+    fn thinking_budget(effort: &str, max_tokens: u32) -> Option<u32> {
+        let requested = match effort {
+            "none" | "off" | "" => return None,
+            "minimal" | "low" => 2048,
+            "medium" => 8192,
+            _ => 16384,
+        };
+        let budget = requested.min(max_tokens / 2);
+        if budget < 1024 {
+            tracing::warn!(
+                "max_tokens {} too small for extended thinking, disabling it",
+                max_tokens
+            );
+            return None;
+        }
+        Some(budget)
+    }
+
+    /// Build an image content block
+    fn image_block(img: &ImageContent) -> serde_json::Value {
+        serde_json::json!({
+            "type": "image",
+            "source": {
+                "type": "base64",
+                "media_type": img.media_type,
+                "data": img.base64,
+            }
+        })
+    }
+
+    /// Convert ChatMessages to a system prompt plus Messages API messages.
+    ///
+    /// System messages are concatenated into the top-level `system` field. Tool results
+    /// become `tool_result` blocks in a user turn, and consecutive turns with the same role
+    /// are merged since the API expects user/assistant alternation.
+    fn convert_messages(&self, messages: &[ChatMessage]) -> (Option<String>, Vec<AnthropicMessage>) {
+        let mut system_parts: Vec<&str> = Vec::new();
+        let mut converted: Vec<AnthropicMessage> = Vec::new();
+        let thinking_blocks = self.thinking_blocks.lock();
+
+        for msg in messages {
+            let (role, blocks) = if let Some(ref calls) = msg.tool_calls {
+                let mut blocks: Vec<serde_json::Value> = calls
+                    .first()
+                    .and_then(|c| thinking_blocks.get(&c.id))
+                    .cloned()
+                    .unwrap_or_default();
+                if !msg.content.is_empty() {
+                    blocks.push(serde_json::json!({"type": "text", "text": msg.content}));
+                }
+                blocks.extend(calls.iter().map(|c| {
+                    serde_json::json!({
+                        "type": "tool_use",
+                        "id": c.id,
+                        "name": c.name,
+                        "input": if c.arguments.is_object() { c.arguments.clone() } else { serde_json::json!({}) },
+                    })
+                }));
+                ("assistant", blocks)
+            } else if let Some(ref call_id) = msg.tool_call_id {
+                let content = if msg.images.is_empty() {
+                    serde_json::Value::String(msg.content.clone())
+                } else {
+                    let mut parts = vec![serde_json::json!({"type": "text", "text": msg.content})];
+                    parts.extend(msg.images.iter().map(Self::image_block));
+                    serde_json::Value::Array(parts)
+                };
+                (
+                    "user",
+                    vec![serde_json::json!({
+                        "type": "tool_result",
+                        "tool_use_id": call_id,
+                        "content": content,
+                    })],
+                )
+            } else {
+                let role = match msg.role {
+                    ChatRole::System => {
+                        system_parts.push(&msg.content);
+                        continue;
+                    }
+                    ChatRole::User | ChatRole::Tool => "user",
+                    ChatRole::Assistant => "assistant",
+                };
+                let mut blocks: Vec<serde_json::Value> =
+                    msg.images.iter().map(Self::image_block).collect();
+                if !msg.content.is_empty() {
+                    blocks.push(serde_json::json!({"type": "text", "text": msg.content}));
+                }
+                if blocks.is_empty() {
+                    continue; // The API rejects empty content
+                }
+                (role, blocks)
+            };
+
+            match converted.last_mut() {
+                Some(last) if last.role == role => last.content.extend(blocks),
+                _ => converted.push(AnthropicMessage {
+                    role: role.to_string(),
+                    content: blocks,
+                }),
+            }
+        }
+
+        let system = (!system_parts.is_empty()).then(|| system_parts.join("\n\n"));
+        (system, converted)
+    }
+
+    /// Convert ToolDefinitions to Messages API tools
+    fn convert_tools(tools: &[ToolDefinition]) -> Vec<serde_json::Value> {
+        tools
+            .iter()
+            .map(|t| {
+                serde_json::json!({
+                    "name": t.name,
+                    "description": t.description,
+                    "input_schema": t.parameters,
+                })
+            })
+            .collect()
+    }
+
+    /// Build a request. Thinking is enabled when configured, which also requires
+    /// leaving temperature unset.
+    fn request(
+        &self,
+        messages: &[ChatMessage],
+        tools: Option<&[ToolDefinition]>,
+        options: &RequestOptions,
+        stream: bool,
+    ) -> AnthropicRequest {
+        let (system, messages) = self.convert_messages(messages);
+        let wire_tools = tools.map(Self::convert_tools).filter(|t| !t.is_empty());
+        let tool_choice = match options.tool_choice {
+            _ if wire_tools.is_none() => None,
+            ToolChoice::Auto => None,
+            ToolChoice::None => Some(serde_json::json!({"type": "none"})),
+            ToolChoice::Required => Some(serde_json::json!({"type": "any"})),
+            ToolChoice::Tool(ref name) => Some(serde_json::json!({"type": "tool", "name": name})),
+        };
+        let budget = match options.reasoning_effort {
+            Some(ref effort) => Self::thinking_budget(effort, self.max_tokens),
+            None => self.thinking_budget,
+        };
+        // Forcing a tool call is incompatible with extended thinking
+        let forced = matches!(options.tool_choice, ToolChoice::Required | ToolChoice::Tool(_));
+        let thinking = budget.filter(|_| !(forced && tool_choice.is_some())).map(|budget| {
+            serde_json::json!({"type": "enabled", "budget_tokens": budget})
+        });
+        let temperature = options.temperature.or(self.temperature);
+        AnthropicRequest {
+            model: options.model.clone().unwrap_or_else(|| self.model.clone()),
+            max_tokens: self.max_tokens,
+            system,
+            messages,
+            temperature: if thinking.is_some() { None } else { temperature },
+            tools: wire_tools,
+            tool_choice,
+            thinking,
+            stream: stream.then_some(true),
+        }
+    }
+
+    /// POST a request to `/messages`, applying `read` to the response
+    fn post<T: Send + 'static>(
+        &self,
+        request: &AnthropicRequest,
+        read: impl FnOnce(ureq::Response) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let url = endpoint_url(&self.base_url, "messages");
+
+        tracing::debug!("Sending request to Anthropic Messages API at {}", url);
+        tracing::debug!("Model: {}", self.model);
+
+        let req = self.http_agent.post(&url)
+            .set("Content-Type", "application/json")
+            .set("x-api-key", &self.api_key)
+            .set("anthropic-version", ANTHROPIC_VERSION);
+
+        let cancel = self.cancel.lock().clone();
+        send_cancellable(req, serde_json::to_value(request)?, "Anthropic", &cancel, read)
+    }
+
+    /// Send request and parse response
+    fn send_request(&self, request: &AnthropicRequest) -> Result<AnthropicResponse> {
+        let body = self.post(request, |resp| Ok(resp.into_string()?))?;
+        tracing::debug!("Raw Anthropic response: {}", body);
+        let response: AnthropicResponse = serde_json::from_str(&body).map_err(|e| {
+            tracing::error!("Failed to parse Anthropic response: {}", e);
+            tracing::error!("Response body: {}", body);
+            anyhow::anyhow!("Failed to read JSON: {}", e)
+        })?;
+        Self::check_complete(response)
+    }
+
+    /// Send a streaming request, forwarding text deltas to `on_delta`.
+    /// Returns the response reassembled from all events.
+    fn send_request_streaming(
+        &self,
+        request: &AnthropicRequest,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AnthropicResponse> {
+        let resp = self.post(request, Ok)?;
+        let cancel = self.cancel.lock().clone();
+        let response = Self::read_event_stream(cancellable_reader(resp, &cancel), on_delta)
+            .map_err(|e| stream_error(e, &cancel))?;
+        Self::check_complete(response)
+    }
+
+    /// Reassemble a Messages API server-sent event stream into a response.
+    fn read_event_stream(
+        reader: impl std::io::BufRead,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<AnthropicResponse> {
+        let mut response = AnthropicResponse::default();
+        // Raw JSON of tool_use inputs, accumulated per block index
+        let mut partial_inputs: Vec<String> = Vec::new();
+
+        for data in sse_data_lines(reader) {
+            let data = data?;
+            let event: AnthropicStreamEvent = match serde_json::from_str(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Skipping unparseable stream event: {} ({})", data, e);
+                    continue;
+                }
+            };
+
+            match event.event_type.as_str() {
+                "message_start" => {
+                    if let Some(message) = event.message {
+                        response.usage = message.usage;
+                    }
+                }
+                "content_block_start" => {
+                    let block = event.content_block.unwrap_or_default();
+                    if response.content.len() <= event.index {
+                        response.content.resize(event.index + 1, Default::default());
+                        partial_inputs.resize(event.index + 1, String::new());
+                    }
+                    response.content[event.index] = block;
+                }
+                "content_block_delta" => {
+                    let (Some(block), Some(delta)) =
+                        (response.content.get_mut(event.index), event.delta)
+                    else {
+                        continue;
+                    };
+                    match delta["type"].as_str() {
+                        Some("text_delta") => {
+                            let text = delta["text"].as_str().unwrap_or_default();
+                            on_delta(text);
+                            block.text.get_or_insert_with(String::new).push_str(text);
+                        }
+                        Some("thinking_delta") => {
+                            let text = delta["thinking"].as_str().unwrap_or_default();
+                            block.thinking.get_or_insert_with(String::new).push_str(text);
+                        }
+                        Some("signature_delta") => {
+                            let text = delta["signature"].as_str().unwrap_or_default();
+                            block.signature.get_or_insert_with(String::new).push_str(text);
+                        }
+                        Some("input_json_delta") => {
+                            let text = delta["partial_json"].as_str().unwrap_or_default();
+                            partial_inputs[event.index].push_str(text);
+                        }
+                        _ => {}
+                    }
+                }
+                "message_delta" => {
+                    if let Some(reason) = event.delta.as_ref().and_then(|d| d["stop_reason"].as_str()) {
+                        response.stop_reason = Some(reason.to_string());
+                    }
+                    if let Some(usage) = event.usage {
+                        let total = response.usage.get_or_insert_with(Default::default);
+                        total.output_tokens = usage.output_tokens;
+                    }
+                }
+                "message_stop" => {
+                    for (block, input) in response.content.iter_mut().zip(&partial_inputs) {
+                        if block.block_type == "tool_use" && !input.is_empty() {
+                            block.input = Some(serde_json::from_str(input).unwrap_or_default());
+                        }
+                    }
+                    return Ok(response);
+                }
+                "error" => {
+                    let message = event
+                        .error
+                        .as_ref()
+                        .and_then(|e| e["message"].as_str())
+                        .unwrap_or("unknown error")
+                        .to_string();
+                    return Err(anyhow::anyhow!("Anthropic stream error: {}", message));
+                }
+                _ => {}
+            }
+        }
+
+        Err(anyhow::anyhow!("Stream ended without message_stop"))
+    }
+
+    /// Reject responses cut off by the token limit
+    fn check_complete(response: AnthropicResponse) -> Result<AnthropicResponse> {
+        let has_tool_use = response.content.iter().any(|b| b.block_type == "tool_use");
+        if response.stop_reason.as_deref() == Some("max_tokens") && !has_tool_use {
+            return Err(anyhow::anyhow!(
+                "Response incomplete: max_tokens. Consider increasing max_tokens."
+            ));
+        }
+        Ok(response)
+    }
+
+    /// Convert API usage to TokenUsage (cached prompt tokens count as input)
+    fn convert_usage(usage: &Option<AnthropicUsage>) -> Option<TokenUsage> {
+        usage.as_ref().map(|u| {
+            let input = u.input_tokens + u.cache_creation_input_tokens + u.cache_read_input_tokens;
+            TokenUsage {
+                input_tokens: input,
+                output_tokens: u.output_tokens,
+                total_tokens: input + u.output_tokens,
+            }
+        })
+    }
+
+    /// Concatenate all blocks of the given type via `field`
+    fn join_blocks(
+        blocks: &[AnthropicContentBlock],
+        block_type: &str,
+        field: impl Fn(&AnthropicContentBlock) -> Option<&String>,
+    ) -> Option<String> {
+        let parts: Vec<&str> = blocks
+            .iter()
+            .filter(|b| b.block_type == block_type)
+            .filter_map(|b| field(b).map(|s| s.as_str()))
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n"))
+    }
+
+    /// Interpret a response as either tool calls or text, remembering thinking blocks
+    /// that must be replayed with the tool results
+    fn to_llm_response(&self, response: AnthropicResponse) -> Result<LlmResponse> {
+        let usage = Self::convert_usage(&response.usage);
+
+        if let Some(ref u) = usage {
+            tracing::info!(
+                "Token usage: input={}, output={}, total={}",
+                u.input_tokens, u.output_tokens, u.total_tokens
+            );
+        }
+
+        let tool_calls: Vec<ToolCallInfo> = response
+            .content
+            .iter()
+            .filter(|b| b.block_type == "tool_use")
+            .filter_map(|b| {
+                Some(ToolCallInfo {
+                    id: b.id.clone()?,
+                    name: b.name.clone()?,
+                    arguments: b.input.clone().unwrap_or_else(|| serde_json::json!({})),
+                })
+            })
+            .collect();
+
+        if !tool_calls.is_empty() {
+            let thinking: Vec<serde_json::Value> = response
+                .content
+                .iter()
+                .filter_map(|b| match b.block_type.as_str() {
+                    "thinking" => Some(serde_json::json!({
+                        "type": "thinking",
+                        "thinking": b.thinking.clone().unwrap_or_default(),
+                        "signature": b.signature.clone().unwrap_or_default(),
+                    })),
+                    "redacted_thinking" => Some(serde_json::json!({
+                        "type": "redacted_thinking",
+                        "data": b.data.clone().unwrap_or_default(),
+                    })),
+                    _ => None,
+                })
+                .collect();
+            if !thinking.is_empty() {
+                let mut cache = self.thinking_blocks.lock();
+                if cache.len() >= MAX_CACHED_THINKING_TURNS {
+                    cache.clear();
+                }
+                cache.insert(tool_calls[0].id.clone(), thinking);
+            }
+
+            tracing::info!("Anthropic returned {} tool calls", tool_calls.len());
+            return Ok(LlmResponse::ToolCalls(tool_calls, usage));
+        }
+
+        let text = Self::join_blocks(&response.content, "text", |b| b.text.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("No text content or tool calls in response"))?;
+        let reasoning = Self::join_blocks(&response.content, "thinking", |b| b.thinking.as_ref());
+
+        Ok(LlmResponse::Text {
+            content: text,
+            reasoning,
+            usage,
         })
     }
 }
 
+impl LlmProvider for AnthropicProvider {
+    fn set_cancel_token(&self, token: CancelToken) {
+        *self.cancel.lock() = token;
+    }
+
+    fn count_tokens(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        TokenEstimator::ANTHROPIC.count_request(messages, tools)
+    }
+
+    fn supports_structured_output(&self) -> bool {
+        true
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn chat(&self, messages: &[ChatMessage], options: &RequestOptions) -> Result<String> {
+        let request = self.request(messages, None, options, false);
+        let response = self.send_request(&request)?;
+        Self::join_blocks(&response.content, "text", |b| b.text.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("No text content in response"))
+    }
+
+    /// Structured output via a forced tool call whose input schema is the requested schema.
+    fn chat_with_schema(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+        schema_name: &str,
+        options: &RequestOptions,
+    ) -> Result<String> {
+        let mut request = self.request(messages, None, options, false);
+        request.tools = Some(vec![serde_json::json!({
+            "name": schema_name,
+            "description": "Respond with output matching this schema.",
+            "input_schema": schema,
+        })]);
+        request.tool_choice = Some(serde_json::json!({"type": "tool", "name": schema_name}));
+        // Forced tool choice is incompatible with extended thinking
+        request.thinking = None;
+        request.temperature = options.temperature.or(self.temperature);
+
+        tracing::debug!("Sending request to Anthropic Messages API with JSON Schema");
+
+        let response = self.send_request(&request)?;
+        let input = response
+            .content
+            .iter()
+            .find(|b| b.block_type == "tool_use")
+            .and_then(|b| b.input.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("No structured output in response"))?;
+        Ok(serde_json::to_string(input)?)
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+    ) -> Result<LlmResponse> {
+        let request = self.request(messages, Some(tools), options, false);
+
+        tracing::debug!("Sending chat_with_tools request to Anthropic Messages API");
+
+        let response = self.send_request(&request)?;
+        self.to_llm_response(response)
+    }
+
+    fn chat_streaming(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let request = self.request(messages, None, options, true);
+        let response = self.send_request_streaming(&request, on_delta)?;
+        let text = Self::join_blocks(&response.content, "text", |b| b.text.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("No text content in response"))?;
+        Ok((text, Self::convert_usage(&response.usage)))
+    }
+
+    fn chat_with_tools_streaming(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<LlmResponse> {
+        let request = self.request(messages, Some(tools), options, true);
+
+        tracing::debug!("Sending streaming chat_with_tools request to Anthropic Messages API");
+
+        let response = self.send_request_streaming(&request, on_delta)?;
+        self.to_llm_response(response)
+    }
+}
+
 // ============================================================================
 // Factory function
 // ============================================================================
 
+/// Default endpoint of the OpenAI cloud API
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Provider backend `create_provider` picks: `provider` if set, otherwise chosen from
+/// model_path/base_url
+pub fn provider_kind(provider: Option<&str>, model_path: Option<&str>, base_url: &str) -> String {
+    match provider {
+        Some(kind) => kind.to_string(),
+        None if model_path.is_some() => "local".to_string(),
+        None if base_url.trim_end_matches('/') == OPENAI_BASE_URL => "openai".to_string(),
+        None if base_url.trim_end_matches('/') == ANTHROPIC_BASE_URL => "anthropic".to_string(),
+        None => "openai_compat".to_string(),
+    }
+}
+
 /// Create LLM provider based on runtime configuration
 ///
-/// Selection logic:
+/// `provider` selects the backend explicitly: "local", "openai" (Responses API),
+/// "openai_compat" (Chat Completions API) or "anthropic" (Messages API).
+/// When it is `None`, selection is automatic:
 /// 1. If model_path is provided → local FFI (in-process llama.cpp)
-/// 2. If api_key is provided → OpenAI (cloud)
-/// 3. Otherwise → error
+/// 2. If base_url is the OpenAI cloud API → OpenAI Responses API (requires api_key)
+/// 3. If base_url is the Anthropic API → Anthropic Messages API (requires api_key)
+/// 4. Otherwise → OpenAI-compatible Chat Completions at base_url (api_key optional)
+#[allow(clippy::too_many_arguments)]
 pub fn create_provider(
+    provider: Option<String>,
     model_path: Option<String>,
-    _base_url: String,
+    base_url: String,
     model: String,
     api_key: Option<String>,
     temperature: Option<f32>,
     max_tokens: u32,
     reasoning_effort: Option<String>,
+    sampling: SamplingConfig,
+    runtime: LocalRuntimeConfig,
 ) -> Result<Box<dyn LlmProvider>, anyhow::Error> {
-    if let Some(ref path) = model_path {
-        #[cfg(feature = "local")]
-        {
-            tracing::info!("Using local llama.cpp provider (FFI)");
-            let temp = temperature.unwrap_or(0.7);
-            let provider =
-                crate::llm_local::LlamaLocalProvider::new(path, temp, max_tokens, 8192)
-                    .map_err(|e| {
-                        tracing::error!("Failed to create local provider: {}", e);
-                        anyhow::anyhow!("Failed to load model from {}: {}", path, e)
-                    })?;
-            return Ok(Box::new(provider));
+    let kind = provider_kind(provider.as_deref(), model_path.as_deref(), &base_url);
+
+    match kind.as_str() {
+        "local" => {
+            let path = model_path
+                .ok_or_else(|| anyhow::anyhow!("Provider 'local' requires model_path"))?;
+            #[cfg(feature = "local")]
+            {
+                tracing::info!("Using local llama.cpp provider (FFI)");
+                let temp = temperature.unwrap_or(0.7);
+                let provider = crate::llm_local::LlamaLocalProvider::new(
+                    &path, temp, max_tokens, sampling, runtime,
+                )
+                .map_err(|e| {
+                    tracing::error!("Failed to create local provider: {}", e);
+                    anyhow::anyhow!("Failed to load model from {}: {}", path, e)
+                })?;
+                Ok(Box::new(provider))
+            }
+            #[cfg(not(feature = "local"))]
+            {
+                let _ = (path, sampling, runtime);
+                anyhow::bail!(
+                    "Local model support not compiled in. Build with --features local"
+                );
+            }
         }
-        #[cfg(not(feature = "local"))]
-        {
-            let _ = path;
-            anyhow::bail!(
-                "Local model support not compiled in. Build with --features local"
-            );
+        "openai" => {
+            let key = api_key.ok_or_else(|| {
+                anyhow::anyhow!("No model_path or api_key provided. Set MODEL_PATH for local inference or OPENAI_API_KEY for cloud.")
+            })?;
+            tracing::info!("Using OpenAI provider (Responses API)");
+            Ok(Box::new(OpenAiProvider::new(
+                base_url,
+                key,
+                model,
+                temperature,
+                max_tokens,
+                reasoning_effort,
+            )))
         }
-    }
-
-    if let Some(key) = api_key {
-        tracing::info!("Using OpenAI provider (API key provided)");
-        Ok(Box::new(OpenAiProvider::new(
-            key,
-            model,
-            temperature,
-            max_tokens,
-            reasoning_effort,
-        )))
-    } else {
-        anyhow::bail!("No model_path or api_key provided. Set MODEL_PATH for local inference or OPENAI_API_KEY for cloud.");
+        "openai_compat" => {
+            tracing::info!("Using OpenAI-compatible provider at {}", base_url);
+            Ok(Box::new(OpenAiCompatProvider::new(
+                base_url,
+                api_key,
+                model,
+                temperature,
+                max_tokens,
+                reasoning_effort,
+            )))
+        }
+        "anthropic" => {
+            let key = api_key
+                .ok_or_else(|| anyhow::anyhow!("Provider 'anthropic' requires an api_key (ANTHROPIC_API_KEY)"))?;
+            // The config default points at OpenAI; swap in the Anthropic endpoint
+            let base_url = if base_url.trim_end_matches('/') == OPENAI_BASE_URL {
+                ANTHROPIC_BASE_URL.to_string()
+            } else {
+                base_url
+            };
+            tracing::info!("Using Anthropic provider (Messages API)");
+            Ok(Box::new(AnthropicProvider::new(
+                base_url,
+                key,
+                model,
+                temperature,
+                max_tokens,
+                reasoning_effort,
+            )))
+        }
+        other => anyhow::bail!(
+            "Unknown provider '{}'. Expected one of: local, openai, openai_compat, anthropic",
+            other
+        ),
     }
 }
 
@@ -1025,5 +2722,719 @@ mod tests {
         assert_eq!(content[1]["type"], "input_image");
         assert!(content[1]["image_url"].as_str().unwrap().contains("SCREENSHOT"));
     }
-}
 
+    #[test]
+    fn test_read_event_stream_collects_deltas() {
+        let stream = concat!(
+            "event: response.created\n",
+            "data: {\"type\":\"response.created\",\"response\":{\"status\":\"in_progress\",\"output\":[]}}\n",
+            "\n",
+            "event: response.output_text.delta\n",
+            "data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hel\"}\n",
+            "\n",
+            "event: response.output_text.delta\n",
+            "data: {\"type\":\"response.output_text.delta\",\"delta\":\"lo!\"}\n",
+            "\n",
+            "event: response.completed\n",
+            "data: {\"type\":\"response.completed\",\"response\":{\"status\":\"completed\",",
+            "\"output\":[{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Hello!\"}]}],",
+            "\"usage\":{\"input_tokens\":5,\"output_tokens\":2,\"total_tokens\":7}}}\n",
+            "\n",
+        );
+
+        let mut deltas = Vec::new();
+        let response = OpenAiProvider::read_event_stream(stream.as_bytes(), &mut |d| {
+            deltas.push(d.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(deltas, vec!["Hel", "lo!"]);
+        assert_eq!(response.status, "completed");
+        assert_eq!(OpenAiProvider::extract_text(&response.output).unwrap(), "Hello!");
+        assert_eq!(response.usage.unwrap().total_tokens, 7);
+    }
+
+    #[test]
+    fn test_read_event_stream_tool_call_has_no_deltas() {
+        let stream = concat!(
+            "data: {\"type\":\"response.function_call_arguments.delta\",\"delta\":\"{\\\"a\\\"\"}\n",
+            "data: {\"type\":\"response.completed\",\"response\":{\"status\":\"completed\",",
+            "\"output\":[{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"read\",",
+            "\"arguments\":\"{\\\"file_path\\\":\\\"a.txt\\\"}\"}]}}\n",
+        );
+
+        let mut deltas = Vec::new();
+        let response = OpenAiProvider::read_event_stream(stream.as_bytes(), &mut |d| {
+            deltas.push(d.to_string())
+        })
+        .unwrap();
+
+        assert!(deltas.is_empty());
+        match OpenAiProvider::into_llm_response(response).unwrap() {
+            LlmResponse::ToolCalls(calls, _) => {
+                assert_eq!(calls.len(), 1);
+                assert_eq!(calls[0].name, "read");
+                assert_eq!(calls[0].arguments["file_path"], "a.txt");
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_event_stream_error_event() {
+        let stream = "data: {\"type\":\"error\",\"message\":\"rate limited\"}\n";
+        let err = OpenAiProvider::read_event_stream(stream.as_bytes(), &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("rate limited"));
+    }
+
+    #[test]
+    fn test_read_event_stream_truncated() {
+        let stream = "data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\n";
+        let err = OpenAiProvider::read_event_stream(stream.as_bytes(), &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("without a completed response"));
+    }
+
+    // ---- Stub HTTP server helpers ----
+
+    /// A request captured by the stub server: (url, authorization header, JSON body)
+    type StubRequest = (String, Option<String>, serde_json::Value);
+
+    /// Serve the given bodies (one per request) on an ephemeral port.
+    /// Returns the base URL ("http://127.0.0.1:<port>/v1") and a receiver of captured requests.
+    fn spawn_stub_server(
+        bodies: Vec<&'static str>,
+        content_type: &'static str,
+    ) -> (String, std::sync::mpsc::Receiver<StubRequest>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            for body in bodies {
+                let mut request = server.recv().unwrap();
+                let mut raw = String::new();
+                request.as_reader().read_to_string(&mut raw).unwrap();
+                let auth = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .map(|h| h.value.to_string());
+                let json = serde_json::from_str(&raw).unwrap_or_default();
+                tx.send((request.url().to_string(), auth, json)).unwrap();
+
+                let header =
+                    tiny_http::Header::from_bytes("Content-Type", content_type).unwrap();
+                request
+                    .respond(tiny_http::Response::from_string(body).with_header(header))
+                    .unwrap();
+            }
+        });
+
+        (format!("http://127.0.0.1:{}/v1", port), rx)
+    }
+
+    fn compat_provider(base_url: String, api_key: Option<String>) -> OpenAiCompatProvider {
+        OpenAiCompatProvider::new(base_url, api_key, "test-model".to_string(), None, 256, None)
+    }
+
+    // ---- Chat Completions provider tests ----
+
+    #[test]
+    fn test_compat_convert_user_message_with_images() {
+        let mut msg = ChatMessage::user("what is this".to_string());
+        msg.images = vec![ImageContent {
+            base64: "AAAA".to_string(),
+            media_type: "image/png".to_string(),
+        }];
+
+        let json = OpenAiCompatProvider::convert_messages(&[msg]);
+        assert_eq!(json.len(), 1);
+        let content = json[0]["content"].as_array().unwrap();
+        assert_eq!(content[0]["type"], "text");
+        assert_eq!(content[1]["type"], "image_url");
+        assert_eq!(content[1]["image_url"]["url"], "data:image/png;base64,AAAA");
+    }
+
+    #[test]
+    fn test_compat_convert_tool_roundtrip() {
+        let msgs = vec![
+            ChatMessage::assistant_tool_calls(vec![ToolCallInfo {
+                id: "call_1".to_string(),
+                name: "capture_screen".to_string(),
+                arguments: serde_json::json!({"window_id": 1}),
+            }]),
+            ChatMessage::tool_result_with_images(
+                "call_1".to_string(),
+                "capture_screen".to_string(),
+                "Window: Chrome".to_string(),
+                vec![ImageContent {
+                    base64: "SCREENSHOT".to_string(),
+                    media_type: "image/png".to_string(),
+                }],
+            ),
+        ];
+
+        let json = OpenAiCompatProvider::convert_messages(&msgs);
+        // assistant(tool_calls) + tool + user(image) = 3
+        assert_eq!(json.len(), 3);
+        assert_eq!(json[0]["role"], "assistant");
+        assert!(json[0]["content"].is_null());
+        assert_eq!(json[0]["tool_calls"][0]["function"]["name"], "capture_screen");
+        assert_eq!(json[0]["tool_calls"][0]["function"]["arguments"], "{\"window_id\":1}");
+        assert_eq!(json[1]["role"], "tool");
+        assert_eq!(json[1]["tool_call_id"], "call_1");
+        assert_eq!(json[1]["content"], "Window: Chrome");
+        assert_eq!(json[2]["role"], "user");
+        assert_eq!(json[2]["content"][1]["image_url"]["url"], "data:image/png;base64,SCREENSHOT");
+    }
+
+    #[test]
+    fn test_compat_chat_with_tools_against_stub() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![r#"{
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_abc",
+                            "type": "function",
+                            "function": {"name": "read", "arguments": "{\"file_path\":\"a.txt\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }],
+                "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+            }"#],
+            "application/json",
+        );
+        let provider = compat_provider(base_url, None);
+        let tools = vec![ToolDefinition {
+            name: "read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: serde_json::json!({"type": "object", "properties": {}}),
+        }];
+
+        let response = provider
+            .chat_with_tools(
+                &[ChatMessage::user("read a.txt".to_string())],
+                &tools,
+                &RequestOptions::default(),
+            )
+            .unwrap();
+
+        let (url, auth, body) = requests.recv().unwrap();
+        assert_eq!(url, "/v1/chat/completions");
+        assert!(auth.is_none(), "no api_key means no Authorization header");
+        assert_eq!(body["model"], "test-model");
+        assert_eq!(body["tools"][0]["function"]["name"], "read");
+        assert_eq!(body["messages"][0]["content"], "read a.txt");
+
+        match response {
+            LlmResponse::ToolCalls(calls, usage) => {
+                assert_eq!(calls[0].id, "call_abc");
+                assert_eq!(calls[0].arguments["file_path"], "a.txt");
+                assert_eq!(usage.unwrap().total_tokens, 15);
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compat_request_options_override_config() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![r#"{"choices": [{"message": {"content": "ok"}, "finish_reason": "stop"}]}"#],
+            "application/json",
+        );
+        let provider = compat_provider(base_url, None);
+        let tools = vec![ToolDefinition {
+            name: "read".to_string(),
+            description: "Read a file".to_string(),
+            parameters: serde_json::json!({"type": "object", "properties": {}}),
+        }];
+        let options = RequestOptions {
+            model: Some("other-model".to_string()),
+            temperature: Some(0.2),
+            reasoning_effort: Some("low".to_string()),
+            tool_choice: ToolChoice::parse("read"),
+        };
+
+        provider
+            .chat_with_tools(&[ChatMessage::user("hi".to_string())], &tools, &options)
+            .unwrap();
+
+        let (_, _, body) = requests.recv().unwrap();
+        assert_eq!(body["model"], "other-model");
+        assert!((body["temperature"].as_f64().unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(body["reasoning_effort"], "low");
+        assert_eq!(body["tool_choice"]["function"]["name"], "read");
+    }
+
+    #[test]
+    fn test_compat_chat_with_schema_against_stub() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![r#"{"choices": [{"message": {"content": "{\"response\":\"hi\",\"keywords\":[]}"}, "finish_reason": "stop"}]}"#],
+            "application/json",
+        );
+        let provider = compat_provider(base_url, Some("sk-test".to_string()));
+
+        let text = provider
+            .chat_with_schema(
+                &[ChatMessage::user("hello".to_string())],
+                serde_json::json!({"type": "object"}),
+                "reply",
+                &RequestOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(text, "{\"response\":\"hi\",\"keywords\":[]}");
+
+        let (_, auth, body) = requests.recv().unwrap();
+        assert_eq!(auth.as_deref(), Some("Bearer sk-test"));
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["name"], "reply");
+        assert!(body.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_compat_text_with_reasoning_against_stub() {
+        let (base_url, _requests) = spawn_stub_server(
+            vec![r#"{"choices": [{"message": {"content": "Four.", "reasoning_content": "2+2=4"}, "finish_reason": "stop"}]}"#],
+            "application/json",
+        );
+        let provider = compat_provider(base_url, None);
+
+        match provider
+            .chat_with_tools(
+                &[ChatMessage::user("2+2?".to_string())],
+                &[],
+                &RequestOptions::default(),
+            )
+            .unwrap()
+        {
+            LlmResponse::Text { content, reasoning, usage } => {
+                assert_eq!(content, "Four.");
+                assert_eq!(reasoning.as_deref(), Some("2+2=4"));
+                assert!(usage.is_none());
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compat_length_finish_is_error() {
+        let (base_url, _requests) = spawn_stub_server(
+            vec![r#"{"choices": [{"message": {"content": "Once upon a"}, "finish_reason": "length"}]}"#],
+            "application/json",
+        );
+        let provider = compat_provider(base_url, None);
+        let err = provider
+            .chat(&[ChatMessage::user("story".to_string())], &RequestOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("incomplete"));
+    }
+
+    #[test]
+    fn test_compat_streaming_against_stub() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![concat!(
+                "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":4,\"completion_tokens\":2,\"total_tokens\":6}}\n\n",
+                "data: [DONE]\n\n",
+            )],
+            "text/event-stream",
+        );
+        let provider = compat_provider(base_url, None);
+
+        let mut deltas = Vec::new();
+        let response = provider
+            .chat_with_tools_streaming(
+                &[ChatMessage::user("hi".to_string())],
+                &[],
+                &RequestOptions::default(),
+                &mut |d| deltas.push(d.to_string()),
+            )
+            .unwrap();
+
+        let (_, _, body) = requests.recv().unwrap();
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        match response {
+            LlmResponse::Text { content, usage, .. } => {
+                assert_eq!(content, "Hello");
+                assert_eq!(usage.unwrap().total_tokens, 6);
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compat_chat_streaming_reports_usage() {
+        let (base_url, _requests) = spawn_stub_server(
+            vec![concat!(
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":4,\"completion_tokens\":1,\"total_tokens\":5}}\n\n",
+                "data: [DONE]\n\n",
+            )],
+            "text/event-stream",
+        );
+        let provider = compat_provider(base_url, None);
+
+        let mut deltas = Vec::new();
+        let (text, usage) = provider
+            .chat_streaming(
+                &[ChatMessage::user("hi".to_string())],
+                &RequestOptions::default(),
+                &mut |d| deltas.push(d.to_string()),
+            )
+            .unwrap();
+        assert_eq!(text, "Hi");
+        assert_eq!(deltas, vec!["Hi"]);
+        assert_eq!(usage.unwrap().total_tokens, 5);
+    }
+
+    #[test]
+    fn test_compat_streamed_tool_call_fragments_are_joined() {
+        let stream = concat!(
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"glob\",\"arguments\":\"\"}}]}}]}\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"pattern\\\":\"}}]}}]}\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"*.rs\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n",
+            "data: [DONE]\n",
+        );
+
+        let (choice, _) =
+            OpenAiCompatProvider::read_chunk_stream(stream.as_bytes(), &mut |_| {}).unwrap();
+        match OpenAiCompatProvider::into_llm_response(choice, None).unwrap() {
+            LlmResponse::ToolCalls(calls, _) => {
+                assert_eq!(calls.len(), 1);
+                assert_eq!(calls[0].id, "call_1");
+                assert_eq!(calls[0].name, "glob");
+                assert_eq!(calls[0].arguments["pattern"], "*.rs");
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_responses_provider_honours_base_url() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![r#"{"status": "completed", "output": [{"type": "message", "content": [{"type": "output_text", "text": "pong"}]}]}"#],
+            "application/json",
+        );
+        let provider = OpenAiProvider::new(
+            format!("{}/", base_url),
+            "sk-test".to_string(),
+            "gpt-test".to_string(),
+            None,
+            256,
+            None,
+        );
+
+        let text = provider
+            .chat(&[ChatMessage::user("ping".to_string())], &RequestOptions::default())
+            .unwrap();
+        assert_eq!(text, "pong");
+
+        let (url, auth, body) = requests.recv().unwrap();
+        assert_eq!(url, "/v1/responses");
+        assert_eq!(auth.as_deref(), Some("Bearer sk-test"));
+        assert_eq!(body["model"], "gpt-test");
+    }
+
+    #[test]
+    fn test_cancel_abandons_blocked_request() {
+        // A server that accepts the request but never answers
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            let _request = server.recv().unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(30));
+        });
+
+        let provider = compat_provider(format!("http://127.0.0.1:{}/v1", port), None);
+        let cancel = CancelToken::new();
+        provider.set_cancel_token(cancel.clone());
+
+        let canceller = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        let start = std::time::Instant::now();
+        let err = provider
+            .chat(&[ChatMessage::user("hi".to_string())], &RequestOptions::default())
+            .unwrap_err();
+        assert!(matches!(AgentError::from_provider(err), AgentError::Cancelled));
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    // ---- Anthropic Messages API provider tests ----
+
+    fn anthropic_provider(base_url: String, reasoning_effort: Option<String>) -> AnthropicProvider {
+        AnthropicProvider::new(
+            base_url,
+            "sk-ant-test".to_string(),
+            "claude-test".to_string(),
+            Some(0.5),
+            4096,
+            reasoning_effort,
+        )
+    }
+
+    #[test]
+    fn test_anthropic_convert_messages() {
+        let provider = anthropic_provider(ANTHROPIC_BASE_URL.to_string(), None);
+        let call = ToolCallInfo {
+            id: "toolu_1".to_string(),
+            name: "capture_screen".to_string(),
+            arguments: serde_json::json!({}),
+        };
+        let messages = vec![
+            ChatMessage::system("be brief".to_string()),
+            ChatMessage::system("situation".to_string()),
+            ChatMessage::user("look".to_string()),
+            ChatMessage::assistant_tool_calls(vec![call]),
+            ChatMessage::tool_result_with_images(
+                "toolu_1".to_string(),
+                "capture_screen".to_string(),
+                "captured".to_string(),
+                vec![ImageContent {
+                    base64: "AAAA".to_string(),
+                    media_type: "image/png".to_string(),
+                }],
+            ),
+            ChatMessage::user("what do you see?".to_string()),
+        ];
+
+        let (system, converted) = provider.convert_messages(&messages);
+        assert_eq!(system.as_deref(), Some("be brief\n\nsituation"));
+        assert_eq!(converted.len(), 3);
+        assert_eq!(converted[1].role, "assistant");
+        assert_eq!(converted[1].content[0]["type"], "tool_use");
+        assert_eq!(converted[1].content[0]["id"], "toolu_1");
+
+        // Tool result and the following user text share one user turn
+        let user = &converted[2];
+        assert_eq!(user.role, "user");
+        assert_eq!(user.content.len(), 2);
+        assert_eq!(user.content[0]["type"], "tool_result");
+        assert_eq!(user.content[0]["tool_use_id"], "toolu_1");
+        assert_eq!(user.content[0]["content"][1]["type"], "image");
+        assert_eq!(user.content[0]["content"][1]["source"]["data"], "AAAA");
+        assert_eq!(user.content[1]["text"], "what do you see?");
+    }
+
+    #[test]
+    fn test_anthropic_thinking_budget() {
+        assert_eq!(AnthropicProvider::thinking_budget("low", 8192), Some(2048));
+        assert_eq!(AnthropicProvider::thinking_budget("high", 8192), Some(4096));
+        assert_eq!(AnthropicProvider::thinking_budget("medium", 1024), None);
+        assert_eq!(AnthropicProvider::thinking_budget("none", 8192), None);
+    }
+
+    #[test]
+    fn test_anthropic_tool_use_against_stub() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![
+                r#"{"content": [
+                    {"type": "thinking", "thinking": "need files", "signature": "sig"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "glob", "input": {"pattern": "*.rs"}}
+                ], "stop_reason": "tool_use",
+                "usage": {"input_tokens": 10, "cache_read_input_tokens": 5, "output_tokens": 7}}"#,
+                r#"{"content": [
+                    {"type": "thinking", "thinking": "two files"},
+                    {"type": "text", "text": "Found 2 files."}
+                ], "stop_reason": "end_turn", "usage": {"input_tokens": 30, "output_tokens": 4}}"#,
+            ],
+            "application/json",
+        );
+        let provider = anthropic_provider(base_url, Some("low".to_string()));
+        let tools = vec![ToolDefinition {
+            name: "glob".to_string(),
+            description: "Find files".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        }];
+
+        let mut messages = vec![ChatMessage::user("list rust files".to_string())];
+        let response = provider
+            .chat_with_tools(&messages, &tools, &RequestOptions::default())
+            .unwrap();
+        let calls = match response {
+            LlmResponse::ToolCalls(calls, usage) => {
+                let usage = usage.unwrap();
+                assert_eq!(usage.input_tokens, 15);
+                assert_eq!(usage.total_tokens, 22);
+                calls
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        };
+        assert_eq!(calls[0].arguments["pattern"], "*.rs");
+
+        let (url, _, body) = requests.recv().unwrap();
+        assert_eq!(url, "/v1/messages");
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(body["thinking"]["budget_tokens"], 2048);
+        assert!(body.get("temperature").is_none());
+
+        messages.push(ChatMessage::assistant_tool_calls(calls));
+        messages.push(ChatMessage::tool_result(
+            "toolu_1".to_string(),
+            "glob".to_string(),
+            "a.rs\nb.rs".to_string(),
+        ));
+        match provider.chat_with_tools(&messages, &tools, &RequestOptions::default()).unwrap() {
+            LlmResponse::Text { content, reasoning, .. } => {
+                assert_eq!(content, "Found 2 files.");
+                assert_eq!(reasoning.as_deref(), Some("two files"));
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+
+        // The thinking block is replayed ahead of the tool_use it preceded
+        let (_, _, body) = requests.recv().unwrap();
+        let assistant = &body["messages"][1]["content"];
+        assert_eq!(assistant[0]["type"], "thinking");
+        assert_eq!(assistant[0]["signature"], "sig");
+        assert_eq!(assistant[1]["type"], "tool_use");
+    }
+
+    #[test]
+    fn test_anthropic_chat_with_schema_forces_tool() {
+        let (base_url, requests) = spawn_stub_server(
+            vec![r#"{"content": [{"type": "tool_use", "id": "toolu_1", "name": "answer", "input": {"ok": true}}], "stop_reason": "tool_use"}"#],
+            "application/json",
+        );
+        let provider = anthropic_provider(base_url, Some("high".to_string()));
+
+        let json = provider
+            .chat_with_schema(
+                &[ChatMessage::user("ok?".to_string())],
+                serde_json::json!({"type": "object"}),
+                "answer",
+                &RequestOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(json, r#"{"ok":true}"#);
+
+        let (_, _, body) = requests.recv().unwrap();
+        assert_eq!(body["tool_choice"]["name"], "answer");
+        assert!(body.get("thinking").is_none());
+    }
+
+    #[test]
+    fn test_anthropic_max_tokens_is_error() {
+        let response: AnthropicResponse = serde_json::from_str(
+            r#"{"content": [{"type": "text", "text": "cut"}], "stop_reason": "max_tokens"}"#,
+        )
+        .unwrap();
+        assert!(AnthropicProvider::check_complete(response).is_err());
+    }
+
+    #[test]
+    fn test_anthropic_read_event_stream() {
+        let stream = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"hmm\"}}\n",
+            "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"lo\"}}\n",
+            "data: {\"type\":\"content_block_start\",\"index\":2,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"glob\",\"input\":{}}}\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"pattern\\\":\"}}\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"*.rs\\\"}\"}}\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":20}}\n",
+            "data: {\"type\":\"message_stop\"}\n",
+        );
+
+        let mut deltas = Vec::new();
+        let response =
+            AnthropicProvider::read_event_stream(stream.as_bytes(), &mut |d| deltas.push(d.to_string()))
+                .unwrap();
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        assert_eq!(response.content[0].thinking.as_deref(), Some("hmm"));
+        assert_eq!(response.content[1].text.as_deref(), Some("Hello"));
+        assert_eq!(response.content[2].input.as_ref().unwrap()["pattern"], "*.rs");
+        let usage = AnthropicProvider::convert_usage(&response.usage).unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 20);
+    }
+
+    #[test]
+    fn test_anthropic_stream_error_event() {
+        let stream = "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n";
+        let err = AnthropicProvider::read_event_stream(stream.as_bytes(), &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("Overloaded"));
+    }
+
+    #[test]
+    fn test_create_provider_errors() {
+        let err = create_provider(
+            Some("nope".to_string()),
+            None,
+            OPENAI_BASE_URL.to_string(),
+            "m".to_string(),
+            None,
+            None,
+            256,
+            None,
+            SamplingConfig::default(),
+            LocalRuntimeConfig::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("Unknown provider"));
+
+        // The OpenAI cloud endpoint still requires a key
+        let err = create_provider(
+            None,
+            None,
+            OPENAI_BASE_URL.to_string(),
+            "m".to_string(),
+            None,
+            None,
+            256,
+            None,
+            SamplingConfig::default(),
+            LocalRuntimeConfig::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("OPENAI_API_KEY"));
+
+        let err = create_provider(
+            Some("anthropic".to_string()),
+            None,
+            OPENAI_BASE_URL.to_string(),
+            "m".to_string(),
+            None,
+            None,
+            256,
+            None,
+            SamplingConfig::default(),
+            LocalRuntimeConfig::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("ANTHROPIC_API_KEY"));
+
+        // Any other endpoint works without a key
+        assert!(create_provider(
+            None,
+            None,
+            "http://127.0.0.1:8080/v1".to_string(),
+            "m".to_string(),
+            None,
+            None,
+            256,
+            None,
+            SamplingConfig::default(),
+            LocalRuntimeConfig::default(),
+        )
+        .is_ok());
+    }
+}
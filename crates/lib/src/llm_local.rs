@@ -3,29 +3,93 @@
 //! Loads a GGUF model directly — no server needed.
 
 use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
 use std::collections::HashSet;
 use std::num::NonZeroU32;
 use std::path::Path;
 
-use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::params::{KvCacheType, LlamaContextParams};
+use llama_cpp_2::context::LlamaContext;
 use llama_cpp_2::llama_backend::LlamaBackend;
 use llama_cpp_2::llama_batch::LlamaBatch;
 use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::mtmd::{
+    mtmd_default_marker, MtmdBitmap, MtmdContext, MtmdContextParams, MtmdInputChunkType,
+    MtmdInputText,
+};
 use llama_cpp_2::model::{
     AddBos, ChatTemplateResult, GrammarTriggerType, LlamaChatTemplate, LlamaModel,
 };
 use llama_cpp_2::openai::OpenAIChatTemplateParams;
 use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::LlamaToken;
+use regex::Regex;
+
+use crate::cancel::CancelToken;
+use crate::llm::{
+    ChatMessage, ChatRole, ImageContent, LlmProvider, LlmResponse, LocalRuntimeConfig,
+    RequestOptions, SamplingConfig, TokenUsage, ToolCallInfo, ToolChoice, ToolDefinition,
+};
+use crate::tokens::TokenEstimator;
+use crate::AgentError;
+
+/// Context size when `LocalRuntimeConfig::n_ctx` is unset
+const DEFAULT_N_CTX: u32 = 8192;
+
+/// Prompt tokens decoded per batch when `LocalRuntimeConfig::n_batch` is unset
+const DEFAULT_N_BATCH: u32 = 512;
+
+/// llama.cpp's `llama_flash_attn_type` values (unset leaves it on auto)
+const FLASH_ATTN_DISABLED: i32 = 0;
+const FLASH_ATTN_ENABLED: i32 = 1;
+
+/// llama.cpp's `LLAMA_DEFAULT_SEED`: the dist sampler picks a random seed
+const RANDOM_SEED: u32 = u32::MAX;
+
+/// Characters that reset the DRY sampler's repeated-sequence matching (llama.cpp defaults)
+const DRY_SEQ_BREAKERS: [&str; 4] = ["\n", ":", "\"", "*"];
+
+/// Reasoning block delimiters used by Qwen3, DeepSeek-R1 and similar templates
+const THINK_OPEN: &str = "<think>";
+const THINK_CLOSE: &str = "</think>";
+
+/// Fed to the model to end a reasoning block once the thinking budget is spent
+const THINK_FORCE_CLOSE: &str = "\n</think>\n\n";
+
+/// Stands in for KV positions filled by image embeddings; never equal to a real token
+const MEDIA_POSITION: LlamaToken = LlamaToken(-1);
 
-use crate::llm::{ChatMessage, ChatRole, LlmProvider, LlmResponse, TokenUsage, ToolCallInfo, ToolDefinition};
+/// KV positions budgeted per image when counting tokens without encoding it
+const IMAGE_TOKEN_ESTIMATE: usize = 1024;
+
+/// A context kept alive across calls, with the tokens currently held in its KV cache.
+struct KvSession {
+    ctx: LlamaContext<'static>,
+    n_ctx: u32,
+    cached: Vec<LlamaToken>,
+}
 
 pub struct LlamaLocalProvider {
+    /// Declared before `model` so the context is dropped before the model it borrows.
+    session: parking_lot::Mutex<Option<KvSession>>,
+    /// Multimodal projector for vision models. Also dropped before `model`.
+    vision: Option<parking_lot::Mutex<MtmdContext>>,
     backend: LlamaBackend,
-    model: LlamaModel,
+    model: Box<LlamaModel>,
     template: LlamaChatTemplate,
     temperature: f32,
     max_tokens: u32,
+    enable_thinking: bool,
+    /// Thinking tokens allowed on top of `max_tokens` answer tokens
+    thinking_budget: u32,
     n_ctx: u32,
+    /// Prompt tokens decoded per batch; cancellation is checked between batches.
+    n_batch: usize,
+    /// Thread, batch, offload, flash-attention and KV-type settings; `n_ctx` is set per context
+    context_params: LlamaContextParams,
+    sampling: SamplingConfig,
+    cancel: parking_lot::Mutex<CancelToken>,
 }
 
 // LlamaModel is Send+Sync. LlamaBackend and LlamaChatTemplate are safe to share.
@@ -37,23 +101,28 @@ impl LlamaLocalProvider {
         model_path: &str,
         temperature: f32,
         max_tokens: u32,
-        n_ctx: u32,
+        sampling: SamplingConfig,
+        runtime: LocalRuntimeConfig,
     ) -> Result<Self> {
         tracing::info!("Initializing local llama.cpp provider (FFI)");
         tracing::info!("  Model path: {}", model_path);
-        tracing::info!("  Context size: {}", n_ctx);
+        tracing::info!("  Sampling: {:?}", sampling);
+        tracing::info!("  Runtime: {:?}", runtime);
 
         let mut backend = LlamaBackend::init()
             .map_err(|e| anyhow::anyhow!("Failed to init llama backend: {}", e))?;
         backend.void_logs();
 
         // On iOS simulator, Metal doesn't support residency sets — use CPU only.
-        // On real iOS devices and macOS, offload all layers to Metal GPU.
+        // On real iOS devices and macOS, offload all layers to the GPU unless configured otherwise.
         let use_gpu = if cfg!(target_os = "ios") && cfg!(target_abi = "sim") {
             tracing::info!("  iOS simulator detected — using CPU only (no Metal)");
             false
+        } else if runtime.cpu_only {
+            tracing::info!("  CPU-only mode");
+            false
         } else {
-            tracing::info!("  Using Metal GPU acceleration");
+            tracing::info!("  Using GPU acceleration");
             true
         };
 
@@ -62,7 +131,7 @@ impl LlamaLocalProvider {
             unsafe { std::env::set_var("GGML_METAL_NO_RESIDENCY", "1"); }
         }
 
-        let gpu_layers: u32 = if use_gpu { 999 } else { 0 };
+        let gpu_layers: u32 = if use_gpu { runtime.gpu_layers.unwrap_or(999) } else { 0 };
         let model_params = LlamaModelParams::default()
             .with_n_gpu_layers(gpu_layers);
 
@@ -72,25 +141,101 @@ impl LlamaLocalProvider {
         tracing::info!("  Model loaded: {} params", model.n_params());
         tracing::info!("  Context train: {}", model.n_ctx_train());
 
-        let template = model
-            .chat_template(None)
-            .unwrap_or_else(|_| {
+        let mut n_ctx = runtime.n_ctx.unwrap_or(DEFAULT_N_CTX);
+        if n_ctx > model.n_ctx_train() {
+            tracing::warn!(
+                "n_ctx {} exceeds the model's training context {}, using {}",
+                n_ctx,
+                model.n_ctx_train(),
+                model.n_ctx_train()
+            );
+            n_ctx = model.n_ctx_train();
+        }
+        tracing::info!("  Context size: {}", n_ctx);
+
+        let n_batch = runtime.n_batch.unwrap_or(DEFAULT_N_BATCH).max(1);
+        let mut context_params = LlamaContextParams::default()
+            .with_n_batch(n_batch)
+            .with_offload_kqv(use_gpu);
+        if let Some(n_threads) = runtime.n_threads {
+            context_params = context_params
+                .with_n_threads(n_threads)
+                .with_n_threads_batch(runtime.n_threads_batch.unwrap_or(n_threads));
+        } else if let Some(n_threads_batch) = runtime.n_threads_batch {
+            context_params = context_params.with_n_threads_batch(n_threads_batch);
+        }
+        if let Some(flash_attention) = runtime.flash_attention {
+            context_params = context_params.with_flash_attention_policy(if flash_attention {
+                FLASH_ATTN_ENABLED
+            } else {
+                FLASH_ATTN_DISABLED
+            });
+        }
+        if let Some(ref name) = runtime.kv_cache_type {
+            let kv_type = parse_kv_cache_type(name)?;
+            context_params = context_params.with_type_k(kv_type).with_type_v(kv_type);
+        }
+
+        let vision = match runtime.mmproj_path {
+            Some(ref path) => {
+                tracing::info!("  Multimodal projector: {}", path);
+                let defaults = MtmdContextParams::default();
+                let params = MtmdContextParams {
+                    use_gpu,
+                    print_timings: false,
+                    n_threads: runtime.n_threads.unwrap_or(defaults.n_threads),
+                    ..defaults
+                };
+                let mtmd = MtmdContext::init_from_file(path, &model, &params)
+                    .map_err(|e| anyhow::anyhow!("Failed to load projector {}: {}", path, e))?;
+                if !mtmd.support_vision() {
+                    anyhow::bail!("Projector {} does not support image input", path);
+                }
+                Some(parking_lot::Mutex::new(mtmd))
+            }
+            None => None,
+        };
+
+        let template = match runtime.chat_template_path {
+            Some(ref path) => {
+                tracing::info!("  Chat template override: {}", path);
+                let source = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read chat template {}: {}", path, e))?;
+                LlamaChatTemplate::new(&source)
+                    .map_err(|e| anyhow::anyhow!("Invalid chat template {}: {}", path, e))?
+            }
+            None => model.chat_template(None).unwrap_or_else(|_| {
                 tracing::warn!("No chat template in model, using chatml fallback");
                 LlamaChatTemplate::new("chatml").expect("chatml is a valid template")
-            });
+            }),
+        };
 
         Ok(Self {
+            session: parking_lot::Mutex::new(None),
+            vision,
             backend,
-            model,
+            model: Box::new(model),
             template,
             temperature,
             max_tokens,
+            enable_thinking: runtime.enable_thinking,
+            thinking_budget: runtime.thinking_budget.unwrap_or(max_tokens),
             n_ctx,
+            n_batch: n_batch as usize,
+            context_params,
+            sampling,
+            cancel: parking_lot::Mutex::new(CancelToken::new()),
         })
     }
 
     /// Serialize ChatMessages to OpenAI-compatible JSON array string.
-    fn messages_to_json(messages: &[ChatMessage]) -> String {
+    /// With a `media_marker`, attached images become markers in the message text and are
+    /// returned in marker order; without one they are dropped.
+    fn messages_to_json<'m>(
+        messages: &'m [ChatMessage],
+        media_marker: Option<&str>,
+    ) -> (String, Vec<&'m ImageContent>) {
+        let mut images = Vec::new();
         let json_messages: Vec<serde_json::Value> = messages
             .iter()
             .flat_map(|msg| {
@@ -127,7 +272,7 @@ impl LlamaLocalProvider {
                 if let Some(ref call_id) = msg.tool_call_id {
                     return vec![serde_json::json!({
                         "role": "tool",
-                        "content": msg.content,
+                        "content": Self::content_with_media(msg, media_marker, &mut images),
                         "tool_call_id": call_id
                     })];
                 }
@@ -135,12 +280,35 @@ impl LlamaLocalProvider {
                 // Regular message
                 vec![serde_json::json!({
                     "role": role,
-                    "content": msg.content
+                    "content": Self::content_with_media(msg, media_marker, &mut images)
                 })]
             })
             .collect();
 
-        serde_json::to_string(&json_messages).unwrap_or_else(|_| "[]".to_string())
+        let json = serde_json::to_string(&json_messages).unwrap_or_else(|_| "[]".to_string());
+        (json, images)
+    }
+
+    /// Message text preceded by one media marker per attached image, collecting the images.
+    fn content_with_media<'m>(
+        msg: &'m ChatMessage,
+        media_marker: Option<&str>,
+        images: &mut Vec<&'m ImageContent>,
+    ) -> String {
+        if msg.images.is_empty() {
+            return msg.content.clone();
+        }
+        let Some(marker) = media_marker else {
+            tracing::warn!(
+                "No multimodal projector loaded (mmproj_path), dropping {} images",
+                msg.images.len()
+            );
+            return msg.content.clone();
+        };
+        images.extend(&msg.images);
+        let mut content = format!("{}\n", marker).repeat(msg.images.len());
+        content.push_str(&msg.content);
+        content
     }
 
     /// Serialize ToolDefinitions to OpenAI-compatible tools JSON array string.
@@ -162,68 +330,263 @@ impl LlamaLocalProvider {
         serde_json::to_string(&json_tools).unwrap_or_else(|_| "[]".to_string())
     }
 
-    /// Apply chat template with optional tools, returning ChatTemplateResult.
-    fn apply_template(
+    /// Apply chat template with optional tools or JSON schema, returning ChatTemplateResult
+    /// and the images whose markers appear in the prompt.
+    /// A schema makes the template produce a strict grammar constraining the whole output,
+    /// so thinking is only enabled without one.
+    fn apply_template<'m>(
         &self,
-        messages: &[ChatMessage],
+        messages: &'m [ChatMessage],
         tools: Option<&[ToolDefinition]>,
-    ) -> Result<ChatTemplateResult> {
-        let messages_json = Self::messages_to_json(messages);
+        json_schema: Option<&str>,
+    ) -> Result<(ChatTemplateResult, Vec<&'m ImageContent>)> {
+        let media_marker = self.vision.is_some().then(mtmd_default_marker);
+        let (messages_json, images) = Self::messages_to_json(messages, media_marker);
         let tools_json = tools.map(Self::tools_to_json);
 
         let params = OpenAIChatTemplateParams {
             messages_json: &messages_json,
             tools_json: tools_json.as_deref(),
             tool_choice: None,
-            json_schema: None,
+            json_schema,
             grammar: None,
             reasoning_format: None,
             chat_template_kwargs: None,
             add_generation_prompt: true,
             use_jinja: true,
             parallel_tool_calls: false,
-            enable_thinking: false,
+            enable_thinking: self.enable_thinking && json_schema.is_none(),
             add_bos: true,
             add_eos: false,
             parse_tool_calls: tools.is_some(),
         };
 
-        self.model
+        let template_result = self
+            .model
             .apply_chat_template_oaicompat(&self.template, &params)
-            .map_err(|e| anyhow::anyhow!("Failed to apply chat template: {}", e))
+            .map_err(|e| anyhow::anyhow!("Failed to apply chat template: {}", e))?;
+        Ok((template_result, images))
     }
 
-    /// Core generation loop. Tokenize, decode, sample until done.
-    /// Returns (generated_text, token_usage).
-    fn generate(&self, template_result: &ChatTemplateResult) -> Result<(String, TokenUsage)> {
+    /// Get the persistent context ready for a prompt of `n_prompt` positions, keeping the
+    /// longest prefix of `tokens` that is already cached.
+    ///
+    /// Only the diverging tail of the KV cache is evicted, so the system prompt, tool
+    /// definitions and earlier turns are not re-decoded on every call. The context is
+    /// recreated when it is too small, and the cache is cleared entirely when nothing can
+    /// be reused (e.g. the prompt shrank after compaction dropped early messages) or the
+    /// partial eviction is unsupported.
+    fn prepare_session<'s>(
+        &self,
+        slot: &'s mut Option<KvSession>,
+        n_prompt: usize,
+        tokens: &[LlamaToken],
+    ) -> Result<&'s mut KvSession> {
+        let reserve = self.max_tokens + if self.enable_thinking { self.thinking_budget } else { 0 };
+        let n_ctx = self.n_ctx.max(n_prompt as u32 + reserve);
+        if slot.as_ref().is_some_and(|s| s.n_ctx < n_ctx) {
+            tracing::info!("Prompt needs {} context tokens, recreating context", n_ctx);
+            *slot = None;
+        }
+
+        if slot.is_none() {
+            let ctx_params = self
+                .context_params
+                .clone()
+                .with_n_ctx(NonZeroU32::new(n_ctx));
+            let ctx = self
+                .model
+                .new_context(&self.backend, ctx_params)
+                .map_err(|e| anyhow::anyhow!("Failed to create context: {}", e))?;
+            // SAFETY: the model is boxed, so its address is stable for the provider's
+            // lifetime, and `session` is declared before `model`, so this context is
+            // always dropped before the model it borrows.
+            let ctx: LlamaContext<'static> = unsafe { std::mem::transmute(ctx) };
+            *slot = Some(KvSession {
+                ctx,
+                n_ctx,
+                cached: Vec::new(),
+            });
+        }
+        let session = slot.as_mut().expect("session was just created");
+
+        // Always re-decode at least the last prompt token to get fresh logits
+        let common = session
+            .cached
+            .iter()
+            .zip(tokens)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(tokens.len().saturating_sub(1));
+
+        if tokens.len() < session.cached.len() {
+            tracing::debug!(
+                "Prompt shrank from {} to {} tokens, {} still shared",
+                session.cached.len(),
+                tokens.len(),
+                common
+            );
+        }
+
+        let evicted = common > 0
+            && session
+                .ctx
+                .clear_kv_cache_seq(Some(0), Some(common as u32), None)
+                .unwrap_or(false);
+        if evicted {
+            session.cached.truncate(common);
+        } else {
+            session.ctx.clear_kv_cache();
+            session.cached.clear();
+        }
+
+        Ok(session)
+    }
+
+    /// Exact prompt size: the rendered template run through the model's tokenizer.
+    /// Images are not run through the projector here and are counted at a flat rate.
+    fn count_prompt_tokens(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+    ) -> Result<usize> {
+        let tools = (!tools.is_empty()).then_some(tools);
+        let (template_result, images) = self.apply_template(messages, tools, None)?;
         let tokens = self
             .model
             .str_to_token(&template_result.prompt, AddBos::Never)
             .map_err(|e| anyhow::anyhow!("Tokenization failed: {}", e))?;
+        Ok(tokens.len() + images.len() * IMAGE_TOKEN_ESTIMATE)
+    }
 
-        let n_prompt = tokens.len() as u32;
-        let n_ctx = self.n_ctx.max(n_prompt + self.max_tokens);
+    /// Decode the uncached tail of a text prompt in batches so a long prefill can be cancelled.
+    fn prefill(
+        session: &mut KvSession,
+        tokens: &[LlamaToken],
+        batch: &mut LlamaBatch,
+        n_batch: usize,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let KvSession { ctx, cached, .. } = session;
+        let n_reused = cached.len();
+        tracing::info!("KV cache: reusing {}/{} prompt tokens", n_reused, tokens.len());
 
-        let ctx_params = LlamaContextParams::default()
-            .with_n_ctx(NonZeroU32::new(n_ctx))
-            .with_n_batch(n_ctx);
+        let last_index = tokens.len().saturating_sub(1) as i32;
+        for (chunk_start, chunk) in (n_reused as i32..)
+            .step_by(n_batch)
+            .zip(tokens[n_reused..].chunks(n_batch))
+        {
+            if cancel.is_cancelled() {
+                return Err(AgentError::Cancelled.into());
+            }
+            batch.clear();
+            for (i, token) in (chunk_start..).zip(chunk.iter().copied()) {
+                batch
+                    .add(token, i, &[0], i == last_index)
+                    .map_err(|e| anyhow::anyhow!("Failed to add token to batch: {}", e))?;
+            }
+            ctx.decode(batch)
+                .map_err(|e| anyhow::anyhow!("Initial decode failed: {}", e))?;
+            cached.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
 
-        let mut ctx = self
-            .model
-            .new_context(&self.backend, ctx_params)
-            .map_err(|e| anyhow::anyhow!("Failed to create context: {}", e))?;
+    /// Evaluate a prompt containing media markers through the multimodal projector.
+    ///
+    /// mtmd evaluates the whole prompt at once, so the KV cache is cleared first. Afterwards
+    /// the text chunks are recorded as cached tokens (and media positions as `MEDIA_POSITION`)
+    /// so a later text-only prompt can still reuse the prefix before the first image.
+    /// Returns the session and the number of prompt tokens.
+    fn prefill_media<'s>(
+        &self,
+        slot: &'s mut Option<KvSession>,
+        prompt: &str,
+        images: &[&ImageContent],
+        cancel: &CancelToken,
+    ) -> Result<(&'s mut KvSession, u32)> {
+        let vision = self
+            .vision
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Images require a multimodal projector (mmproj_path)"))?;
+        let mut mtmd = vision.lock();
+
+        let bitmaps = images
+            .iter()
+            .map(|img| {
+                let bytes = BASE64
+                    .decode(&img.base64)
+                    .map_err(|e| anyhow::anyhow!("Invalid base64 image data: {}", e))?;
+                MtmdBitmap::from_buffer(&mtmd, &bytes, false)
+                    .map_err(|e| anyhow::anyhow!("Failed to load {} image: {}", img.media_type, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let bitmap_refs: Vec<&MtmdBitmap> = bitmaps.iter().collect();
 
-        // Feed prompt tokens
-        let mut batch = LlamaBatch::new(n_ctx as usize, 1);
-        let last_index = tokens.len().saturating_sub(1) as i32;
-        for (i, token) in (0_i32..).zip(tokens.iter().copied()) {
-            batch
-                .add(token, i, &[0], i == last_index)
-                .map_err(|e| anyhow::anyhow!("Failed to add token to batch: {}", e))?;
+        let text = MtmdInputText {
+            text: prompt.to_string(),
+            add_special: false,
+            parse_special: true,
+        };
+        let chunks = mtmd
+            .tokenize(text, &bitmap_refs)
+            .map_err(|e| anyhow::anyhow!("Multimodal tokenization failed: {}", e))?;
+        let n_positions = chunks.total_positions().max(0) as usize;
+
+        let session = self.prepare_session(slot, n_positions, &[])?;
+        cancel.check()?;
+        tracing::info!(
+            "Evaluating multimodal prompt: {} images, {} tokens",
+            images.len(),
+            chunks.total_tokens()
+        );
+        let n_past = chunks
+            .eval_chunks(&mut mtmd, &mut session.ctx, 0, 0, self.n_batch as i32, true)
+            .map_err(|e| anyhow::anyhow!("Multimodal decode failed: {}", e))?;
+
+        for chunk in (0..chunks.len()).filter_map(|i| chunks.get(i)) {
+            match chunk.chunk_type() {
+                MtmdInputChunkType::Text => {
+                    session.cached.extend_from_slice(chunk.text_tokens().unwrap_or_default())
+                }
+                _ => session.cached.extend(
+                    std::iter::repeat(MEDIA_POSITION).take(chunk.n_positions().max(0) as usize),
+                ),
+            }
         }
+        session.cached.resize(n_past.max(0) as usize, MEDIA_POSITION);
 
-        ctx.decode(&mut batch)
-            .map_err(|e| anyhow::anyhow!("Initial decode failed: {}", e))?;
+        Ok((session, chunks.total_tokens() as u32))
+    }
+
+    /// Core generation loop. Tokenize, decode, sample until done.
+    /// `images` are the images whose media markers appear in the prompt, in order.
+    /// If `on_delta` is set, answer text is streamed to it token by token; a leading
+    /// `<think>` block is never streamed and is closed early once `thinking_budget` is spent.
+    /// Fails with `AgentError::Cancelled` between prompt batches or tokens once cancelled.
+    /// Returns (answer_text, reasoning, token_usage).
+    fn generate(
+        &self,
+        template_result: &ChatTemplateResult,
+        images: &[&ImageContent],
+        temperature: f32,
+        on_delta: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<(String, Option<String>, TokenUsage)> {
+        let cancel = self.cancel.lock().clone();
+        let mut slot = self.session.lock();
+        let mut batch = LlamaBatch::new(self.n_batch, 1);
+
+        let (KvSession { ctx, cached, .. }, n_prompt) = if images.is_empty() {
+            let tokens = self
+                .model
+                .str_to_token(&template_result.prompt, AddBos::Never)
+                .map_err(|e| anyhow::anyhow!("Tokenization failed: {}", e))?;
+            let session = self.prepare_session(&mut slot, tokens.len(), &tokens)?;
+            Self::prefill(session, &tokens, &mut batch, self.n_batch, &cancel)?;
+            (session, tokens.len() as u32)
+        } else {
+            self.prefill_media(&mut slot, &template_result.prompt, images, &cancel)?
+        };
 
         // Build preserved token set
         let mut preserved = HashSet::new();
@@ -236,16 +599,24 @@ impl LlamaLocalProvider {
         }
 
         // Build sampler
-        let mut sampler = self.build_sampler(template_result, &preserved)?;
+        let mut sampler = self.build_sampler(template_result, &preserved, temperature)?;
 
-        // Generate tokens
-        let mut n_cur = batch.n_tokens();
+        // Generate tokens; thinking and answer tokens have separate limits
+        let mut n_cur = cached.len() as i32;
         let batch_start = n_cur;
-        let max_tokens = n_cur + self.max_tokens as i32;
+        let mut n_thinking = 0u32;
+        let mut n_answer = 0u32;
+        let mut phase = ThinkPhase::initial(&template_result.prompt);
         let mut decoder = encoding_rs::UTF_8.new_decoder();
         let mut generated_text = String::new();
+        let mut stream = on_delta.map(|f| DeltaStream::new(f, template_result));
+
+        while n_answer < self.max_tokens {
+            if cancel.is_cancelled() {
+                tracing::info!("Local generation cancelled after {} tokens", n_cur - batch_start);
+                return Err(AgentError::Cancelled.into());
+            }
 
-        while n_cur <= max_tokens {
             let token = sampler.sample(&ctx, batch.n_tokens() - 1);
 
             if self.model.is_eog_token(token) {
@@ -263,6 +634,25 @@ impl LlamaLocalProvider {
             let _ = decoder.decode_to_string(&output_bytes, &mut output_string, false);
             generated_text.push_str(&output_string);
 
+            let was_answering = matches!(phase, ThinkPhase::Answer(_));
+            phase = phase.advance(&generated_text);
+            // Without thinking enabled the context has no room reserved for a thinking
+            // budget, so a <think> block the model opens on its own counts as answer
+            if phase == ThinkPhase::Thinking && self.enable_thinking {
+                n_thinking += 1;
+            } else {
+                n_answer += 1;
+            }
+
+            if let (Some(stream), ThinkPhase::Answer(start)) = (stream.as_mut(), phase) {
+                if special && was_answering {
+                    // Preserved tokens open tool-call markup — nothing after them is speakable
+                    stream.halt();
+                } else {
+                    stream.push(generated_text[start..].trim_start());
+                }
+            }
+
             // Check additional stop sequences
             if template_result
                 .additional_stops
@@ -272,14 +662,30 @@ impl LlamaLocalProvider {
                 break;
             }
 
+            let mut feed = vec![token];
+            if phase == ThinkPhase::Thinking && n_thinking >= self.thinking_budget {
+                tracing::info!("Thinking budget of {} tokens spent, closing reasoning", self.thinking_budget);
+                let close = self
+                    .model
+                    .str_to_token(THINK_FORCE_CLOSE, AddBos::Never)
+                    .map_err(|e| anyhow::anyhow!("Tokenization failed: {}", e))?;
+                sampler.accept_many(&close);
+                feed.extend(close);
+                generated_text.push_str(THINK_FORCE_CLOSE);
+                phase = phase.advance(&generated_text);
+            }
+
             batch.clear();
-            batch
-                .add(token, n_cur, &[0], true)
-                .map_err(|e| anyhow::anyhow!("Failed to add generated token: {}", e))?;
-            n_cur += 1;
+            for (i, &t) in feed.iter().enumerate() {
+                batch
+                    .add(t, n_cur, &[0], i == feed.len() - 1)
+                    .map_err(|e| anyhow::anyhow!("Failed to add generated token: {}", e))?;
+                n_cur += 1;
+            }
 
             ctx.decode(&mut batch)
                 .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))?;
+            cached.extend_from_slice(&feed);
         }
 
         // Trim stop sequences from end
@@ -291,6 +697,12 @@ impl LlamaLocalProvider {
             }
         }
 
+        let (answer, reasoning) = phase.split(&generated_text);
+
+        if let Some(ref mut stream) = stream {
+            stream.finish(&answer);
+        }
+
         let n_output = (n_cur - batch_start) as u64;
         let usage = TokenUsage {
             input_tokens: n_prompt as u64,
@@ -298,98 +710,251 @@ impl LlamaLocalProvider {
             total_tokens: n_prompt as u64 + n_output,
         };
         tracing::info!(
-            "Local LLM usage: input={}, output={}, total={}",
-            usage.input_tokens, usage.output_tokens, usage.total_tokens
+            "Local LLM usage: input={}, output={} ({} thinking), total={}",
+            usage.input_tokens, usage.output_tokens, n_thinking, usage.total_tokens
         );
 
-        Ok((generated_text, usage))
+        Ok((answer, reasoning, usage))
     }
 
-    /// Build appropriate sampler chain based on template result.
+    /// Build the sampler chain for a template result.
+    ///
+    /// The grammar sampler (if any) goes first so the truncation samplers only ever choose
+    /// among grammar-valid tokens and cannot leave the constrained set empty.
     fn build_sampler(
         &self,
         template_result: &ChatTemplateResult,
         preserved: &HashSet<llama_cpp_2::token::LlamaToken>,
+        temperature: f32,
     ) -> Result<LlamaSampler> {
-        if let Some(ref grammar) = template_result.grammar {
-            if template_result.grammar_lazy && !template_result.grammar_triggers.is_empty() {
-                // Lazy grammar: only activates when triggered
-                let mut trigger_patterns = Vec::new();
-                let mut trigger_tokens = Vec::new();
-
-                for trigger in &template_result.grammar_triggers {
-                    match trigger.trigger_type {
-                        GrammarTriggerType::Token => {
-                            if let Some(token) = trigger.token {
-                                trigger_tokens.push(token);
-                            }
+        let mut samplers: Vec<LlamaSampler> = self
+            .grammar_sampler(template_result, preserved)
+            .into_iter()
+            .collect();
+        samplers.extend(self.sampling_chain(temperature));
+        Ok(LlamaSampler::chain_simple(samplers))
+    }
+
+    /// Grammar sampler for tool calling or structured output, if the template requests one.
+    fn grammar_sampler(
+        &self,
+        template_result: &ChatTemplateResult,
+        preserved: &HashSet<llama_cpp_2::token::LlamaToken>,
+    ) -> Option<LlamaSampler> {
+        let grammar = template_result.grammar.as_ref()?;
+        if template_result.grammar_lazy && !template_result.grammar_triggers.is_empty() {
+            // Lazy grammar: only activates when triggered
+            let mut trigger_patterns = Vec::new();
+            let mut trigger_tokens = Vec::new();
+
+            for trigger in &template_result.grammar_triggers {
+                match trigger.trigger_type {
+                    GrammarTriggerType::Token => {
+                        if let Some(token) = trigger.token {
+                            trigger_tokens.push(token);
                         }
-                        GrammarTriggerType::Word => {
-                            if let Ok(toks) =
-                                self.model.str_to_token(&trigger.value, AddBos::Never)
-                            {
-                                if toks.len() == 1 && preserved.contains(&toks[0]) {
-                                    trigger_tokens.push(toks[0]);
-                                } else {
-                                    trigger_patterns.push(regex_escape(&trigger.value));
-                                }
+                    }
+                    GrammarTriggerType::Word => {
+                        if let Ok(toks) = self.model.str_to_token(&trigger.value, AddBos::Never) {
+                            if toks.len() == 1 && preserved.contains(&toks[0]) {
+                                trigger_tokens.push(toks[0]);
+                            } else {
+                                trigger_patterns.push(regex_escape(&trigger.value));
                             }
                         }
-                        GrammarTriggerType::Pattern => {
-                            trigger_patterns.push(trigger.value.clone());
-                        }
-                        GrammarTriggerType::PatternFull => {
-                            trigger_patterns.push(anchor_pattern(&trigger.value));
-                        }
                     }
-                }
-
-                match LlamaSampler::grammar_lazy_patterns(
-                    &self.model,
-                    grammar,
-                    "root",
-                    &trigger_patterns,
-                    &trigger_tokens,
-                ) {
-                    Ok(grammar_sampler) => {
-                        tracing::debug!("Using lazy grammar sampler");
-                        return Ok(LlamaSampler::chain_simple([
-                            grammar_sampler,
-                            LlamaSampler::temp(self.temperature),
-                            LlamaSampler::dist(1234),
-                        ]));
+                    GrammarTriggerType::Pattern => {
+                        trigger_patterns.push(trigger.value.clone());
                     }
-                    Err(e) => {
-                        tracing::warn!("Lazy grammar sampler failed, falling back: {}", e);
+                    GrammarTriggerType::PatternFull => {
+                        trigger_patterns.push(anchor_pattern(&trigger.value));
                     }
                 }
-            } else {
-                // Strict grammar
-                match LlamaSampler::grammar(&self.model, grammar, "root") {
-                    Ok(grammar_sampler) => {
-                        tracing::debug!("Using strict grammar sampler");
-                        return Ok(LlamaSampler::chain_simple([
-                            grammar_sampler,
-                            LlamaSampler::temp(self.temperature),
-                            LlamaSampler::dist(1234),
-                        ]));
-                    }
-                    Err(e) => {
-                        tracing::warn!("Grammar sampler failed, falling back: {}", e);
-                    }
+            }
+
+            match LlamaSampler::grammar_lazy_patterns(
+                &self.model,
+                grammar,
+                "root",
+                &trigger_patterns,
+                &trigger_tokens,
+            ) {
+                Ok(grammar_sampler) => {
+                    tracing::debug!("Using lazy grammar sampler");
+                    Some(grammar_sampler)
+                }
+                Err(e) => {
+                    tracing::warn!("Lazy grammar sampler failed, falling back: {}", e);
+                    None
+                }
+            }
+        } else {
+            // Strict grammar
+            match LlamaSampler::grammar(&self.model, grammar, "root") {
+                Ok(grammar_sampler) => {
+                    tracing::debug!("Using strict grammar sampler");
+                    Some(grammar_sampler)
+                }
+                Err(e) => {
+                    tracing::warn!("Grammar sampler failed, falling back: {}", e);
+                    None
+                }
+            }
+        }
+    }
+
+    /// Penalties, truncation, temperature and the final token pick, in llama.cpp's order.
+    fn sampling_chain(&self, temperature: f32) -> Vec<LlamaSampler> {
+        let cfg = &self.sampling;
+        let mut chain = Vec::new();
+
+        let repeat = cfg.repeat_penalty.unwrap_or(1.0);
+        let frequency = cfg.frequency_penalty.unwrap_or(0.0);
+        let presence = cfg.presence_penalty.unwrap_or(0.0);
+        if repeat != 1.0 || frequency != 0.0 || presence != 0.0 {
+            chain.push(LlamaSampler::penalties(
+                self.model.n_vocab(),
+                cfg.penalty_last_n.unwrap_or(64),
+                repeat,
+                frequency,
+                presence,
+            ));
+        }
+
+        let dry_multiplier = cfg.dry_multiplier.unwrap_or(0.0);
+        if dry_multiplier > 0.0 {
+            chain.push(LlamaSampler::dry(
+                &self.model,
+                dry_multiplier,
+                cfg.dry_base.unwrap_or(1.75),
+                cfg.dry_allowed_length.unwrap_or(2),
+                cfg.dry_penalty_last_n.unwrap_or(-1),
+                DRY_SEQ_BREAKERS,
+            ));
+        }
+
+        if temperature <= 0.0 {
+            // Deterministic: truncation and randomness are irrelevant
+            chain.push(LlamaSampler::greedy());
+            return chain;
+        }
+
+        chain.push(LlamaSampler::top_k(cfg.top_k.unwrap_or(40)));
+        chain.push(LlamaSampler::top_p(cfg.top_p.unwrap_or(0.95), 1));
+        chain.push(LlamaSampler::min_p(cfg.min_p.unwrap_or(0.05), 1));
+        chain.push(LlamaSampler::temp(temperature));
+        chain.push(LlamaSampler::dist(cfg.seed.unwrap_or(RANDOM_SEED)));
+        chain
+    }
+
+    /// Sampling temperature of a request. The model and reasoning effort of a local
+    /// provider are fixed, so those overrides are ignored.
+    fn temperature(&self, options: &RequestOptions) -> f32 {
+        options.temperature.unwrap_or(self.temperature)
+    }
+
+    /// Plain chat completion, optionally streaming.
+    fn complete(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<(String, TokenUsage)> {
+        let (template_result, images) = self.apply_template(messages, None, None)?;
+
+        tracing::debug!(
+            "Prompt length: {} chars, {} tokens (approx)",
+            template_result.prompt.len(),
+            template_result.prompt.len() / 4
+        );
+
+        let temperature = self.temperature(options);
+        let (text, reasoning, usage) =
+            self.generate(&template_result, &images, temperature, on_delta)?;
+
+        if let Some(reasoning) = reasoning {
+            tracing::debug!("Reasoning: {}", reasoning);
+        }
+        tracing::debug!("Generated: {}", text);
+        Ok((text, usage))
+    }
+
+    /// Schema-constrained completion: the grammar derived from `schema` only admits
+    /// matching JSON, so the output can be parsed directly.
+    fn complete_with_schema(
+        &self,
+        messages: &[ChatMessage],
+        schema: &serde_json::Value,
+        options: &RequestOptions,
+    ) -> Result<String> {
+        let schema_json = serde_json::to_string(schema)?;
+        let (template_result, images) = self.apply_template(messages, None, Some(&schema_json))?;
+
+        if template_result.grammar.is_none() {
+            tracing::warn!("No grammar generated for JSON schema, output is unconstrained");
+        }
+
+        let temperature = self.temperature(options);
+        let (text, _reasoning, _usage) =
+            self.generate(&template_result, &images, temperature, None)?;
+
+        tracing::debug!("Structured output: {}", text);
+        Ok(text.trim().to_string())
+    }
+
+    /// Tool-calling completion, optionally streaming the plain-text part of the output.
+    /// Tool choice "none" renders the prompt without tools; other choices cannot be
+    /// enforced and leave the decision to the model.
+    fn complete_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<LlmResponse> {
+        let tools = (options.tool_choice != ToolChoice::None).then_some(tools);
+        let (template_result, images) = self.apply_template(messages, tools, None)?;
+
+        tracing::debug!(
+            "Prompt: {} chars, grammar: {}, lazy: {}",
+            template_result.prompt.len(),
+            template_result.grammar.is_some(),
+            template_result.grammar_lazy,
+        );
+
+        let temperature = self.temperature(options);
+        let (generated, reasoning, usage) =
+            self.generate(&template_result, &images, temperature, on_delta)?;
+
+        tracing::debug!("Raw generated: {}", generated);
+
+        // Parse response using llama.cpp's built-in parser
+        if template_result.parse_tool_calls {
+            match template_result.parse_response_oaicompat(&generated, false) {
+                Ok(parsed_json) => {
+                    tracing::debug!("Parsed OAI response: {}", parsed_json);
+                    return Self::parse_oai_response_with_usage(&parsed_json, reasoning, usage);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse tool calls, returning as text: {}", e);
                 }
             }
         }
 
-        // Fallback: no grammar
-        Ok(LlamaSampler::chain_simple([
-            LlamaSampler::temp(self.temperature),
-            LlamaSampler::dist(1234),
-        ]))
+        Ok(LlmResponse::Text {
+            content: generated,
+            reasoning,
+            usage: Some(usage),
+        })
     }
 
     /// Parse the OpenAI-compatible JSON from parse_response_oaicompat into LlmResponse.
-    fn parse_oai_response_with_usage(json_str: &str, usage: TokenUsage) -> Result<LlmResponse> {
+    /// `reasoning` is the already-extracted `<think>` content, if any.
+    fn parse_oai_response_with_usage(
+        json_str: &str,
+        reasoning: Option<String>,
+        usage: TokenUsage,
+    ) -> Result<LlmResponse> {
         let parsed: serde_json::Value = serde_json::from_str(json_str)
             .map_err(|e| anyhow::anyhow!("Failed to parse OAI response JSON: {}", e))?;
 
@@ -433,26 +998,54 @@ impl LlamaLocalProvider {
 
         Ok(LlmResponse::Text {
             content,
-            reasoning: None,
+            reasoning,
             usage: Some(usage),
         })
     }
 }
 
 impl LlmProvider for LlamaLocalProvider {
-    fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
-        let template_result = self.apply_template(messages, None)?;
+    fn set_cancel_token(&self, token: CancelToken) {
+        *self.cancel.lock() = token;
+    }
 
-        tracing::debug!(
-            "Prompt length: {} chars, {} tokens (approx)",
-            template_result.prompt.len(),
-            template_result.prompt.len() / 4
-        );
+    fn count_tokens(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        self.count_prompt_tokens(messages, tools).unwrap_or_else(|e| {
+            tracing::warn!("Token count failed, estimating: {}", e);
+            TokenEstimator::default().count_request(messages, tools)
+        })
+    }
 
-        let (text, _usage) = self.generate(&template_result)?;
+    fn context_size(&self) -> Option<u32> {
+        Some(self.n_ctx)
+    }
 
-        tracing::debug!("Generated: {}", text);
-        Ok(text)
+    fn chat(&self, messages: &[ChatMessage], options: &RequestOptions) -> Result<String> {
+        self.complete(messages, options, None).map(|(text, _)| text)
+    }
+
+    fn chat_streaming(
+        &self,
+        messages: &[ChatMessage],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let (text, usage) = self.complete(messages, options, Some(on_delta))?;
+        Ok((text, Some(usage)))
+    }
+
+    fn chat_with_schema(
+        &self,
+        messages: &[ChatMessage],
+        schema: serde_json::Value,
+        _schema_name: &str,
+        options: &RequestOptions,
+    ) -> Result<String> {
+        self.complete_with_schema(messages, &schema, options)
+    }
+
+    fn supports_structured_output(&self) -> bool {
+        true
     }
 
     fn supports_tools(&self) -> bool {
@@ -463,41 +1056,256 @@ impl LlmProvider for LlamaLocalProvider {
         &self,
         messages: &[ChatMessage],
         tools: &[ToolDefinition],
+        options: &RequestOptions,
     ) -> Result<LlmResponse> {
-        let template_result = self.apply_template(messages, Some(tools))?;
+        self.complete_with_tools(messages, tools, options, None)
+    }
 
-        tracing::debug!(
-            "Prompt: {} chars, grammar: {}, lazy: {}",
-            template_result.prompt.len(),
-            template_result.grammar.is_some(),
-            template_result.grammar_lazy,
-        );
+    fn chat_with_tools_streaming(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        options: &RequestOptions,
+        on_delta: &mut dyn FnMut(&str),
+    ) -> Result<LlmResponse> {
+        self.complete_with_tools(messages, tools, options, Some(on_delta))
+    }
+}
 
-        let (generated, usage) = self.generate(&template_result)?;
+/// Where generation is relative to a leading `<think>` block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ThinkPhase {
+    /// The output so far could still be the start of `<think>`
+    Pending,
+    /// Inside the reasoning block
+    Thinking,
+    /// The answer starts at this byte offset of the output
+    Answer(usize),
+}
 
-        tracing::debug!("Raw generated: {}", generated);
+impl ThinkPhase {
+    /// Templates with thinking enabled may open the block in the generation prompt
+    fn initial(prompt: &str) -> Self {
+        if prompt.trim_end().ends_with(THINK_OPEN) {
+            ThinkPhase::Thinking
+        } else {
+            ThinkPhase::Pending
+        }
+    }
 
-        // Parse response using llama.cpp's built-in parser
-        if template_result.parse_tool_calls {
-            match template_result.parse_response_oaicompat(&generated, false) {
-                Ok(parsed_json) => {
-                    tracing::debug!("Parsed OAI response: {}", parsed_json);
-                    return Self::parse_oai_response_with_usage(&parsed_json, usage);
+    /// Re-evaluate after `text` (the full output so far) grew.
+    fn advance(self, text: &str) -> Self {
+        match self {
+            ThinkPhase::Pending => {
+                let trimmed = text.trim_start();
+                if trimmed.starts_with(THINK_OPEN) {
+                    ThinkPhase::Thinking.advance(text)
+                } else if THINK_OPEN.starts_with(trimmed) {
+                    ThinkPhase::Pending
+                } else {
+                    ThinkPhase::Answer(0)
                 }
+            }
+            ThinkPhase::Thinking => match text.find(THINK_CLOSE) {
+                Some(pos) => ThinkPhase::Answer(pos + THINK_CLOSE.len()),
+                None => ThinkPhase::Thinking,
+            },
+            answer => answer,
+        }
+    }
+
+    /// Split the final output into (answer, reasoning).
+    fn split(self, text: &str) -> (String, Option<String>) {
+        let (thought, answer) = match self {
+            ThinkPhase::Pending => return (text.to_string(), None),
+            // Generation stopped mid-thought: there is no answer
+            ThinkPhase::Thinking => (text, ""),
+            ThinkPhase::Answer(start) => text.split_at(start),
+        };
+        let thought = thought.trim();
+        let thought = thought.strip_prefix(THINK_OPEN).unwrap_or(thought);
+        let thought = thought.strip_suffix(THINK_CLOSE).unwrap_or(thought).trim();
+        let reasoning = (!thought.is_empty()).then(|| thought.to_string());
+        (answer.trim_start().to_string(), reasoning)
+    }
+}
+
+/// Characters that can open tool-call markup matched by a pattern trigger
+/// (`{"name": ...`, `<function=...>`, `[TOOL_CALLS]`, fenced JSON, `>>>name`).
+const TOOL_CALL_OPENERS: &[char] = &['{', '[', '<', '`', '>'];
+
+/// Forwards generated text to a streaming callback as it is produced.
+///
+/// Any tail that could be the start of a stop sequence or a tool-call trigger word is held
+/// back until it is disambiguated, so template markup never reaches the listener. Regex
+/// triggers cannot be matched against a prefix, so when the template has any, text from
+/// the first character that could open a tool call is held back until the output ends.
+/// Once a trigger appears (or a preserved special token is sampled) streaming stops for
+/// this call.
+struct DeltaStream<'a> {
+    on_delta: &'a mut dyn FnMut(&str),
+    markers: Vec<String>,
+    patterns: Vec<Regex>,
+    has_patterns: bool,
+    emitted: usize,
+    halted: bool,
+}
+
+impl<'a> DeltaStream<'a> {
+    fn new(on_delta: &'a mut dyn FnMut(&str), template_result: &ChatTemplateResult) -> Self {
+        let mut markers: Vec<String> = template_result
+            .additional_stops
+            .iter()
+            .chain(template_result.preserved_tokens.iter())
+            .cloned()
+            .collect();
+        markers.extend(
+            template_result
+                .grammar_triggers
+                .iter()
+                .filter(|t| matches!(t.trigger_type, GrammarTriggerType::Word))
+                .map(|t| t.value.clone()),
+        );
+        let patterns: Vec<String> = template_result
+            .grammar_triggers
+            .iter()
+            .filter_map(|trigger| match trigger.trigger_type {
+                GrammarTriggerType::Pattern => Some(trigger.value.clone()),
+                // Must match the whole output, as in llama.cpp
+                GrammarTriggerType::PatternFull => Some(format!(r"\A(?:{})\z", trigger.value)),
+                _ => None,
+            })
+            .collect();
+        Self::with_triggers(on_delta, markers, &patterns)
+    }
+
+    /// Stream holding back `markers` (literal) and `patterns` (regexes, searched anywhere)
+    fn with_triggers(
+        on_delta: &'a mut dyn FnMut(&str),
+        mut markers: Vec<String>,
+        patterns: &[String],
+    ) -> Self {
+        markers.retain(|m| !m.is_empty());
+        let has_patterns = !patterns.is_empty();
+        let patterns = patterns
+            .iter()
+            .filter_map(|source| match Regex::new(source) {
+                Ok(re) => Some(re),
                 Err(e) => {
-                    tracing::warn!("Failed to parse tool calls, returning as text: {}", e);
+                    tracing::warn!(
+                        "Tool-call trigger pattern {:?} not supported for streaming: {}",
+                        source,
+                        e
+                    );
+                    None
                 }
+            })
+            .collect();
+
+        Self {
+            on_delta,
+            markers,
+            patterns,
+            has_patterns,
+            emitted: 0,
+            halted: false,
+        }
+    }
+
+    /// Where tool-call markup starts in `text`, if a trigger has appeared
+    fn trigger_start(&self, text: &str) -> Option<usize> {
+        let pending = &text[self.emitted..];
+        let word = self
+            .markers
+            .iter()
+            .filter_map(|m| pending.find(m.as_str()))
+            .map(|pos| self.emitted + pos);
+        // The first capture group marks the start of the tool call, as in llama.cpp
+        let pattern = self.patterns.iter().filter_map(|re| {
+            let caps = re.captures(text)?;
+            caps.get(1).or_else(|| caps.get(0)).map(|m| m.start())
+        });
+        word.chain(pattern).min().map(|pos| pos.max(self.emitted))
+    }
+
+    /// Stream whatever part of `text` (the full output so far) is now known to be plain text.
+    fn push(&mut self, text: &str) {
+        if self.halted {
+            return;
+        }
+        if let Some(pos) = self.trigger_start(text) {
+            self.emit(text, pos);
+            self.halted = true;
+            return;
+        }
+
+        let pending = &text[self.emitted..];
+        let mut hold = self
+            .markers
+            .iter()
+            .map(|m| held_back_len(pending, m))
+            .max()
+            .unwrap_or(0);
+        if self.has_patterns {
+            if let Some(pos) = pending.find(TOOL_CALL_OPENERS) {
+                hold = hold.max(pending.len() - pos);
             }
         }
+        self.emit(text, text.len() - hold);
+    }
 
-        Ok(LlmResponse::Text {
-            content: generated,
-            reasoning: None,
-            usage: Some(usage),
-        })
+    fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Flush the remainder once generation (and stop-sequence trimming) is done.
+    fn finish(&mut self, text: &str) {
+        if self.halted {
+            return;
+        }
+        let end = self.trigger_start(text).unwrap_or(text.len());
+        self.emit(text, end);
+    }
+
+    fn emit(&mut self, text: &str, end: usize) {
+        if end > self.emitted {
+            (self.on_delta)(&text[self.emitted..end]);
+            self.emitted = end;
+        }
     }
 }
 
+/// Length of the longest suffix of `text` that is a proper prefix of `marker`.
+fn held_back_len(text: &str, marker: &str) -> usize {
+    let max = marker.len().saturating_sub(1).min(text.len());
+    (1..=max)
+        .rev()
+        .find(|&n| {
+            let start = text.len() - n;
+            text.is_char_boundary(start) && marker.as_bytes().starts_with(&text.as_bytes()[start..])
+        })
+        .unwrap_or(0)
+}
+
+/// Map a `LocalRuntimeConfig::kv_cache_type` name to the ggml type.
+fn parse_kv_cache_type(name: &str) -> Result<KvCacheType> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "f32" => KvCacheType::F32,
+        "f16" => KvCacheType::F16,
+        "bf16" => KvCacheType::BF16,
+        "q8_0" => KvCacheType::Q8_0,
+        "q5_1" => KvCacheType::Q5_1,
+        "q5_0" => KvCacheType::Q5_0,
+        "q4_1" => KvCacheType::Q4_1,
+        "q4_0" => KvCacheType::Q4_0,
+        "iq4_nl" => KvCacheType::IQ4_NL,
+        other => anyhow::bail!(
+            "Unknown kv_cache_type '{}'. Expected one of: f32, f16, bf16, q8_0, q5_1, q5_0, q4_1, q4_0, iq4_nl",
+            other
+        ),
+    })
+}
+
 fn regex_escape(value: &str) -> String {
     let mut escaped = String::with_capacity(value.len());
     for ch in value.chars() {
@@ -526,3 +1334,162 @@ fn anchor_pattern(pattern: &str) -> String {
     }
     anchored
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `pieces` one at a time, as generated tokens, returning the final phase
+    fn advance_all(mut phase: ThinkPhase, pieces: &[&str]) -> (ThinkPhase, String) {
+        let mut text = String::new();
+        for piece in pieces {
+            text.push_str(piece);
+            phase = phase.advance(&text);
+        }
+        (phase, text)
+    }
+
+    #[test]
+    fn test_think_opener_split_across_tokens() {
+        let (phase, _) = advance_all(ThinkPhase::Pending, &["\n<th"]);
+        assert_eq!(phase, ThinkPhase::Pending);
+
+        let (phase, text) = advance_all(
+            ThinkPhase::Pending,
+            &["\n<th", "ink>", "Check the ", "date.</th", "ink>\n\n", "It's Monday."],
+        );
+        assert!(matches!(phase, ThinkPhase::Answer(_)));
+        assert_eq!(
+            phase.split(&text),
+            ("It's Monday.".to_string(), Some("Check the date.".to_string()))
+        );
+
+        // Output that cannot become "<think>" is the answer from the start
+        let (phase, text) = advance_all(ThinkPhase::Pending, &["<b>", "Hi"]);
+        assert_eq!(phase, ThinkPhase::Answer(0));
+        assert_eq!(phase.split(&text), ("<b>Hi".to_string(), None));
+    }
+
+    #[test]
+    fn test_think_opener_in_prompt() {
+        let phase = ThinkPhase::initial("<|im_start|>assistant\n<think>\n");
+        assert_eq!(phase, ThinkPhase::Thinking);
+        assert_eq!(ThinkPhase::initial("<|im_start|>assistant\n"), ThinkPhase::Pending);
+
+        let (phase, text) = advance_all(phase, &["Easy.", "</think>", "\n\nFour."]);
+        assert_eq!(
+            phase.split(&text),
+            ("Four.".to_string(), Some("Easy.".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_generation_stopped_mid_thought() {
+        let (phase, text) = advance_all(ThinkPhase::Pending, &["<think>", "Still going"]);
+        assert_eq!(phase, ThinkPhase::Thinking);
+        assert_eq!(
+            phase.split(&text),
+            (String::new(), Some("Still going".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_forced_close_after_budget() {
+        let (phase, mut text) = advance_all(ThinkPhase::Pending, &["<think>", "Long reasoning"]);
+        assert_eq!(phase, ThinkPhase::Thinking);
+
+        // What `generate` appends when the thinking budget is spent
+        text.push_str(THINK_FORCE_CLOSE);
+        let phase = phase.advance(&text);
+        assert!(matches!(phase, ThinkPhase::Answer(_)));
+
+        text.push_str("Short answer.");
+        assert_eq!(
+            phase.advance(&text).split(&text),
+            ("Short answer.".to_string(), Some("Long reasoning".to_string()))
+        );
+    }
+
+    /// Run a `DeltaStream` over `pieces` (generated tokens), returning the deltas.
+    /// The piece at `preserved` is a special token, which halts the stream as in `generate`.
+    fn stream(
+        markers: &[&str],
+        patterns: &[&str],
+        pieces: &[&str],
+        preserved: Option<usize>,
+    ) -> Vec<String> {
+        let mut deltas = Vec::new();
+        let mut on_delta = |d: &str| deltas.push(d.to_string());
+        let markers = markers.iter().map(|m| m.to_string()).collect();
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let mut stream = DeltaStream::with_triggers(&mut on_delta, markers, &patterns);
+        let mut text = String::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            text.push_str(piece);
+            if preserved == Some(i) {
+                stream.halt();
+            } else {
+                stream.push(&text);
+            }
+        }
+        stream.finish(&text);
+        deltas
+    }
+
+    #[test]
+    fn test_held_back_len() {
+        assert_eq!(held_back_len("Hi <too", "<tool_call>"), 4);
+        assert_eq!(held_back_len("Hi <", "<tool_call>"), 1);
+        assert_eq!(held_back_len("Hi", "<tool_call>"), 0);
+        // Never the whole marker; multi-byte prefixes are held whole
+        assert_eq!(held_back_len("<a>", "<a>"), 0);
+        assert_eq!(held_back_len("日本", "本>"), 3);
+    }
+
+    #[test]
+    fn test_delta_stream_holds_back_trigger_words() {
+        let pieces = ["Let me ", "check. <too", "l_call>{\"name\""];
+        let deltas = stream(&["<tool_call>"], &[], &pieces, None);
+        assert_eq!(deltas, ["Let me ", "check. "]);
+
+        // A partial marker that turns out to be text is released
+        let deltas = stream(&["<tool_call>"], &[], &["a <t", "iny> b"], None);
+        assert_eq!(deltas.concat(), "a <tiny> b");
+    }
+
+    #[test]
+    fn test_delta_stream_holds_back_tool_call_openers() {
+        let patterns = [r#"(\{\s*"name"\s*:\s*")[\s\S]*"#];
+
+        // Held from the opener until the pattern matches, then halted there
+        let pieces = ["Sure. ", "{", "\"name\": \"", "read\"}"];
+        let deltas = stream(&[], &patterns, &pieces, None);
+        assert_eq!(deltas, ["Sure. "]);
+
+        // Held until the end when no tool call follows; finish() flushes the tail
+        let deltas = stream(&[], &patterns, &["a ", "< b", " is true"], None);
+        assert_eq!(deltas, ["a ", "< b is true"]);
+
+        // Without pattern triggers nothing is held
+        let deltas = stream(&[], &[], &["a ", "< b"], None);
+        assert_eq!(deltas, ["a ", "< b"]);
+    }
+
+    #[test]
+    fn test_delta_stream_pattern_full_trigger() {
+        let patterns = [r#"\A(?:(\{"name")[\s\S]*)\z"#];
+        let deltas = stream(&[], &patterns, &["{\"na", "me\": \"read\"}"], None);
+        assert!(deltas.is_empty());
+
+        // The same JSON after text is not a full-output match: it is released at the end
+        let deltas = stream(&[], &patterns, &["See ", "{\"name\"}"], None);
+        assert_eq!(deltas.concat(), "See {\"name\"}");
+    }
+
+    #[test]
+    fn test_delta_stream_halt_after_preserved_token() {
+        // Nothing after the token is streamed, even text no trigger would hold back
+        let deltas = stream(&[], &[], &["Hello", " world", "<|tool|>", " more"], Some(2));
+        assert_eq!(deltas, ["Hello", " world"]);
+    }
+}
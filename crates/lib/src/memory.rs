@@ -1,13 +1,168 @@
-use crate::llm::{ChatMessage, ChatRole};
+use std::ops::Range;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::history;
+use crate::llm::{ChatMessage, ChatRole, TokenUsage};
 
 /// Backchannel marker in message history
 const BACKCHANNEL_MARKER: &str = "⟂";
 
-/// Message with backchannel flag
+/// Heading of the system note that carries the rolling summary of compacted messages
+const SUMMARY_HEADING: &str = "Conversation so far (older messages, summarised):";
+
+/// Tool results longer than this are cut when building a summarisation transcript
+const SUMMARY_TOOL_RESULT_CHARS: usize = 500;
+
+/// Tool results kept in "summarized" mode are cut to this many characters
+const SUMMARIZED_TOOL_RESULT_CHARS: usize = 300;
+
+/// Turns whose tool trace "last_turns" mode keeps when `last_turns` is unset
+const DEFAULT_TOOL_TURNS: u32 = 3;
+
+/// How the agent compacts memory when a request approaches the context window
+#[derive(Debug, Clone, Default)]
+pub struct CompactionConfig {
+    /// Condense dropped messages into a rolling summary note instead of discarding them.
+    /// Falls back to plain dropping if the summarisation call fails.
+    pub summarize: bool,
+    /// Fraction of the context window a request may fill before compacting (default 0.9)
+    pub trigger_ratio: Option<f32>,
+    /// Fraction of the context window the request is compacted down to (default 0.5)
+    pub target_ratio: Option<f32>,
+}
+
+/// What the agent keeps of each turn's tool calls and results for later turns
+#[derive(Debug, Clone, Default)]
+pub struct ToolMemoryConfig {
+    /// "full" (default) keeps every call and result; "summarized" keeps the calls with
+    /// results cut short and images dropped; "last_turns" keeps the full trace of the
+    /// most recent turns only; "off" keeps just the final answers
+    pub mode: Option<String>,
+    /// Turns whose trace "last_turns" keeps (default 3)
+    pub last_turns: Option<u32>,
+}
+
+/// Parsed `ToolMemoryConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolRetention {
+    Off,
+    Full,
+    Summarized,
+    LastTurns(usize),
+}
+
+impl ToolMemoryConfig {
+    pub fn retention(&self) -> Result<ToolRetention, String> {
+        match self.mode.as_deref().unwrap_or("full") {
+            "full" => Ok(ToolRetention::Full),
+            "summarized" | "summarised" => Ok(ToolRetention::Summarized),
+            "last_turns" => Ok(ToolRetention::LastTurns(
+                self.last_turns.unwrap_or(DEFAULT_TOOL_TURNS) as usize,
+            )),
+            "off" => Ok(ToolRetention::Off),
+            other => Err(format!(
+                "Unknown tool memory mode '{}' (expected full, summarized, last_turns or off)",
+                other
+            )),
+        }
+    }
+}
+
+/// When and how a message was produced. Replies the agent generated also carry the
+/// provider, model, token usage and latencies of their turn.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    /// Wall-clock time the message was added (ms since the Unix epoch)
+    pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    /// Time spent waiting for the model, tool calls excluded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm_latency_ms: Option<u64>,
+    /// Time from the request to the first streamed text (streaming steps only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_token_ms: Option<u64>,
+    /// Time spent running tools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_latency_ms: Option<u64>,
+    /// The user spoke over the reply before it finished
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+impl MessageMetadata {
+    /// Metadata stamped with the current time and nothing else
+    pub fn now() -> Self {
+        Self {
+            timestamp_ms: now_ms(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Token usage and latency totals over the replies in memory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversationStats {
+    /// Replies that carry usage or latency metadata
+    pub turns: u32,
+    pub interrupted_turns: u32,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_llm_latency_ms: u64,
+    pub total_tool_latency_ms: u64,
+    pub avg_llm_latency_ms: u64,
+    pub avg_tool_latency_ms: u64,
+    /// Average over the streamed replies
+    pub avg_first_token_ms: Option<u64>,
+}
+
+/// Message with backchannel flag and metadata
 #[derive(Debug, Clone)]
 struct MessageEntry {
     message: ChatMessage,
     is_backchannel: bool,
+    metadata: MessageMetadata,
+}
+
+/// One exchange removed by `ConversationMemory::pop_turn`, backchannel markers included
+#[derive(Debug, Clone)]
+pub struct Turn {
+    entries: Vec<MessageEntry>,
+}
+
+impl Turn {
+    /// The user message that started the turn
+    pub fn user_message(&self) -> &ChatMessage {
+        self.entries
+            .iter()
+            .find(|e| !e.is_backchannel && e.message.role == ChatRole::User)
+            .map(|e| &e.message)
+            .expect("a turn starts with a user message")
+    }
+
+    /// Messages of the turn without backchannel markers, oldest first
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        self.entries
+            .iter()
+            .filter(|e| !e.is_backchannel)
+            .map(|e| e.message.clone())
+            .collect()
+    }
+
+    /// Like `messages`, with each message's metadata
+    pub fn messages_with_metadata(&self) -> Vec<(ChatMessage, MessageMetadata)> {
+        self.entries
+            .iter()
+            .filter(|e| !e.is_backchannel)
+            .map(|e| (e.message.clone(), e.metadata.clone()))
+            .collect()
+    }
 }
 
 /// Conversation memory manager.
@@ -15,6 +170,8 @@ struct MessageEntry {
 pub struct ConversationMemory {
     messages: Vec<MessageEntry>,
     max_messages: usize,
+    /// Rolling summary of compacted messages, kept apart from the system prompt
+    summary: Option<String>,
 }
 
 impl ConversationMemory {
@@ -28,75 +185,274 @@ impl ConversationMemory {
         Self {
             messages: Vec::new(),
             max_messages,
+            summary: None,
         }
     }
 
     /// Add a regular message to the conversation
     pub fn add_message(&mut self, message: ChatMessage) {
+        self.add_message_with_metadata(message, MessageMetadata::now());
+    }
+
+    /// Add a regular message with how it was produced
+    pub fn add_message_with_metadata(&mut self, message: ChatMessage, metadata: MessageMetadata) {
         self.messages.push(MessageEntry {
             message,
             is_backchannel: false,
+            metadata,
         });
         self.trim_messages();
     }
 
+    /// Remove and return the most recent entry (regular message or backchannel marker)
+    pub fn pop_message(&mut self) -> Option<ChatMessage> {
+        self.messages.pop().map(|e| e.message)
+    }
+
+    /// Remove the last turn: the last user message with everything after it, and the
+    /// backchannel markers from while the user was speaking it. Markers after the last
+    /// message belong to the next utterance and stay.
+    pub fn pop_turn(&mut self) -> Option<Turn> {
+        let user = self
+            .messages
+            .iter()
+            .rposition(|e| !e.is_backchannel && e.message.role == ChatRole::User)?;
+        let mut start = user;
+        while start > 0 && self.messages[start - 1].is_backchannel {
+            start -= 1;
+        }
+        let end = self.messages.iter().rposition(|e| !e.is_backchannel)? + 1;
+        Some(Turn {
+            entries: self.messages.drain(start..end).collect(),
+        })
+    }
+
+    /// Put back a turn removed by `pop_turn`
+    pub fn restore_turn(&mut self, turn: Turn) {
+        self.messages.extend(turn.entries);
+        self.trim_messages();
+    }
+
     /// Add a backchannel marker to the conversation
     /// This is for tempo tracking only — doesn't pollute context
     pub fn add_backchannel(&mut self) {
         self.messages.push(MessageEntry {
             message: ChatMessage::assistant(BACKCHANNEL_MARKER.to_string()),
             is_backchannel: true,
+            metadata: MessageMetadata::now(),
         });
         self.trim_messages();
     }
 
-    /// Trim messages to max capacity
+    /// Trim messages to max capacity, dropping the oldest non-system entries.
+    /// A tool call and its results go together.
     fn trim_messages(&mut self) {
-        if self.messages.len() > self.max_messages {
-            // Keep system messages at the beginning
-            let system_messages: Vec<_> = self
-                .messages
-                .iter()
-                .filter(|e| e.message.role == ChatRole::System)
-                .cloned()
-                .collect();
+        let mut excess = self.messages.len().saturating_sub(self.max_messages);
+        if excess == 0 {
+            return;
+        }
+        let mut keep = vec![true; self.messages.len()];
+        for unit in self.units() {
+            if excess == 0 {
+                break;
+            }
+            if self.messages[unit.start].message.role == ChatRole::System {
+                continue;
+            }
+            excess = excess.saturating_sub(unit.len());
+            keep[unit].fill(false);
+        }
+        self.retain_entries(&keep);
+    }
 
-            // Calculate how many non-system messages to keep
-            let non_system_to_keep = self.max_messages.saturating_sub(system_messages.len());
+    /// Entry ranges that are kept or dropped as a whole: an assistant tool-call message
+    /// with the tool results that follow it, or a single other entry.
+    fn units(&self) -> Vec<Range<usize>> {
+        let mut units = Vec::new();
+        let mut i = 0;
+        while i < self.messages.len() {
+            let start = i;
+            i += 1;
+            if self.messages[start].message.tool_calls.is_some() {
+                while i < self.messages.len() && self.messages[i].message.role == ChatRole::Tool {
+                    i += 1;
+                }
+            }
+            units.push(start..i);
+        }
+        units
+    }
 
-            // Get all non-system messages
-            let all_non_system: Vec<_> = self
-                .messages
-                .iter()
-                .filter(|e| e.message.role != ChatRole::System)
-                .cloned()
-                .collect();
+    fn retain_entries(&mut self, keep: &[bool]) {
+        let mut keep = keep.iter();
+        self.messages.retain(|_| *keep.next().unwrap_or(&true));
+    }
 
-            // Keep the last N non-system messages
-            let total_non_system = all_non_system.len();
-            let non_system_messages: Vec<_> = if total_non_system > non_system_to_keep {
-                all_non_system
-                    .into_iter()
-                    .skip(total_non_system - non_system_to_keep)
-                    .collect()
-            } else {
-                all_non_system
-            };
+    /// Add the assistant tool calls and tool results of the turn being answered, as
+    /// `retention` allows. Returns the messages added.
+    pub fn add_tool_trace(
+        &mut self,
+        trace: Vec<ChatMessage>,
+        retention: ToolRetention,
+    ) -> Vec<ChatMessage> {
+        let trace: Vec<ChatMessage> = match retention {
+            ToolRetention::Off => Vec::new(),
+            ToolRetention::Full | ToolRetention::LastTurns(_) => trace,
+            ToolRetention::Summarized => trace.into_iter().map(summarize_tool_message).collect(),
+        };
+        for message in &trace {
+            self.add_message(message.clone());
+        }
+        trace
+    }
 
-            self.messages = system_messages;
-            self.messages.extend(non_system_messages);
+    /// Remove tool calls and results from all but the last `keep_turns` turns (a turn
+    /// starts at a user message). Text an assistant sent alongside its calls is kept.
+    /// Returns the number of messages removed.
+    pub fn prune_tool_turns(&mut self, keep_turns: usize) -> usize {
+        let turn_starts: Vec<usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.is_backchannel && e.message.role == ChatRole::User)
+            .map(|(i, _)| i)
+            .collect();
+        if turn_starts.len() <= keep_turns {
+            return 0;
         }
+        let cutoff = turn_starts[turn_starts.len() - keep_turns];
+
+        let before = self.messages.len();
+        let mut index = 0;
+        self.messages.retain_mut(|e| {
+            let old = index < cutoff;
+            index += 1;
+            if !old {
+                return true;
+            }
+            if e.message.role == ChatRole::Tool {
+                return false;
+            }
+            if e.message.tool_calls.take().is_some() {
+                return !e.message.content.is_empty();
+            }
+            true
+        });
+        before - self.messages.len()
+    }
+
+    /// Drop the `count` oldest non-system messages, as a compaction did before.
+    /// Used when replaying a stored session.
+    pub fn drop_oldest(&mut self, mut count: usize) {
+        self.messages.retain(|e| {
+            if count > 0 && !e.is_backchannel && e.message.role != ChatRole::System {
+                count -= 1;
+                false
+            } else {
+                true
+            }
+        });
     }
 
-    /// Get all messages (excluding backchannel markers by default)
+    /// Drop tool calls that have no result and results whose call is gone, e.g. after a
+    /// session file was cut short mid-turn. Providers reject either.
+    pub fn repair_tool_pairs(&mut self) {
+        let keep = history::repair_tool_pairs_mask(
+            self.messages.iter_mut().map(|e| &mut e.message).collect(),
+        );
+        self.retain_entries(&keep);
+    }
+
+    /// Get all messages (excluding backchannel markers by default).
+    /// The summary note, if any, follows the leading system messages.
     pub fn get_messages(&self) -> Vec<ChatMessage> {
-        self.messages
+        let mut messages: Vec<ChatMessage> = self
+            .messages
             .iter()
             .filter(|e| !e.is_backchannel)
             .map(|e| e.message.clone())
+            .collect();
+        if let Some(ref summary) = self.summary {
+            let pos = messages
+                .iter()
+                .position(|m| m.role != ChatRole::System)
+                .unwrap_or(messages.len());
+            messages.insert(
+                pos,
+                ChatMessage::system(format!("{}\n{}", SUMMARY_HEADING, summary)),
+            );
+        }
+        messages
+    }
+
+    /// Messages (excluding backchannel markers and the summary note) with their metadata
+    pub fn get_messages_with_metadata(&self) -> Vec<(ChatMessage, MessageMetadata)> {
+        self.messages
+            .iter()
+            .filter(|e| !e.is_backchannel)
+            .map(|e| (e.message.clone(), e.metadata.clone()))
             .collect()
     }
 
+    /// Flag the latest assistant reply as interrupted. Returns false if there is none.
+    pub fn mark_last_reply_interrupted(&mut self) -> bool {
+        let last = self.messages.iter_mut().rev().find(|e| {
+            !e.is_backchannel
+                && e.message.role == ChatRole::Assistant
+                && e.message.tool_calls.is_none()
+        });
+        match last {
+            Some(entry) => {
+                entry.metadata.interrupted = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Token usage and latency totals over the replies still in memory
+    pub fn stats(&self) -> ConversationStats {
+        let mut stats = ConversationStats::default();
+        let mut first_token = (0u64, 0u64);
+        for m in self
+            .messages
+            .iter()
+            .filter(|e| !e.is_backchannel)
+            .map(|e| &e.metadata)
+        {
+            if m.usage.is_none() && m.llm_latency_ms.is_none() {
+                continue;
+            }
+            stats.turns += 1;
+            stats.interrupted_turns += u32::from(m.interrupted);
+            if let Some(ref usage) = m.usage {
+                stats.input_tokens += usage.input_tokens;
+                stats.output_tokens += usage.output_tokens;
+            }
+            stats.total_llm_latency_ms += m.llm_latency_ms.unwrap_or(0);
+            stats.total_tool_latency_ms += m.tool_latency_ms.unwrap_or(0);
+            if let Some(ms) = m.first_token_ms {
+                first_token = (first_token.0 + ms, first_token.1 + 1);
+            }
+        }
+        if stats.turns > 0 {
+            stats.avg_llm_latency_ms = stats.total_llm_latency_ms / u64::from(stats.turns);
+            stats.avg_tool_latency_ms = stats.total_tool_latency_ms / u64::from(stats.turns);
+        }
+        stats.avg_first_token_ms = first_token.0.checked_div(first_token.1);
+        stats
+    }
+
+    /// Rolling summary of compacted messages
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    /// Replace the rolling summary of compacted messages
+    pub fn set_summary(&mut self, summary: String) {
+        self.summary = Some(summary);
+    }
+
     /// Get all messages including backchannel markers
     pub fn get_messages_with_backchannels(&self) -> Vec<ChatMessage> {
         self.messages.iter().map(|e| e.message.clone()).collect()
@@ -128,26 +484,70 @@ impl ConversationMemory {
     /// Drop oldest non-system messages until estimated tokens < `target_tokens`.
     /// Returns the number of messages dropped.
     pub fn compact(&mut self, target_tokens: usize) -> usize {
-        let mut dropped = 0;
-        while self.estimate_tokens() > target_tokens {
-            // Find the first non-system, non-backchannel message
-            let pos = self.messages.iter().position(|e| {
-                !e.is_backchannel && e.message.role != ChatRole::System
-            });
-            match pos {
-                Some(i) => {
-                    self.messages.remove(i);
-                    dropped += 1;
+        self.compact_with(target_tokens, |m| m.content.len() / 4 + 10)
+    }
+
+    /// Like `compact`, but with each message's size given by `count_tokens` (e.g. the
+    /// provider's tokenizer). Each message is counted once. The newest message is never
+    /// dropped, so the turn being answered survives even when it alone exceeds the target,
+    /// and a tool call is only ever dropped together with its results.
+    pub fn compact_with(
+        &mut self,
+        target_tokens: usize,
+        count_tokens: impl Fn(&ChatMessage) -> usize,
+    ) -> usize {
+        self.drain_compactable(target_tokens, count_tokens).len()
+    }
+
+    /// Remove the messages `compact_with` would drop and return them, oldest first,
+    /// so the caller can summarise them.
+    pub fn drain_compactable(
+        &mut self,
+        target_tokens: usize,
+        count_tokens: impl Fn(&ChatMessage) -> usize,
+    ) -> Vec<ChatMessage> {
+        let costs: Vec<usize> = self
+            .messages
+            .iter()
+            .map(|e| {
+                if e.is_backchannel {
+                    0
+                } else {
+                    count_tokens(&e.message)
                 }
-                None => break, // Only system/backchannel messages left
+            })
+            .collect();
+        let mut total: usize = costs.iter().sum();
+        let last = self.messages.iter().rposition(|e| !e.is_backchannel);
+
+        let mut keep = vec![true; self.messages.len()];
+        for unit in self.units() {
+            if total <= target_tokens {
+                break;
             }
+            let e = &self.messages[unit.start];
+            if e.is_backchannel
+                || e.message.role == ChatRole::System
+                || last.is_some_and(|l| unit.contains(&l))
+            {
+                continue;
+            }
+            total -= unit.clone().map(|i| costs[i]).sum::<usize>();
+            keep[unit].fill(false);
         }
-        dropped
+
+        let (kept, dropped): (Vec<_>, Vec<_>) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .zip(keep)
+            .partition(|(_, keep)| *keep);
+        self.messages = kept.into_iter().map(|(e, _)| e).collect();
+        dropped.into_iter().map(|(e, _)| e.message).collect()
     }
 
-    /// Clear all messages
+    /// Clear all messages and the summary
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.summary = None;
     }
 
     /// Get the number of messages (excluding backchannel markers)
@@ -166,6 +566,89 @@ impl ConversationMemory {
     }
 }
 
+/// Current wall-clock time in ms since the Unix epoch
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Tool result cut to `SUMMARIZED_TOOL_RESULT_CHARS` without images; other messages as is
+fn summarize_tool_message(mut message: ChatMessage) -> ChatMessage {
+    if message.role == ChatRole::Tool {
+        let total = message.content.chars().count();
+        if total > SUMMARIZED_TOOL_RESULT_CHARS {
+            let mut cut: String = message
+                .content
+                .chars()
+                .take(SUMMARIZED_TOOL_RESULT_CHARS)
+                .collect();
+            cut.push_str(&format!(
+                " … [{} more characters]",
+                total - SUMMARIZED_TOOL_RESULT_CHARS
+            ));
+            message.content = cut;
+        }
+        if !message.images.is_empty() {
+            message
+                .content
+                .push_str(&format!(" [{} image(s) omitted]", message.images.len()));
+            message.images.clear();
+        }
+    }
+    message
+}
+
+/// Build the request that folds `dropped` messages into the rolling summary.
+pub fn summary_request(previous: Option<&str>, dropped: &[ChatMessage]) -> Vec<ChatMessage> {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str("Summary so far:\n");
+        transcript.push_str(previous);
+        transcript.push_str("\n\nLater messages:\n");
+    }
+    for msg in dropped {
+        let line = match msg.role {
+            ChatRole::System => format!("System: {}", msg.content),
+            ChatRole::User => format!("User: {}", msg.content),
+            ChatRole::Assistant => match msg.tool_calls {
+                Some(ref calls) => {
+                    let calls: Vec<String> = calls
+                        .iter()
+                        .map(|c| format!("{}({})", c.name, c.arguments))
+                        .collect();
+                    format!("Assistant called: {}", calls.join(", "))
+                }
+                None => format!("Assistant: {}", msg.content),
+            },
+            ChatRole::Tool => {
+                let name = msg.tool_name.as_deref().unwrap_or("tool");
+                let result: String = msg
+                    .content
+                    .chars()
+                    .take(SUMMARY_TOOL_RESULT_CHARS)
+                    .collect();
+                format!("Tool {} returned: {}", name, result)
+            }
+        };
+        transcript.push_str(&line);
+        transcript.push('\n');
+    }
+
+    vec![
+        ChatMessage::system(
+            "You maintain the running summary of a conversation between a user and a voice \
+             assistant. Merge the summary so far (if any) with the later messages into one \
+             updated summary. Keep names, facts about the user, decisions, open tasks and \
+             promised follow-ups; drop small talk. Write concise plain-text notes in the \
+             conversation's language and reply with the summary only."
+                .to_string(),
+        ),
+        ChatMessage::user(transcript),
+    ]
+}
+
 impl Default for ConversationMemory {
     fn default() -> Self {
         Self::new()
@@ -191,6 +674,16 @@ mod tests {
         assert_eq!(memory.len(), 0);
     }
 
+    #[test]
+    fn test_pop_message() {
+        let mut memory = ConversationMemory::new();
+        memory.add_message(ChatMessage::user("Hello".to_string()));
+        memory.add_message(ChatMessage::user("Cancelled".to_string()));
+        let popped = memory.pop_message().unwrap();
+        assert_eq!(popped.content, "Cancelled");
+        assert_eq!(memory.len(), 1);
+    }
+
     #[test]
     fn test_compact_drops_oldest_non_system() {
         let mut memory = ConversationMemory::new();
@@ -216,6 +709,244 @@ mod tests {
         assert!(last.content.starts_with("Message 9"));
     }
 
+    #[test]
+    fn test_compact_with_keeps_newest_message() {
+        let mut memory = ConversationMemory::new();
+        memory.add_message(ChatMessage::system("System prompt".to_string()));
+        memory.add_message(ChatMessage::user("old".to_string()));
+        memory.add_message(ChatMessage::assistant("reply".to_string()));
+        memory.add_message(ChatMessage::user("huge".to_string()));
+
+        // Every message costs 100 tokens: even a target of 0 keeps system + newest
+        let dropped = memory.compact_with(0, |_| 100);
+        assert_eq!(dropped, 2);
+        let messages = memory.get_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, ChatRole::System);
+        assert_eq!(messages[1].content, "huge");
+    }
+
+    #[test]
+    fn test_compact_with_uses_given_counts() {
+        let mut memory = ConversationMemory::new();
+        for text in ["日本語のメッセージ", "short", "latest"] {
+            memory.add_message(ChatMessage::user(text.to_string()));
+        }
+        // Count one token per character: dropping the first message is enough
+        let dropped = memory.compact_with(12, |m| m.content.chars().count());
+        assert_eq!(dropped, 1);
+        assert_eq!(memory.get_messages()[0].content, "short");
+    }
+
+    #[test]
+    fn test_summary_note_follows_system_messages() {
+        let mut memory = ConversationMemory::new();
+        memory.add_message(ChatMessage::system("System prompt".to_string()));
+        memory.add_message(ChatMessage::user("Hi".to_string()));
+        memory.set_summary("User is Aiko, planning a trip to Osaka.".to_string());
+
+        let messages = memory.get_messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].role, ChatRole::System);
+        assert!(messages[1].content.starts_with(SUMMARY_HEADING));
+        assert!(messages[1].content.contains("Osaka"));
+        assert_eq!(messages[2].content, "Hi");
+
+        memory.clear();
+        assert!(memory.summary().is_none());
+        assert!(memory.get_messages().is_empty());
+    }
+
+    #[test]
+    fn test_drain_compactable_returns_dropped_in_order() {
+        let mut memory = ConversationMemory::new();
+        for text in ["first", "second", "third"] {
+            memory.add_message(ChatMessage::user(text.to_string()));
+        }
+        let dropped = memory.drain_compactable(10, |_| 10);
+        let texts: Vec<_> = dropped.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(texts, ["first", "second"]);
+        assert_eq!(memory.get_messages()[0].content, "third");
+    }
+
+    fn tool_turn(
+        memory: &mut ConversationMemory,
+        question: &str,
+        id: &str,
+        retention: ToolRetention,
+    ) {
+        memory.add_message(ChatMessage::user(question.to_string()));
+        let call = ChatMessage::assistant_tool_calls(vec![crate::llm::ToolCallInfo {
+            id: id.to_string(),
+            name: "read_file".to_string(),
+            arguments: serde_json::json!({"path": "notes.txt"}),
+        }]);
+        let result =
+            ChatMessage::tool_result(id.to_string(), "read_file".to_string(), "y".repeat(1000));
+        memory.add_tool_trace(vec![call, result], retention);
+        memory.add_message(ChatMessage::assistant(format!("answer {}", id)));
+    }
+
+    #[test]
+    fn test_compaction_keeps_tool_call_with_result() {
+        let mut memory = ConversationMemory::new();
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::Full);
+        memory.add_message(ChatMessage::user("q2".to_string()));
+
+        // Dropping the user message and the call reaches the target, but the result goes too
+        let dropped = memory.drain_compactable(25, |_| 10);
+        assert_eq!(dropped.len(), 3);
+        assert_eq!(dropped[2].role, ChatRole::Tool);
+        assert_eq!(memory.get_messages()[0].content, "answer c1");
+
+        // Capacity trimming drops pairs whole as well
+        let mut memory = ConversationMemory::with_capacity(3);
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::Full);
+        memory.add_message(ChatMessage::user("q2".to_string()));
+        let roles: Vec<_> = memory
+            .get_messages()
+            .iter()
+            .map(|m| m.role.clone())
+            .collect();
+        assert_eq!(roles, [ChatRole::Assistant, ChatRole::User]);
+    }
+
+    #[test]
+    fn test_tool_retention_modes() {
+        let mut memory = ConversationMemory::new();
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::Summarized);
+        let result = &memory.get_messages()[2];
+        assert!(result.content.len() < 400);
+        assert!(result.content.ends_with("[700 more characters]"));
+
+        let mut memory = ConversationMemory::new();
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::Off);
+        assert_eq!(memory.len(), 2);
+
+        let mut memory = ConversationMemory::new();
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::LastTurns(1));
+        tool_turn(&mut memory, "q2", "c2", ToolRetention::LastTurns(1));
+        assert_eq!(memory.prune_tool_turns(1), 2);
+        let messages = memory.get_messages();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[1].content, "answer c1");
+        assert_eq!(messages[3].tool_calls.as_ref().unwrap()[0].id, "c2");
+
+        assert!(ToolMemoryConfig {
+            mode: Some("all".into()),
+            last_turns: None
+        }
+        .retention()
+        .is_err());
+    }
+
+    #[test]
+    fn test_repair_tool_pairs_drops_unanswered_calls() {
+        let mut memory = ConversationMemory::new();
+        memory.add_message(ChatMessage::tool_result(
+            "c0".into(),
+            "read_file".into(),
+            "orphan".into(),
+        ));
+        tool_turn(&mut memory, "q1", "c1", ToolRetention::Full);
+        memory.add_message(ChatMessage::user("q2".to_string()));
+        memory.add_message(ChatMessage::assistant_tool_calls(vec![
+            crate::llm::ToolCallInfo {
+                id: "c2".to_string(),
+                name: "read_file".to_string(),
+                arguments: serde_json::json!({}),
+            },
+        ]));
+
+        memory.repair_tool_pairs();
+        let messages = memory.get_messages();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].content, "q1");
+        assert_eq!(messages[4].content, "q2");
+    }
+
+    #[test]
+    fn test_pop_turn_takes_tool_messages_and_backchannels() {
+        let mut memory = ConversationMemory::new();
+        memory.add_message(ChatMessage::user("first".to_string()));
+        memory.add_message(ChatMessage::assistant("reply".to_string()));
+        memory.add_backchannel();
+        tool_turn(&mut memory, "red the file", "c1", ToolRetention::Full);
+        memory.add_backchannel();
+
+        let turn = memory.pop_turn().unwrap();
+        assert_eq!(turn.user_message().content, "red the file");
+        assert_eq!(turn.messages().len(), 4);
+        // The marker from the turn goes, the trailing one for the next utterance stays
+        assert_eq!(memory.total_len(), 3);
+        assert_eq!(memory.len(), 2);
+
+        memory.restore_turn(turn);
+        assert_eq!(memory.len(), 6);
+        assert_eq!(memory.total_len(), 8);
+
+        let mut empty = ConversationMemory::new();
+        assert!(empty.pop_turn().is_none());
+    }
+
+    #[test]
+    fn test_stats_and_interrupted_reply() {
+        let mut memory = ConversationMemory::new();
+        assert!(!memory.mark_last_reply_interrupted());
+        for (llm, tools, first_token) in [(800, 0, None), (1200, 400, Some(300))] {
+            memory.add_message(ChatMessage::user("question".to_string()));
+            memory.add_message_with_metadata(
+                ChatMessage::assistant("answer".to_string()),
+                MessageMetadata {
+                    usage: Some(TokenUsage {
+                        input_tokens: 100,
+                        output_tokens: 20,
+                        total_tokens: 120,
+                    }),
+                    llm_latency_ms: Some(llm),
+                    tool_latency_ms: Some(tools),
+                    first_token_ms: first_token,
+                    ..MessageMetadata::now()
+                },
+            );
+        }
+        memory.add_backchannel();
+        assert!(memory.mark_last_reply_interrupted());
+
+        let stats = memory.stats();
+        assert_eq!(stats.turns, 2);
+        assert_eq!(stats.interrupted_turns, 1);
+        assert_eq!(stats.input_tokens, 200);
+        assert_eq!(stats.avg_llm_latency_ms, 1000);
+        assert_eq!(stats.avg_tool_latency_ms, 200);
+        assert_eq!(stats.avg_first_token_ms, Some(300));
+
+        let messages = memory.get_messages_with_metadata();
+        assert!(messages[3].1.interrupted);
+        assert!(!messages[1].1.interrupted);
+        assert!(messages[0].1.timestamp_ms > 0);
+    }
+
+    #[test]
+    fn test_summary_request_includes_previous_and_transcript() {
+        let dropped = vec![
+            ChatMessage::user("My name is Ken".to_string()),
+            ChatMessage::tool_result(
+                "call_1".to_string(),
+                "read_file".to_string(),
+                "x".repeat(2000),
+            ),
+        ];
+        let request = summary_request(Some("Earlier: talked about Rust."), &dropped);
+        assert_eq!(request.len(), 2);
+        assert_eq!(request[0].role, ChatRole::System);
+        let transcript = &request[1].content;
+        assert!(transcript.contains("Earlier: talked about Rust."));
+        assert!(transcript.contains("User: My name is Ken"));
+        assert!(transcript.contains("Tool read_file returned:"));
+        assert!(transcript.len() < 1000);
+    }
+
     #[test]
     fn test_compact_preserves_all_when_under_target() {
         let mut memory = ConversationMemory::new();
@@ -1,28 +1,173 @@
-use crate::llm::{ChatMessage, LlmProvider, LlmResponse, TokenUsage, ToolCallInfo};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::cancel::CancelToken;
+use crate::llm::{
+    ChatMessage, ChatRole, LlmProvider, LlmResponse, RequestOptions, TokenUsage, ToolCallInfo, ToolChoice,
+    ToolDefinition,
+};
 use crate::tool::{ToolAccess, ToolResult};
 use crate::AgentError;
 
 const DEFAULT_MAX_ITERATIONS: u32 = 10;
 
+/// Identical calls (same tool and arguments) from which each result warns the model
+const REPEAT_WARNING: usize = 2;
+
+/// Identical calls after which the loop stops and asks for the final answer
+const REPEAT_LIMIT: usize = 4;
+
+/// Added (for that request only) when the loop runs out of iterations
+const FINAL_ANSWER_PROMPT: &str = "You have used up the tool calls for this request. \
+Do not call any more tools. Answer now with what you have found so far, and briefly say \
+what is still missing, if anything. Reply in the language the user has been using.";
+
+/// Reply when the model still calls tools after `FINAL_ANSWER_PROMPT`
+const NO_ANSWER_FALLBACK: &str =
+    "Sorry, I couldn't finish that within the steps I'm allowed. Could you narrow it down?";
+
+/// `NO_ANSWER_FALLBACK` for Japanese conversations
+const NO_ANSWER_FALLBACK_JA: &str =
+    "すみません、決められた手順の中では終わりませんでした。もう少し絞り込んでもらえますか？";
+
+/// Progress of a ReAct loop, for callers that keep the user informed while it runs.
+/// Iterations count from 1; the request for a final answer after the loop stops (see
+/// [`run`]) is numbered one past the last iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReactEvent {
+    LlmCallStarted {
+        iteration: u32,
+        max_iterations: u32,
+    },
+    LlmCallFinished {
+        iteration: u32,
+        duration_ms: u64,
+        /// Tool calls the model asked for (0 for the final answer)
+        tool_calls: u32,
+    },
+    ToolCallStarted {
+        iteration: u32,
+        call_id: String,
+        name: String,
+        /// Arguments as JSON
+        arguments: String,
+    },
+    ToolCallFinished {
+        iteration: u32,
+        call_id: String,
+        name: String,
+        duration_ms: u64,
+        result_chars: u64,
+        images: u32,
+        /// The tool returned an error (reported to the model as the result text)
+        failed: bool,
+    },
+}
+
+/// Settings of one run of the loop
+#[derive(Debug, Clone, Default)]
+pub struct ReactOptions {
+    /// LLM calls before the loop asks for a final answer (default 10)
+    pub max_iterations: Option<u32>,
+    /// Overrides for the loop's LLM requests. `tool_choice` only applies to the first one,
+    /// so a forced tool call is not repeated until the iterations run out.
+    pub request: RequestOptions,
+    /// Reply when the model keeps calling tools after being asked for an answer. By
+    /// default a short apology, in Japanese if the user writes Japanese, else in English.
+    pub no_answer_reply: Option<String>,
+}
+
+/// Receives `ReactEvent`s. Tool events of calls that run concurrently arrive from their
+/// worker threads, interleaved.
+pub trait ReactObserver: Sync {
+    fn on_event(&self, event: ReactEvent);
+}
+
 /// Run a ReAct (Reason+Act) loop: call LLM with tools, execute tool calls, repeat until text response.
 ///
 /// Returns the final text response, optional reasoning, and accumulated token usage.
+/// Repeating a call with identical arguments adds a warning to its result; after
+/// `max_iterations` (or too many repeats) one more request asks the model to answer with
+/// what it has, so the loop ends with a reply rather than an error.
+/// Returns `AgentError::Cancelled` as soon as `cancel` fires (checked before every LLM
+/// call and every tool call, and after the tool calls of a response).
+///
+/// Several calls in one response to tools that are parallel-safe run concurrently, up to
+/// the tools' `max_parallel_calls`; their results are added in call order.
+/// `observer`, if given, is told about every LLM and tool call as it starts and finishes.
 pub fn run(
     client: &dyn LlmProvider,
     messages: &mut Vec<ChatMessage>,
     tools: &dyn ToolAccess,
     max_iterations: Option<u32>,
+    cancel: &CancelToken,
+    observer: Option<&dyn ReactObserver>,
 ) -> Result<(String, Option<String>, TokenUsage), AgentError> {
-    let max_iter = max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS);
-    let tool_defs = tools.get_definitions();
-    let mut total_usage = TokenUsage::default();
+    let options = ReactOptions {
+        max_iterations,
+        ..Default::default()
+    };
+    run_with_options(client, messages, tools, &options, cancel, observer, None)
+}
 
-    for iteration in 0..max_iter {
-        tracing::info!("ReAct iteration {}/{}", iteration + 1, max_iter);
+/// Same as [`run`], but streams text deltas from every LLM call to `on_delta` as they arrive.
+///
+/// Text emitted by the model before a tool call (e.g. "Let me check that file") is streamed
+/// too, so callers can start speaking before the loop finishes.
+pub fn run_streaming(
+    client: &dyn LlmProvider,
+    messages: &mut Vec<ChatMessage>,
+    tools: &dyn ToolAccess,
+    max_iterations: Option<u32>,
+    cancel: &CancelToken,
+    observer: Option<&dyn ReactObserver>,
+    on_delta: &mut dyn FnMut(&str),
+) -> Result<(String, Option<String>, TokenUsage), AgentError> {
+    let options = ReactOptions {
+        max_iterations,
+        ..Default::default()
+    };
+    run_with_options(client, messages, tools, &options, cancel, observer, Some(on_delta))
+}
 
-        let response = client
-            .chat_with_tools(messages, &tool_defs)
-            .map_err(|e| AgentError::NetworkError(e.to_string()))?;
+/// [`run`] with per-run `options`, streaming to `on_delta` when it is given
+pub fn run_with_options(
+    client: &dyn LlmProvider,
+    messages: &mut Vec<ChatMessage>,
+    tools: &dyn ToolAccess,
+    options: &ReactOptions,
+    cancel: &CancelToken,
+    observer: Option<&dyn ReactObserver>,
+    mut on_delta: Option<&mut dyn FnMut(&str)>,
+) -> Result<(String, Option<String>, TokenUsage), AgentError> {
+    let max_iter = options.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS);
+    let later_request = RequestOptions {
+        tool_choice: ToolChoice::Auto,
+        ..options.request.clone()
+    };
+    let tool_defs = tools.get_definitions();
+    let mut total_usage = TokenUsage::default();
+    // How often each (tool, arguments) pair has been called
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    let mut iterations = 0;
+
+    while iterations < max_iter {
+        cancel.check()?;
+        iterations += 1;
+        tracing::info!("ReAct iteration {}/{}", iterations, max_iter);
+
+        let request = if iterations == 1 { &options.request } else { &later_request };
+        let response = call_llm(
+            client,
+            messages,
+            &tool_defs,
+            request,
+            &mut on_delta,
+            (iterations, max_iter),
+            observer,
+        )?;
 
         match response {
             LlmResponse::Text { content, reasoning, usage } => {
@@ -31,7 +176,7 @@ pub fn run(
                 }
                 tracing::info!(
                     "ReAct complete: text response after {} iterations (tokens: in={}, out={}, total={})",
-                    iteration + 1, total_usage.input_tokens, total_usage.output_tokens, total_usage.total_tokens
+                    iterations, total_usage.input_tokens, total_usage.output_tokens, total_usage.total_tokens
                 );
                 return Ok((content, reasoning, total_usage));
             }
@@ -41,17 +186,17 @@ pub fn run(
                 }
                 tracing::info!(
                     "ReAct iteration {}: {} tool call(s)",
-                    iteration + 1,
+                    iterations,
                     calls.len()
                 );
 
                 // Record the assistant's tool calls in message history
                 messages.push(ChatMessage::assistant_tool_calls(calls.clone()));
 
-                // Execute each tool call and add results
-                for call in &calls {
-                    let result = execute_tool_call(tools, call);
-
+                // Execute the tool calls and add results in call order
+                let results = execute_tool_calls(tools, &calls, cancel, iterations, observer)?;
+                let mut looping = false;
+                for (call, mut result) in calls.iter().zip(results) {
                     tracing::info!(
                         "Tool '{}' ({}): {} chars result, {} images",
                         call.name,
@@ -60,6 +205,20 @@ pub fn run(
                         result.images.len(),
                     );
 
+                    let key = (call.name.clone(), call.arguments.to_string());
+                    let count = seen.entry(key).or_insert(0);
+                    *count += 1;
+                    if *count >= REPEAT_WARNING {
+                        tracing::warn!("Tool '{}' called {} times with the same arguments", call.name, count);
+                        result.text.push_str(&format!(
+                            "\n\n[Note: this is call {} to '{}' with these exact arguments. \
+                             If the result has not changed, repeating the call will not help: \
+                             answer with what you have or try something different.]",
+                            count, call.name
+                        ));
+                    }
+                    looping |= *count >= REPEAT_LIMIT;
+
                     if result.images.is_empty() {
                         messages.push(ChatMessage::tool_result(
                             call.id.clone(),
@@ -75,38 +234,227 @@ pub fn run(
                         ));
                     }
                 }
+                if looping {
+                    tracing::warn!("ReAct loop is repeating the same tool call, stopping");
+                    break;
+                }
             }
         }
     }
 
-    Err(AgentError::InternalError(format!(
-        "ReAct loop exceeded maximum iterations ({})",
-        max_iter
-    )))
+    // Out of iterations (or stuck in a loop): ask for an answer from what was gathered
+    // instead of discarding it. Tool definitions stay attached since some providers
+    // reject a tool history without them; tool_choice "none" rules out more calls.
+    cancel.check()?;
+    tracing::warn!("ReAct stopped after {} iterations, requesting a final answer", iterations);
+    let mut request = messages.clone();
+    request.push(ChatMessage::user(FINAL_ANSWER_PROMPT.to_string()));
+    let final_request = RequestOptions {
+        tool_choice: ToolChoice::None,
+        ..options.request.clone()
+    };
+    let response = call_llm(
+        client,
+        &request,
+        &tool_defs,
+        &final_request,
+        &mut on_delta,
+        (iterations + 1, max_iter),
+        observer,
+    )?;
+    match response {
+        LlmResponse::Text { content, reasoning, usage } => {
+            if let Some(ref u) = usage {
+                total_usage.add(u);
+            }
+            Ok((content, reasoning, total_usage))
+        }
+        LlmResponse::ToolCalls(_, usage) => {
+            if let Some(ref u) = usage {
+                total_usage.add(u);
+            }
+            tracing::warn!("Model kept calling tools after the final answer request");
+            let reply = options
+                .no_answer_reply
+                .clone()
+                .unwrap_or_else(|| no_answer_fallback(messages).to_string());
+            if let Some(on_delta) = on_delta {
+                on_delta(&reply);
+            }
+            Ok((reply, None, total_usage))
+        }
+    }
+}
+
+/// `NO_ANSWER_FALLBACK` in the language of the last user message (kana means Japanese)
+fn no_answer_fallback(messages: &[ChatMessage]) -> &'static str {
+    let japanese = messages
+        .iter()
+        .rev()
+        .find(|m| m.role == ChatRole::User)
+        .is_some_and(|m| m.content.chars().any(|c| matches!(c, '\u{3040}'..='\u{30FF}')));
+    if japanese {
+        NO_ANSWER_FALLBACK_JA
+    } else {
+        NO_ANSWER_FALLBACK
+    }
+}
+
+/// One LLM request of the loop, reported to `observer`. `iteration` is (current, max).
+fn call_llm(
+    client: &dyn LlmProvider,
+    messages: &[ChatMessage],
+    tool_defs: &[ToolDefinition],
+    options: &RequestOptions,
+    on_delta: &mut Option<&mut dyn FnMut(&str)>,
+    (iteration, max_iterations): (u32, u32),
+    observer: Option<&dyn ReactObserver>,
+) -> Result<LlmResponse, AgentError> {
+    if let Some(observer) = observer {
+        observer.on_event(ReactEvent::LlmCallStarted {
+            iteration,
+            max_iterations,
+        });
+    }
+    let started = Instant::now();
+    let response = match on_delta {
+        Some(ref mut on_delta) => {
+            client.chat_with_tools_streaming(messages, tool_defs, options, *on_delta)
+        }
+        None => client.chat_with_tools(messages, tool_defs, options),
+    }
+    .map_err(AgentError::from_provider)?;
+    if let Some(observer) = observer {
+        observer.on_event(ReactEvent::LlmCallFinished {
+            iteration,
+            duration_ms: started.elapsed().as_millis() as u64,
+            tool_calls: match response {
+                LlmResponse::ToolCalls(ref calls, _) => calls.len() as u32,
+                LlmResponse::Text { .. } => 0,
+            },
+        });
+    }
+    Ok(response)
+}
+
+/// Execute `calls` and return their results in call order. Consecutive calls to
+/// parallel-safe tools run concurrently, at most `max_parallel_calls` at a time; any other
+/// call waits for the calls before it and runs alone.
+fn execute_tool_calls(
+    tools: &dyn ToolAccess,
+    calls: &[ToolCallInfo],
+    cancel: &CancelToken,
+    iteration: u32,
+    observer: Option<&dyn ReactObserver>,
+) -> Result<Vec<ToolResult>, AgentError> {
+    let run = |call: &ToolCallInfo| execute_tool_call(tools, call, iteration, observer);
+    let limit = tools.max_parallel_calls().max(1);
+    let mut results = Vec::with_capacity(calls.len());
+    let mut start = 0;
+    while start < calls.len() {
+        let mut end = start + 1;
+        if limit > 1 && tools.is_parallel_safe(&calls[start].name) {
+            while end < calls.len() && tools.is_parallel_safe(&calls[end].name) {
+                end += 1;
+            }
+        }
+        let batch = execute_concurrently(&calls[start..end], limit, cancel, &run);
+        // A tool aborted by cancellation reports an error; stop instead of feeding it back
+        cancel.check()?;
+        results.extend(batch.into_iter().map(|r| r.expect("call ran")));
+        start = end;
+    }
+    Ok(results)
+}
+
+/// Run `calls` with `run` on up to `limit` threads. A call is only skipped (`None`) when
+/// `cancel` fired before it started.
+fn execute_concurrently(
+    calls: &[ToolCallInfo],
+    limit: usize,
+    cancel: &CancelToken,
+    run: &(dyn Fn(&ToolCallInfo) -> ToolResult + Sync),
+) -> Vec<Option<ToolResult>> {
+    if let [call] = calls {
+        if cancel.is_cancelled() {
+            return vec![None];
+        }
+        return vec![Some(run(call))];
+    }
+
+    let next = AtomicUsize::new(0);
+    let slots: Vec<Mutex<Option<ToolResult>>> = calls.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..limit.min(calls.len()) {
+            scope.spawn(|| {
+                while !cancel.is_cancelled() {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(call) = calls.get(i) else {
+                        break;
+                    };
+                    let result = run(call);
+                    *slots[i].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+    slots
+        .into_iter()
+        .map(|slot| slot.into_inner().unwrap())
+        .collect()
 }
 
 /// Execute a single tool call, returning the result (or error message)
-fn execute_tool_call(tools: &dyn ToolAccess, call: &ToolCallInfo) -> ToolResult {
-    match tools.call(&call.name, call.arguments.clone()) {
-        Ok(result) => result,
+fn execute_tool_call(
+    tools: &dyn ToolAccess,
+    call: &ToolCallInfo,
+    iteration: u32,
+    observer: Option<&dyn ReactObserver>,
+) -> ToolResult {
+    if let Some(observer) = observer {
+        observer.on_event(ReactEvent::ToolCallStarted {
+            iteration,
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            arguments: call.arguments.to_string(),
+        });
+    }
+    let started = Instant::now();
+    let (result, failed) = match tools.call(&call.name, call.arguments.clone()) {
+        Ok(result) => (result, false),
         Err(e) => {
             tracing::warn!("Tool '{}' error: {}", call.name, e);
-            ToolResult::text(format!("Error executing tool '{}': {}", call.name, e))
+            let text = format!("Error executing tool '{}': {}", call.name, e);
+            (ToolResult::text(text), true)
         }
+    };
+    if let Some(observer) = observer {
+        observer.on_event(ReactEvent::ToolCallFinished {
+            iteration,
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            result_chars: result.text.chars().count() as u64,
+            images: result.images.len() as u32,
+            failed,
+        });
     }
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::llm::{ChatRole, ToolDefinition};
+    use crate::llm::ToolDefinition;
     use crate::tool::ToolRegistry;
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
     /// Mock LLM provider for testing the ReAct loop
     struct MockProvider {
         responses: Vec<LlmResponse>,
         call_count: AtomicUsize,
+        /// `tool_choice` of each request
+        tool_choices: Mutex<Vec<ToolChoice>>,
     }
 
     impl MockProvider {
@@ -114,12 +462,17 @@ mod tests {
             Self {
                 responses,
                 call_count: AtomicUsize::new(0),
+                tool_choices: Mutex::new(Vec::new()),
             }
         }
     }
 
     impl LlmProvider for MockProvider {
-        fn chat(&self, _messages: &[ChatMessage]) -> anyhow::Result<String> {
+        fn chat(
+            &self,
+            _messages: &[ChatMessage],
+            _options: &RequestOptions,
+        ) -> anyhow::Result<String> {
             Ok("mock".to_string())
         }
 
@@ -131,7 +484,9 @@ mod tests {
             &self,
             _messages: &[ChatMessage],
             _tools: &[ToolDefinition],
+            options: &RequestOptions,
         ) -> anyhow::Result<LlmResponse> {
+            self.tool_choices.lock().unwrap().push(options.tool_choice.clone());
             let idx = self.call_count.fetch_add(1, Ordering::SeqCst);
             if idx < self.responses.len() {
                 // We need to clone the response — reconstruct it
@@ -166,7 +521,7 @@ mod tests {
         let mut messages = vec![ChatMessage::user("Hi".to_string())];
         let tools = ToolRegistry::new();
 
-        let (text, reasoning, usage) = run(&provider, &mut messages, &tools, Some(5)).unwrap();
+        let (text, reasoning, usage) = run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
         assert_eq!(text, "Hello!");
         assert!(reasoning.is_none());
         assert_eq!(usage.total_tokens, 0);
@@ -194,7 +549,7 @@ mod tests {
         let mut tools = ToolRegistry::new();
         tools.register(Box::new(TaskTool::new()));
 
-        let (text, _, _) = run(&provider, &mut messages, &tools, Some(5)).unwrap();
+        let (text, _, _) = run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
         assert_eq!(text, "There are no tasks.");
 
         // Messages should contain: user, assistant(tool_calls), tool_result
@@ -248,7 +603,7 @@ mod tests {
         let mut tools = ToolRegistry::new();
         tools.register(Box::new(MockImageTool));
 
-        let (text, _, _) = run(&provider, &mut messages, &tools, Some(5)).unwrap();
+        let (text, _, _) = run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
         assert_eq!(text, "I can see a Chrome window.");
 
         // Messages: user, assistant(tool_calls), tool_result_with_images
@@ -265,6 +620,100 @@ mod tests {
         assert_eq!(tool_msg.images[0].base64, "iVBORw0KGgoAAAANS");
     }
 
+    /// Mock tool that sleeps for `ms` and tracks how many calls overlap
+    struct SlowTool {
+        name: &'static str,
+        parallel: bool,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    impl crate::tool::ToolHandler for SlowTool {
+        fn name(&self) -> &str {
+            self.name
+        }
+        fn description(&self) -> &str {
+            "Sleep, then echo ms"
+        }
+        fn parameters_schema(&self) -> serde_json::Value {
+            serde_json::json!({"type": "object", "properties": {"ms": {"type": "integer"}}})
+        }
+        fn parallel_safe(&self) -> bool {
+            self.parallel
+        }
+        fn call(&self, args: serde_json::Value) -> Result<ToolResult, crate::AgentError> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(now, Ordering::SeqCst);
+            let ms = args["ms"].as_u64().unwrap_or(0);
+            std::thread::sleep(std::time::Duration::from_millis(ms));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(ToolResult::text(format!("{}:{}", self.name, ms)))
+        }
+    }
+
+    #[test]
+    fn test_react_parallel_calls_keep_order_and_limit() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let mut tools = ToolRegistry::new();
+        tools.set_max_parallel(2);
+        for (name, parallel) in [("lookup", true), ("capture", false)] {
+            tools.register(Box::new(SlowTool {
+                name,
+                parallel,
+                running: running.clone(),
+                max_running: max_running.clone(),
+            }));
+        }
+        let call = |i: usize, name: &str, ms: u64| ToolCallInfo {
+            id: format!("c{}", i),
+            name: name.to_string(),
+            arguments: serde_json::json!({ "ms": ms }),
+        };
+
+        // Four lookups with a limit of 2 overlap, but never more than two at once
+        let calls = vec![
+            call(0, "lookup", 60),
+            call(1, "lookup", 10),
+            call(2, "lookup", 40),
+            call(3, "lookup", 20),
+        ];
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(calls, None),
+            LlmResponse::Text {
+                content: "done".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+        let mut messages = vec![ChatMessage::user("look up".to_string())];
+        run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        let results: Vec<(&str, &str)> = messages[2..]
+            .iter()
+            .map(|m| (m.tool_call_id.as_deref().unwrap(), m.content.as_str()))
+            .collect();
+        assert_eq!(
+            results,
+            [("c0", "lookup:60"), ("c1", "lookup:10"), ("c2", "lookup:40"), ("c3", "lookup:20")]
+        );
+
+        // Calls to a tool that opted out run one at a time
+        max_running.store(0, Ordering::SeqCst);
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(vec![call(0, "capture", 20), call(1, "capture", 10)], None),
+            LlmResponse::Text {
+                content: "done".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+        let mut messages = vec![ChatMessage::user("capture".to_string())];
+        run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(messages[3].content, "capture:10");
+    }
+
     #[test]
     fn test_react_tool_without_images_has_empty_images() {
         let provider = MockProvider::new(vec![
@@ -285,13 +734,110 @@ mod tests {
         let mut tools = ToolRegistry::new();
         tools.register(Box::new(TaskTool::new()));
 
-        run(&provider, &mut messages, &tools, Some(5)).unwrap();
+        run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None).unwrap();
 
         let tool_msg = &messages[2];
         assert_eq!(tool_msg.role, ChatRole::Tool);
         assert!(tool_msg.images.is_empty(), "Plain tool result should have no images");
     }
 
+    /// Records events as short strings, leaving out the timings
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ReactObserver for Recorder {
+        fn on_event(&self, event: ReactEvent) {
+            let line = match event {
+                ReactEvent::LlmCallStarted { iteration, max_iterations } => {
+                    format!("llm {}/{}", iteration, max_iterations)
+                }
+                ReactEvent::LlmCallFinished { iteration, tool_calls, .. } => {
+                    format!("llm {} done, {} calls", iteration, tool_calls)
+                }
+                ReactEvent::ToolCallStarted { iteration, name, arguments, .. } => {
+                    format!("tool {} {} {}", iteration, name, arguments)
+                }
+                ReactEvent::ToolCallFinished { name, result_chars, failed, .. } => {
+                    format!("tool {} done, {} chars, failed={}", name, result_chars > 0, failed)
+                }
+            };
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[test]
+    fn test_react_reports_progress() {
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: "call_1".to_string(),
+                name: "tasks".to_string(),
+                arguments: serde_json::json!({"action": "list"}),
+            }], None),
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: "call_2".to_string(),
+                name: "missing".to_string(),
+                arguments: serde_json::json!({}),
+            }], None),
+            LlmResponse::Text {
+                content: "done".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+
+        let mut messages = vec![ChatMessage::user("list".to_string())];
+        use crate::tool::TaskTool;
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(TaskTool::new()));
+
+        let recorder = Recorder(Mutex::new(Vec::new()));
+        run(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), Some(&recorder)).unwrap();
+        assert_eq!(
+            recorder.0.into_inner().unwrap(),
+            [
+                "llm 1/5",
+                "llm 1 done, 1 calls",
+                r#"tool 1 tasks {"action":"list"}"#,
+                "tool tasks done, true chars, failed=false",
+                "llm 2/5",
+                "llm 2 done, 1 calls",
+                "tool 2 missing {}",
+                "tool missing done, true chars, failed=true",
+                "llm 3/5",
+                "llm 3 done, 0 calls",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_react_streaming_delivers_final_text() {
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: "call_1".to_string(),
+                name: "tasks".to_string(),
+                arguments: serde_json::json!({"action": "list"}),
+            }], None),
+            LlmResponse::Text {
+                content: "No tasks yet.".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+
+        let mut messages = vec![ChatMessage::user("List tasks".to_string())];
+        use crate::tool::TaskTool;
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(TaskTool::new()));
+
+        let mut streamed = String::new();
+        let (text, _, _) = run_streaming(&provider, &mut messages, &tools, Some(5), &CancelToken::new(), None, &mut |d| {
+            streamed.push_str(d)
+        })
+        .unwrap();
+
+        assert_eq!(text, "No tasks yet.");
+        assert_eq!(streamed, "No tasks yet.");
+    }
+
     #[test]
     fn test_react_max_iterations() {
         // Provider always returns tool calls — should hit max iterations
@@ -319,9 +865,146 @@ mod tests {
         let mut tools = ToolRegistry::new();
         tools.register(Box::new(TaskTool::new()));
 
-        let result = run(&provider, &mut messages, &tools, Some(2));
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("maximum iterations"));
+        // The model keeps calling tools even when asked to answer: a fallback reply, not an error
+        let (text, _, _) = run(&provider, &mut messages, &tools, Some(2), &CancelToken::new(), None).unwrap();
+        assert_eq!(text, NO_ANSWER_FALLBACK);
+        assert_eq!(provider.call_count.load(Ordering::SeqCst), 3);
+        assert_eq!(provider.tool_choices.lock().unwrap()[2], ToolChoice::None);
+        // The final-answer prompt is not kept; the repeated call's result carries a warning
+        assert_eq!(messages.len(), 5);
+        assert!(!messages[2].content.contains("[Note:"));
+        assert!(messages[4].content.contains("call 2 to 'tasks'"));
+    }
+
+    #[test]
+    fn test_react_tool_choice_applies_to_first_call() {
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: "call_1".to_string(),
+                name: "tasks".to_string(),
+                arguments: serde_json::json!({"action": "list"}),
+            }], None),
+            LlmResponse::Text {
+                content: "done".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+        let mut messages = vec![ChatMessage::user("list".to_string())];
+        use crate::tool::TaskTool;
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(TaskTool::new()));
+
+        let options = ReactOptions {
+            max_iterations: Some(3),
+            request: RequestOptions {
+                tool_choice: ToolChoice::Tool("tasks".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cancel = CancelToken::new();
+        run_with_options(&provider, &mut messages, &tools, &options, &cancel, None, None)
+            .unwrap();
+        assert_eq!(
+            *provider.tool_choices.lock().unwrap(),
+            [ToolChoice::Tool("tasks".to_string()), ToolChoice::Auto]
+        );
+    }
+
+    #[test]
+    fn test_react_repeated_call_stops_loop() {
+        let list = |i: usize| {
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: format!("call_{}", i),
+                name: "tasks".to_string(),
+                arguments: serde_json::json!({"action": "list"}),
+            }], None)
+        };
+        let mut responses: Vec<LlmResponse> = (0..REPEAT_LIMIT).map(list).collect();
+        responses.push(LlmResponse::Text {
+            content: "You have no tasks.".to_string(),
+            reasoning: None,
+            usage: None,
+        });
+        let provider = MockProvider::new(responses);
+
+        let mut messages = vec![ChatMessage::user("List tasks".to_string())];
+        use crate::tool::TaskTool;
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(TaskTool::new()));
+
+        // Stops after REPEAT_LIMIT identical calls, well before the cap, and answers
+        let (text, _, _) = run(&provider, &mut messages, &tools, Some(10), &CancelToken::new(), None).unwrap();
+        assert_eq!(text, "You have no tasks.");
+        assert_eq!(provider.call_count.load(Ordering::SeqCst), REPEAT_LIMIT + 1);
+        assert_eq!(messages.len(), 1 + 2 * REPEAT_LIMIT);
+    }
+
+    /// Tool that cancels the run while it executes (e.g. the user barges in)
+    struct CancellingTool(CancelToken);
+
+    impl crate::tool::ToolHandler for CancellingTool {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn description(&self) -> &str {
+            "Slow tool"
+        }
+
+        fn parameters_schema(&self) -> serde_json::Value {
+            serde_json::json!({"type": "object"})
+        }
+
+        fn call(&self, _args: serde_json::Value) -> Result<ToolResult, AgentError> {
+            self.0.cancel();
+            Err(AgentError::Cancelled)
+        }
+    }
+
+    #[test]
+    fn test_react_cancelled_during_tool_call() {
+        let provider = MockProvider::new(vec![
+            LlmResponse::ToolCalls(vec![ToolCallInfo {
+                id: "call_1".to_string(),
+                name: "slow".to_string(),
+                arguments: serde_json::json!({}),
+            }], None),
+            LlmResponse::Text {
+                content: "unreachable".to_string(),
+                reasoning: None,
+                usage: None,
+            },
+        ]);
+
+        let cancel = CancelToken::new();
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(CancellingTool(cancel.clone())));
+
+        let mut messages = vec![ChatMessage::user("Do something slow".to_string())];
+        let result = run(&provider, &mut messages, &tools, Some(5), &cancel, None);
+        assert!(matches!(result, Err(AgentError::Cancelled)));
+        assert_eq!(provider.call_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_react_cancelled_before_start() {
+        let provider = MockProvider::new(vec![]);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        let mut messages = vec![ChatMessage::user("Hi".to_string())];
+        let result = run(&provider, &mut messages, &ToolRegistry::new(), None, &cancel, None);
+        assert!(matches!(result, Err(AgentError::Cancelled)));
+        assert_eq!(provider.call_count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_no_answer_fallback_follows_language() {
+        let mut messages = vec![ChatMessage::user("What is in my inbox?".to_string())];
+        assert_eq!(no_answer_fallback(&messages), NO_ANSWER_FALLBACK);
+        messages.push(ChatMessage::user("受信箱には何がある？".to_string()));
+        assert_eq!(no_answer_fallback(&messages), NO_ANSWER_FALLBACK_JA);
     }
 }
@@ -0,0 +1,756 @@
+//! Persistent conversation sessions — one append-only JSONL file per session.
+//!
+//! The first line of `<dir>/<id>.jsonl` holds the session header; every later line is a
+//! record appended as the conversation goes: a message (with its metadata: timestamp
+//! and, for replies, provider, model, token usage and latencies), an interruption, a
+//! rename, a compaction, the pruning of old tool calls, or the undo of a turn. Nothing is
+//! rewritten in place, so a crash loses at most the line being written. Replaying the
+//! records rebuilds the `ConversationMemory` the session had when it was last used.
+//!
+//! Messages are stored in the `history` format, tool calls included. Images go to
+//! `<dir>/images/` under their content hash and are shared between sessions, so deleting
+//! a session leaves them in place.
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::{ExportEntry, Transcript};
+use crate::history::StoredMessage;
+use crate::llm::{ChatMessage, ChatRole};
+use crate::memory::{now_ms, ConversationMemory, MessageMetadata};
+use crate::AgentError;
+
+/// Version written to the header of new session files.
+/// 2: messages carry tool calls, tool results and image references.
+pub const SESSION_FORMAT_VERSION: u32 = 2;
+
+/// Subdirectory of the sessions directory holding externalised images
+const IMAGES_DIR: &str = "images";
+
+/// Titles derived from the first user message are cut to this many characters
+const DERIVED_TITLE_CHARS: usize = 60;
+
+/// Distinguishes sessions created within the same millisecond
+static ID_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// One line of a session file. Records live only while being written or replayed, so
+/// the size of the message variant does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header {
+        version: u32,
+        id: String,
+        created_at_ms: u64,
+    },
+    Message {
+        #[serde(flatten)]
+        metadata: MessageMetadata,
+        #[serde(flatten)]
+        message: StoredMessage,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reasoning: Option<String>,
+    },
+    Rename {
+        timestamp_ms: u64,
+        title: String,
+    },
+    /// The `dropped` oldest non-system messages were compacted away; `summary` is the
+    /// rolling summary afterwards, if the agent summarises
+    Compact {
+        timestamp_ms: u64,
+        dropped: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        summary: Option<String>,
+    },
+    /// Tool calls and results were removed from all but the last `keep_turns` turns
+    ToolsPruned {
+        timestamp_ms: u64,
+        keep_turns: usize,
+    },
+    /// The last turn was rolled back (undo, edit or regenerate)
+    Undo {
+        timestamp_ms: u64,
+    },
+    /// The user spoke over the last reply
+    Interrupted {
+        timestamp_ms: u64,
+    },
+}
+
+/// Overview of a stored session, for listing
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    /// Last rename, else the start of the first user message
+    pub title: String,
+    pub created_at_ms: u64,
+    /// Time of the last record
+    pub updated_at_ms: u64,
+    /// Messages recorded, including ones since compacted away
+    pub message_count: u32,
+    /// Sum of the recorded token usage
+    pub total_tokens: u64,
+}
+
+/// A recorded message, as returned by `SessionStore::load`.
+/// Images are referenced by file name in the sessions' image directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEntry {
+    #[serde(flatten)]
+    pub metadata: MessageMetadata,
+    #[serde(flatten)]
+    pub message: StoredMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+}
+
+/// A session read back from disk
+#[derive(Debug, Clone)]
+pub struct LoadedSession {
+    pub info: SessionInfo,
+    /// Full transcript, including messages since compacted away
+    pub entries: Vec<SessionEntry>,
+    /// Memory as the agent last had it (compactions and summary applied)
+    pub memory: ConversationMemory,
+}
+
+/// Directory of session files
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Start a new, empty session and return its ID.
+    pub fn create(&self) -> Result<String, AgentError> {
+        fs::create_dir_all(&self.dir).map_err(|e| {
+            AgentError::InternalError(format!(
+                "Failed to create sessions dir {}: {}",
+                self.dir.display(),
+                e
+            ))
+        })?;
+        let now = now_ms();
+        loop {
+            let seq = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let id = format!("{:x}-{:04x}", now, (std::process::id() ^ seq) & 0xffff);
+            let path = self.path(&id)?;
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let header = Record::Header {
+                        version: SESSION_FORMAT_VERSION,
+                        id: id.clone(),
+                        created_at_ms: now,
+                    };
+                    write_record(&mut file, &header)?;
+                    tracing::info!("Started session {}", id);
+                    return Ok(id);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(AgentError::InternalError(format!(
+                        "Failed to create session {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Record a message with its metadata (time, and for replies usage and latencies)
+    pub fn append_message(
+        &self,
+        id: &str,
+        message: &ChatMessage,
+        metadata: &MessageMetadata,
+    ) -> Result<(), AgentError> {
+        self.append_message_record(id, message, metadata, None)
+    }
+
+    /// Record an assistant reply with its metadata and the model's reasoning
+    pub fn append_reply(
+        &self,
+        id: &str,
+        reply: &ChatMessage,
+        metadata: &MessageMetadata,
+        reasoning: Option<&str>,
+    ) -> Result<(), AgentError> {
+        self.append_message_record(id, reply, metadata, reasoning)
+    }
+
+    fn append_message_record(
+        &self,
+        id: &str,
+        message: &ChatMessage,
+        metadata: &MessageMetadata,
+        reasoning: Option<&str>,
+    ) -> Result<(), AgentError> {
+        let message = StoredMessage::from_message(message, Some(&self.images_dir()))?;
+        self.append(
+            id,
+            &Record::Message {
+                metadata: metadata.clone(),
+                message,
+                reasoning: reasoning.map(str::to_string),
+            },
+        )
+    }
+
+    /// Record that the user spoke over the last reply
+    pub fn append_interrupted(&self, id: &str) -> Result<(), AgentError> {
+        self.append(
+            id,
+            &Record::Interrupted {
+                timestamp_ms: now_ms(),
+            },
+        )
+    }
+
+    /// Record that the `dropped` oldest non-system messages were compacted away,
+    /// with the rolling summary that replaced them (if any).
+    pub fn append_compaction(
+        &self,
+        id: &str,
+        dropped: usize,
+        summary: Option<&str>,
+    ) -> Result<(), AgentError> {
+        self.append(
+            id,
+            &Record::Compact {
+                timestamp_ms: now_ms(),
+                dropped,
+                summary: summary.map(str::to_string),
+            },
+        )
+    }
+
+    /// Record that tool calls and results were pruned from all but the last `keep_turns` turns
+    pub fn append_tool_pruning(&self, id: &str, keep_turns: usize) -> Result<(), AgentError> {
+        self.append(
+            id,
+            &Record::ToolsPruned {
+                timestamp_ms: now_ms(),
+                keep_turns,
+            },
+        )
+    }
+
+    /// Record that the last turn was rolled back
+    pub fn append_undo(&self, id: &str) -> Result<(), AgentError> {
+        self.append(
+            id,
+            &Record::Undo {
+                timestamp_ms: now_ms(),
+            },
+        )
+    }
+
+    /// Give a session a new title
+    pub fn rename(&self, id: &str, title: &str) -> Result<(), AgentError> {
+        self.append(
+            id,
+            &Record::Rename {
+                timestamp_ms: now_ms(),
+                title: title.trim().to_string(),
+            },
+        )
+    }
+
+    /// Remove a session file
+    pub fn delete(&self, id: &str) -> Result<(), AgentError> {
+        let path = self.existing_path(id)?;
+        fs::remove_file(&path).map_err(|e| {
+            AgentError::InternalError(format!("Failed to delete {}: {}", path.display(), e))
+        })?;
+        tracing::info!("Deleted session {}", id);
+        Ok(())
+    }
+
+    /// All sessions, most recently updated first
+    pub fn list(&self) -> Result<Vec<SessionInfo>, AgentError> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(AgentError::InternalError(format!(
+                    "Failed to read sessions dir {}: {}",
+                    self.dir.display(),
+                    e
+                )))
+            }
+        };
+
+        let mut sessions = Vec::new();
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            match self.read(id, None) {
+                Ok((info, _)) => sessions.push(info),
+                Err(e) => tracing::warn!("Skipping session {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at_ms));
+        Ok(sessions)
+    }
+
+    /// Read a session and replay its records
+    pub fn load(&self, id: &str) -> Result<LoadedSession, AgentError> {
+        // Replayed step by step so capacity trimming happens as it did live
+        let mut memory = ConversationMemory::new();
+        let (info, entries) = self.read(id, Some(&mut memory))?;
+
+        // A turn cut short by a crash may leave a call without its result
+        memory.repair_tool_pairs();
+
+        Ok(LoadedSession {
+            info,
+            entries,
+            memory,
+        })
+    }
+
+    /// Read a session's records into its info and transcript, replaying them into
+    /// `memory` if given. Images are only read from disk for the memory.
+    fn read(
+        &self,
+        id: &str,
+        mut memory: Option<&mut ConversationMemory>,
+    ) -> Result<(SessionInfo, Vec<SessionEntry>), AgentError> {
+        let path = self.existing_path(id)?;
+        let file = fs::File::open(&path).map_err(|e| {
+            AgentError::InternalError(format!("Failed to open {}: {}", path.display(), e))
+        })?;
+
+        let mut info = SessionInfo {
+            id: id.to_string(),
+            title: String::new(),
+            created_at_ms: 0,
+            updated_at_ms: 0,
+            message_count: 0,
+            total_tokens: 0,
+        };
+        let mut renamed: Option<String> = None;
+        let mut entries = Vec::new();
+        let images_dir = self.images_dir();
+
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| {
+                AgentError::InternalError(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A torn last line (crash mid-write) must not make the whole session unreadable
+            let record: Record = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    tracing::warn!("{}:{}: skipping bad record: {}", path.display(), n + 1, e);
+                    continue;
+                }
+            };
+            match record {
+                Record::Header {
+                    version,
+                    created_at_ms,
+                    ..
+                } => {
+                    if version > SESSION_FORMAT_VERSION {
+                        return Err(AgentError::ParseError(format!(
+                            "Session {} has format version {}, newer than supported {}",
+                            id, version, SESSION_FORMAT_VERSION
+                        )));
+                    }
+                    info.created_at_ms = created_at_ms;
+                    info.updated_at_ms = created_at_ms;
+                }
+                Record::Message {
+                    metadata,
+                    message,
+                    reasoning,
+                } => {
+                    info.updated_at_ms = metadata.timestamp_ms;
+                    info.message_count += 1;
+                    info.total_tokens += metadata.usage.as_ref().map_or(0, |u| u.total_tokens);
+                    if let Some(ref mut memory) = memory {
+                        memory.add_message_with_metadata(
+                            message.clone().into_message_lossy(Some(&images_dir)),
+                            metadata.clone(),
+                        );
+                    }
+                    entries.push(SessionEntry {
+                        metadata,
+                        message,
+                        reasoning,
+                    });
+                }
+                Record::Rename {
+                    timestamp_ms,
+                    title,
+                } => {
+                    info.updated_at_ms = timestamp_ms;
+                    renamed = Some(title);
+                }
+                Record::Compact {
+                    timestamp_ms,
+                    dropped,
+                    summary,
+                } => {
+                    info.updated_at_ms = timestamp_ms;
+                    if let Some(ref mut memory) = memory {
+                        memory.drop_oldest(dropped);
+                        if let Some(summary) = summary {
+                            memory.set_summary(summary);
+                        }
+                    }
+                }
+                Record::ToolsPruned {
+                    timestamp_ms,
+                    keep_turns,
+                } => {
+                    info.updated_at_ms = timestamp_ms;
+                    if let Some(ref mut memory) = memory {
+                        memory.prune_tool_turns(keep_turns);
+                    }
+                }
+                Record::Interrupted { timestamp_ms } => {
+                    info.updated_at_ms = timestamp_ms;
+                    if let Some(ref mut memory) = memory {
+                        memory.mark_last_reply_interrupted();
+                    }
+                    let reply = entries.iter_mut().rev().find(|e| {
+                        e.message.role == ChatRole::Assistant && e.message.tool_calls.is_none()
+                    });
+                    if let Some(reply) = reply {
+                        reply.metadata.interrupted = true;
+                    }
+                }
+                Record::Undo { timestamp_ms } => {
+                    info.updated_at_ms = timestamp_ms;
+                    if let Some(ref mut memory) = memory {
+                        memory.pop_turn();
+                    }
+                    // The transcript drops the turn too; the tokens it used stay counted
+                    if let Some(user) = entries
+                        .iter()
+                        .rposition(|e| e.message.role == ChatRole::User)
+                    {
+                        info.message_count -= (entries.len() - user) as u32;
+                        entries.truncate(user);
+                    }
+                }
+            }
+        }
+
+        info.title = renamed.filter(|t| !t.is_empty()).unwrap_or_else(|| {
+            entries
+                .iter()
+                .find(|e| e.message.role == ChatRole::User)
+                .map(|e| derive_title(&e.message.content))
+                .unwrap_or_else(|| "Untitled".to_string())
+        });
+        Ok((info, entries))
+    }
+
+    /// A stored session's full transcript for export, images loaded from disk
+    pub fn transcript(&self, id: &str) -> Result<Transcript, AgentError> {
+        let (info, entries) = self.read(id, None)?;
+        let images_dir = self.images_dir();
+        let entries = entries
+            .into_iter()
+            .map(|e| ExportEntry {
+                message: e.message.into_message_lossy(Some(&images_dir)),
+                metadata: Some(e.metadata),
+                reasoning: e.reasoning,
+            })
+            .collect();
+        Ok(Transcript {
+            title: Some(info.title),
+            entries,
+            ..Default::default()
+        })
+    }
+
+    fn images_dir(&self) -> PathBuf {
+        self.dir.join(IMAGES_DIR)
+    }
+
+    fn append(&self, id: &str, record: &Record) -> Result<(), AgentError> {
+        let path = self.existing_path(id)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| {
+                AgentError::InternalError(format!("Failed to open {}: {}", path.display(), e))
+            })?;
+        // Terminate a torn last line so the new record stays readable
+        if ends_without_newline(&mut file) {
+            file.write_all(b"\n").map_err(|e| {
+                AgentError::InternalError(format!("Failed to write session: {}", e))
+            })?;
+        }
+        write_record(&mut file, record)
+    }
+
+    /// Path of a session file. IDs are restricted to `[A-Za-z0-9_-]` so they cannot
+    /// escape the sessions directory.
+    fn path(&self, id: &str) -> Result<PathBuf, AgentError> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(AgentError::ConfigError(format!(
+                "Invalid session ID: {:?}",
+                id
+            )));
+        }
+        Ok(self.dir.join(format!("{}.jsonl", id)))
+    }
+
+    fn existing_path(&self, id: &str) -> Result<PathBuf, AgentError> {
+        let path = self.path(id)?;
+        if !path.is_file() {
+            return Err(AgentError::ConfigError(format!("No such session: {}", id)));
+        }
+        Ok(path)
+    }
+}
+
+/// Serialize `record` as one line and append it with a single write
+fn write_record(file: &mut fs::File, record: &Record) -> Result<(), AgentError> {
+    let mut line = serde_json::to_string(record)
+        .map_err(|e| AgentError::InternalError(format!("Failed to encode record: {}", e)))?;
+    line.push('\n');
+    file.write_all(line.as_bytes())
+        .map_err(|e| AgentError::InternalError(format!("Failed to write session: {}", e)))
+}
+
+fn ends_without_newline(file: &mut fs::File) -> bool {
+    let mut last = [0u8; 1];
+    file.seek(SeekFrom::End(-1)).is_ok() && file.read_exact(&mut last).is_ok() && last[0] != b'\n'
+}
+
+/// First line of `text`, cut to `DERIVED_TITLE_CHARS` characters
+fn derive_title(text: &str) -> String {
+    let line = text.lines().next().unwrap_or("").trim();
+    if line.chars().count() <= DERIVED_TITLE_CHARS {
+        line.to_string()
+    } else {
+        let cut: String = line.chars().take(DERIVED_TITLE_CHARS).collect();
+        format!("{}…", cut.trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::TokenUsage;
+
+    fn reply_metadata(total: u64) -> MessageMetadata {
+        MessageMetadata {
+            model: Some("gpt-test".to_string()),
+            usage: Some(TokenUsage {
+                input_tokens: total - 1,
+                output_tokens: 1,
+                total_tokens: total,
+            }),
+            llm_latency_ms: Some(900),
+            ..MessageMetadata::now()
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        let id = store.create().unwrap();
+        store
+            .append_message(
+                &id,
+                &ChatMessage::user("What's the weather in Tokyo?".into()),
+                &MessageMetadata::now(),
+            )
+            .unwrap();
+        store
+            .append_reply(
+                &id,
+                &ChatMessage::assistant("Sunny.".into()),
+                &reply_metadata(42),
+                Some("Tokyo is sunny today."),
+            )
+            .unwrap();
+        store.append_interrupted(&id).unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.entries.len(), 2);
+        assert_eq!(session.memory.len(), 2);
+        assert_eq!(session.info.title, "What's the weather in Tokyo?");
+        assert_eq!(session.info.total_tokens, 42);
+        let reply = &session.entries[1];
+        assert_eq!(reply.metadata.usage.as_ref().unwrap().total_tokens, 42);
+        assert_eq!(reply.reasoning.as_deref(), Some("Tokyo is sunny today."));
+        assert!(reply.metadata.interrupted);
+        let stats = session.memory.stats();
+        assert_eq!((stats.turns, stats.interrupted_turns), (1, 1));
+        assert_eq!(stats.avg_llm_latency_ms, 900);
+
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, id);
+        assert_eq!(listed[0].message_count, 2);
+    }
+
+    #[test]
+    fn test_tool_trace_and_images_survive_reload() {
+        use crate::llm::{ImageContent, ToolCallInfo};
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        let id = store.create().unwrap();
+        let call = ChatMessage::assistant_tool_calls(vec![ToolCallInfo {
+            id: "call_1".to_string(),
+            name: "capture_screen".to_string(),
+            arguments: serde_json::json!({}),
+        }]);
+        let result = ChatMessage::tool_result_with_images(
+            "call_1".to_string(),
+            "capture_screen".to_string(),
+            "Captured".to_string(),
+            vec![ImageContent {
+                base64: "iVBORw0KGgo=".to_string(),
+                media_type: "image/png".to_string(),
+            }],
+        );
+        store
+            .append_message(&id, &call, &MessageMetadata::now())
+            .unwrap();
+        store
+            .append_message(&id, &result, &MessageMetadata::now())
+            .unwrap();
+
+        let messages = store.load(&id).unwrap().memory.get_messages();
+        assert_eq!(
+            messages[0].tool_calls.as_ref().unwrap()[0].name,
+            "capture_screen"
+        );
+        assert_eq!(messages[1].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(messages[1].images[0].base64, "iVBORw0KGgo=");
+        assert!(dir.path().join(IMAGES_DIR).is_dir());
+
+        // A lost image file degrades to a note instead of hiding the session
+        fs::remove_dir_all(dir.path().join(IMAGES_DIR)).unwrap();
+        assert_eq!(store.list().unwrap()[0].id, id);
+        let messages = store.load(&id).unwrap().memory.get_messages();
+        assert!(messages[1].images.is_empty());
+        assert_eq!(messages[1].content, "Captured\n[image unavailable]");
+    }
+
+    #[test]
+    fn test_replay_applies_compaction_and_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        let id = store.create().unwrap();
+        for text in ["q1", "a1", "q2"] {
+            store
+                .append_message(
+                    &id,
+                    &ChatMessage::user(text.into()),
+                    &MessageMetadata::now(),
+                )
+                .unwrap();
+        }
+        store
+            .append_compaction(&id, 2, Some("User asked q1."))
+            .unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.entries.len(), 3);
+        assert_eq!(session.memory.summary(), Some("User asked q1."));
+        let messages = session.memory.get_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "q2");
+    }
+
+    #[test]
+    fn test_replay_applies_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        let id = store.create().unwrap();
+        let turns = [
+            ChatMessage::user("q1".into()),
+            ChatMessage::assistant("a1".into()),
+            ChatMessage::user("q2 misheard".into()),
+            ChatMessage::assistant("a2".into()),
+        ];
+        for message in &turns {
+            store
+                .append_message(&id, message, &MessageMetadata::now())
+                .unwrap();
+        }
+        store.append_undo(&id).unwrap();
+        store
+            .append_message(
+                &id,
+                &ChatMessage::user("q2".into()),
+                &MessageMetadata::now(),
+            )
+            .unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.info.message_count, 3);
+        assert_eq!(session.entries.len(), 3);
+        let messages = session.memory.get_messages();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2].content, "q2");
+    }
+
+    #[test]
+    fn test_rename_delete_and_torn_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path());
+        let id = store.create().unwrap();
+        store.rename(&id, "Trip planning").unwrap();
+
+        // Simulate a crash in the middle of writing a record
+        let path = dir.path().join(format!("{}.jsonl", id));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"type\":\"message\",\"timest").unwrap();
+
+        store
+            .append_message(
+                &id,
+                &ChatMessage::user("hello".into()),
+                &MessageMetadata::now(),
+            )
+            .unwrap();
+
+        let session = store.load(&id).unwrap();
+        assert_eq!(session.info.title, "Trip planning");
+        assert_eq!(session.entries.len(), 1);
+        store.delete(&id).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.load(&id).is_err());
+        assert!(store.load("../etc/passwd").is_err());
+    }
+}
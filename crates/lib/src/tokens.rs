@@ -0,0 +1,202 @@
+//! Token count estimation for providers without a local tokenizer.
+//!
+//! Cloud APIs only report token usage after a request has been sent, so compaction needs
+//! an estimate up front. Rates are calibrated per tokenizer family and count CJK text
+//! separately, since it costs several times more tokens per character than English.
+
+use crate::llm::{ChatMessage, ToolDefinition};
+
+/// Characters-per-token and per-item overheads for one tokenizer family.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenEstimator {
+    /// ASCII characters per token (English prose, code, JSON)
+    ascii_chars_per_token: f64,
+    /// Tokens per CJK character (kana, kanji, hangul)
+    cjk_tokens_per_char: f64,
+    /// Tokens per other non-ASCII character (accented Latin, Cyrillic, symbols, emoji)
+    other_tokens_per_char: f64,
+    /// Role markers and separators added around each message
+    per_message: usize,
+    /// Fixed cost of an attached image
+    per_image: usize,
+    /// Framing added around each tool definition
+    per_tool: usize,
+}
+
+impl TokenEstimator {
+    /// o200k_base: GPT-4o, GPT-4.1, GPT-5 and the o-series
+    pub const OPENAI_O200K: Self = Self {
+        ascii_chars_per_token: 4.2,
+        cjk_tokens_per_char: 0.8,
+        other_tokens_per_char: 0.6,
+        per_message: 4,
+        per_image: 765,
+        per_tool: 10,
+    };
+
+    /// cl100k_base: GPT-4, GPT-4 Turbo, GPT-3.5
+    pub const OPENAI_CL100K: Self = Self {
+        ascii_chars_per_token: 4.0,
+        cjk_tokens_per_char: 1.1,
+        other_tokens_per_char: 0.8,
+        per_message: 4,
+        per_image: 765,
+        per_tool: 10,
+    };
+
+    /// Claude models
+    pub const ANTHROPIC: Self = Self {
+        ascii_chars_per_token: 3.5,
+        cjk_tokens_per_char: 1.2,
+        other_tokens_per_char: 0.8,
+        per_message: 5,
+        per_image: 1600,
+        per_tool: 20,
+    };
+
+    /// Open-weight models (Qwen, Llama, Gemma, Mistral, ...) served over HTTP
+    pub const OPEN_WEIGHTS: Self = Self {
+        ascii_chars_per_token: 3.7,
+        cjk_tokens_per_char: 1.0,
+        other_tokens_per_char: 0.7,
+        per_message: 6,
+        per_image: 1024,
+        per_tool: 15,
+    };
+
+    /// Pick the estimator for a model name, falling back to the open-weight rates.
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_ascii_lowercase();
+        let name = model.rsplit('/').next().unwrap_or(&model);
+        if name.starts_with("claude") {
+            Self::ANTHROPIC
+        } else if name.starts_with("gpt-4o")
+            || name.starts_with("gpt-4.1")
+            || name.starts_with("gpt-5")
+            || name.starts_with("chatgpt")
+            || name.starts_with("o1")
+            || name.starts_with("o3")
+            || name.starts_with("o4")
+        {
+            Self::OPENAI_O200K
+        } else if name.starts_with("gpt-") {
+            Self::OPENAI_CL100K
+        } else {
+            Self::OPEN_WEIGHTS
+        }
+    }
+
+    /// Estimated tokens for a piece of text
+    pub fn count_text(&self, text: &str) -> usize {
+        let (mut ascii, mut cjk, mut other) = (0usize, 0usize, 0usize);
+        for ch in text.chars() {
+            if ch.is_ascii() {
+                ascii += 1;
+            } else if is_cjk(ch) {
+                cjk += 1;
+            } else {
+                other += 1;
+            }
+        }
+        let tokens = ascii as f64 / self.ascii_chars_per_token
+            + cjk as f64 * self.cjk_tokens_per_char
+            + other as f64 * self.other_tokens_per_char;
+        tokens.ceil() as usize
+    }
+
+    /// Estimated tokens for one message, including tool-call arguments and images
+    pub fn count_message(&self, message: &ChatMessage) -> usize {
+        let calls: usize = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|c| self.count_text(&c.name) + self.count_text(&c.arguments.to_string()))
+            .sum();
+        self.per_message
+            + self.count_text(&message.content)
+            + calls
+            + message.images.len() * self.per_image
+    }
+
+    /// Estimated tokens for a full request: messages plus tool definitions
+    pub fn count_request(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> usize {
+        let messages: usize = messages.iter().map(|m| self.count_message(m)).sum();
+        let tools: usize = tools
+            .iter()
+            .map(|t| {
+                self.per_tool
+                    + self.count_text(&t.name)
+                    + self.count_text(&t.description)
+                    + self.count_text(&t.parameters.to_string())
+            })
+            .sum();
+        messages + tools
+    }
+}
+
+impl Default for TokenEstimator {
+    fn default() -> Self {
+        Self::OPEN_WEIGHTS
+    }
+}
+
+/// Hiragana, katakana, CJK ideographs, hangul and full-width forms
+pub(crate) fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{3000}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}'
+        | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_model_families() {
+        assert_eq!(TokenEstimator::for_model("gpt-5-mini"), TokenEstimator::OPENAI_O200K);
+        assert_eq!(TokenEstimator::for_model("gpt-4-turbo"), TokenEstimator::OPENAI_CL100K);
+        assert_eq!(TokenEstimator::for_model("claude-sonnet-4-5"), TokenEstimator::ANTHROPIC);
+        assert_eq!(
+            TokenEstimator::for_model("anthropic/claude-3-haiku"),
+            TokenEstimator::ANTHROPIC
+        );
+        assert_eq!(TokenEstimator::for_model("qwen3-8b"), TokenEstimator::OPEN_WEIGHTS);
+    }
+
+    #[test]
+    fn test_japanese_costs_more_than_chars_over_four() {
+        let est = TokenEstimator::OPENAI_O200K;
+        let japanese = "今日はいい天気ですね。散歩に行きましょう。";
+        let chars = japanese.chars().count();
+        // chars/4 would give ~5 tokens; real tokenizers use close to one per character
+        assert!(est.count_text(japanese) >= chars * 3 / 4);
+        assert_eq!(est.count_text("abcdefgh"), 2);
+    }
+
+    #[test]
+    fn test_count_request_includes_tools_and_images() {
+        let est = TokenEstimator::ANTHROPIC;
+        let mut msg = ChatMessage::user("hello".to_string());
+        let plain = est.count_request(std::slice::from_ref(&msg), &[]);
+
+        msg.images = vec![crate::llm::ImageContent {
+            base64: "AAAA".to_string(),
+            media_type: "image/png".to_string(),
+        }];
+        let with_image = est.count_request(std::slice::from_ref(&msg), &[]);
+        assert_eq!(with_image - plain, 1600);
+
+        let tool = ToolDefinition {
+            name: "read_file".to_string(),
+            description: "Read a file".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+        assert!(est.count_request(std::slice::from_ref(&msg), &[tool]) > with_image);
+    }
+}
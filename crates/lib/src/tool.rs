@@ -9,6 +9,9 @@ use crate::AgentError;
 /// Maximum characters in a tool result before truncation (~2k tokens).
 const MAX_OUTPUT_CHARS: usize = 8000;
 
+/// Tool calls a registry runs at once unless configured otherwise
+pub const DEFAULT_MAX_PARALLEL_CALLS: usize = 4;
+
 /// Result of a tool call, containing text and optional images
 #[derive(Debug)]
 pub struct ToolResult {
@@ -61,6 +64,13 @@ pub trait ToolHandler: Send + Sync {
     fn dynamic_state(&self) -> Option<String> {
         None
     }
+
+    /// Whether calls may run at the same time as other tool calls from the same model
+    /// response. Tools that share state which must not be interleaved return false, and
+    /// their calls run alone, in order.
+    fn parallel_safe(&self) -> bool {
+        true
+    }
 }
 
 /// Build the full description for a tool: static description + optional dynamic state.
@@ -72,20 +82,39 @@ pub fn full_description(tool: &dyn ToolHandler) -> String {
 }
 
 /// Trait for accessing tools (implemented by both ToolRegistry and FilteredToolRegistry)
-pub trait ToolAccess {
+pub trait ToolAccess: Sync {
     fn get_definitions(&self) -> Vec<ToolDefinition>;
     fn call(&self, name: &str, args: serde_json::Value) -> Result<ToolResult, AgentError>;
     fn is_empty(&self) -> bool;
+
+    /// Whether a call to `name` may run concurrently with other calls
+    fn is_parallel_safe(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Most tool calls run at once
+    fn max_parallel_calls(&self) -> usize {
+        1
+    }
 }
 
 /// Registry of available tools
 pub struct ToolRegistry {
     tools: Vec<Box<dyn ToolHandler>>,
+    max_parallel: usize,
 }
 
 impl ToolRegistry {
     pub fn new() -> Self {
-        Self { tools: Vec::new() }
+        Self {
+            tools: Vec::new(),
+            max_parallel: DEFAULT_MAX_PARALLEL_CALLS,
+        }
+    }
+
+    /// Limit how many tool calls run at once (1 runs them one after another)
+    pub fn set_max_parallel(&mut self, limit: usize) {
+        self.max_parallel = limit.max(1);
     }
 
     pub fn register(&mut self, tool: Box<dyn ToolHandler>) {
@@ -98,6 +127,7 @@ impl ToolRegistry {
         FilteredToolRegistry {
             tools: &self.tools,
             allowed: allowed.to_vec(),
+            max_parallel: self.max_parallel,
         }
     }
 }
@@ -131,12 +161,23 @@ impl ToolAccess for ToolRegistry {
     fn is_empty(&self) -> bool {
         self.tools.is_empty()
     }
+
+    fn is_parallel_safe(&self, name: &str) -> bool {
+        self.tools
+            .iter()
+            .any(|t| t.name() == name && t.parallel_safe())
+    }
+
+    fn max_parallel_calls(&self) -> usize {
+        self.max_parallel
+    }
 }
 
 /// A filtered view of a ToolRegistry that only exposes certain tools
 pub struct FilteredToolRegistry<'a> {
     tools: &'a [Box<dyn ToolHandler>],
     allowed: Vec<String>,
+    max_parallel: usize,
 }
 
 impl<'a> ToolAccess for FilteredToolRegistry<'a> {
@@ -172,6 +213,16 @@ impl<'a> ToolAccess for FilteredToolRegistry<'a> {
     fn is_empty(&self) -> bool {
         !self.tools.iter().any(|t| self.allowed.iter().any(|a| a == t.name()))
     }
+
+    fn is_parallel_safe(&self, name: &str) -> bool {
+        self.tools
+            .iter()
+            .any(|t| t.name() == name && t.parallel_safe())
+    }
+
+    fn max_parallel_calls(&self) -> usize {
+        self.max_parallel
+    }
 }
 
 /// Create default tool registry with built-in tools
@@ -424,6 +475,11 @@ impl ToolHandler for TaskTool {
         "Manage an in-memory task list. Actions: create (new task), update (change status), list (show all tasks)."
     }
 
+    fn parallel_safe(&self) -> bool {
+        // "create" then "update" or "list" in one response must see the new task
+        false
+    }
+
     fn parameters_schema(&self) -> serde_json::Value {
         serde_json::json!({
             "type": "object",